@@ -8,3 +8,13 @@ try {
 } catch (e) {
 	var prefix = '/';
 }
+
+function humanSize(size) {
+	const units = ['B', 'KB', 'MB', 'GB', 'TB'];
+	let i = 0;
+	while (size >= 1000 && i < units.length - 1) {
+		size /= 1000;
+		i++;
+	}
+	return `${i == 0 ? size : size.toFixed(1)} ${units[i]}`;
+}
@@ -38,6 +38,46 @@ async function submit() {
 	$('btn').disabled = false;
 }
 
+async function failed(response) {
+	let errInfo = await response.json();
+	console.log(errInfo);
+	if (errInfo.error == 'AuthError' || errInfo.error == 'WebAuthnError') {
+		setErrorMsg(errInfo.msg);
+	} else {
+		setErrorMsg('Unknown error... check logs if this persists');
+	}
+}
+
+async function passkeyLogin() {
+	let form = Object.fromEntries(new FormData($('login')));
+	let payload = { user: form.user };
+	if (document.querySelector('meta[name="tcloud-passkeys"]').content === 'totp') {
+		payload.password = form.password;
+	} else {
+		payload.totp = form.totp;
+	}
+	$('passkey').disabled = true;
+	let response = await passkeyPost('login/start', payload);
+	if (response.status !== 200) {
+		await failed(response);
+	} else {
+		let resp = await response.json();
+		try {
+			let credential = await getPasskey(resp.options);
+			response = await passkeyPost('login/finish', { id: resp.id, credential: credential });
+			if (response.status !== 200) {
+				await failed(response);
+			} else {
+				window.location.reload();
+			}
+		} catch (error) {
+			console.log(error);
+			setErrorMsg('The passkey was not used.');
+		}
+	}
+	$('passkey').disabled = false;
+}
+
 window.onload = function() {
 	$('login').onsubmit = function(e) {
 		e.preventDefault();
@@ -50,5 +90,11 @@ window.onload = function() {
 		}
 		return false;
 	};
+	if ($('passkey')) {
+		$('passkey').onclick = function(e) {
+			setMsg('Waiting for the passkey...');
+			passkeyLogin();
+		};
+	}
 }
 
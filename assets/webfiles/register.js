@@ -57,6 +57,7 @@ async function submit() {
 		} else {
 			$('totp-url').innerHTML = resp.totp_url;
 		}
+		$('recovery-codes').textContent = resp.recovery_codes.join('\n');
 	}
 	$('btn').disabled = false;
 }
@@ -62,6 +62,7 @@ async function totp() {
 			totpurl.innerHTML += resp.totp_url;
 			$('totp-res').hidden = false;
 		}
+		$('recovery-codes').textContent = resp.recovery_codes.join('\n');
         alert('TOTP successfully changed. Save it before reloading.');
 	}
 }
@@ -102,8 +103,257 @@ async function changepwd() {
     }
 }
 
+async function usage() {
+	let response = await fetch(prefix + 'api/quota/usage', {
+		method: 'GET',
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+	});
+	if (response.status !== 200) {
+		console.log(await response.text());
+		return;
+	}
+	let resp = await response.json();
+	if (resp.quota === null) {
+		$('usage').textContent = `Storage used: ${humanSize(resp.used)}`;
+	} else {
+		$('usage').textContent = `Storage used: ${humanSize(resp.used)} of ${humanSize(resp.quota)}`;
+	}
+}
+
+async function sessions() {
+	let response = await fetch(prefix + 'api/auth/sessions', {
+		method: 'GET',
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+	});
+	if (response.status !== 200) {
+		console.log(await response.text());
+		return;
+	}
+	let list = $('session-list');
+	list.innerHTML = '';
+	for (const session of await response.json()) {
+		let item = document.createElement('li');
+		let lastSeen = new Date(session.last_seen * 1000).toLocaleString();
+		item.textContent = `${session.user_agent} from ${session.ip}, last seen ${lastSeen} `;
+		if (session.current) {
+			item.textContent += '(this session) ';
+		}
+		let button = document.createElement('button');
+		button.type = 'button';
+		button.textContent = 'Log out';
+		button.onclick = function(e) {
+			if (session.current || confirm('Are you sure you want to log out this session?')) {
+				revokeSession(session);
+			}
+		};
+		item.appendChild(button);
+		list.appendChild(item);
+	}
+}
+
+async function revokeSession(session) {
+	let response = await fetch(prefix + 'api/auth/revoke', {
+		method: 'POST',
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+		headers: {
+			'Content-Type': 'application/json',
+		},
+		body: JSON.stringify({ id: session.id }),
+	});
+	if (response.status !== 200) {
+		let errInfo = await response.json();
+		console.log(errInfo);
+		alert('Error: Failed to log out session:<br>' + errInfo.msg);
+	}
+	if (session.current) {
+		window.location.reload();
+	} else {
+		sessions();
+	}
+}
+
+async function passkeys() {
+	let response = await fetch(prefix + 'api/auth/webauthn/list', {
+		method: 'GET',
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+	});
+	if (response.status !== 200) {
+		console.log(await response.text());
+		return;
+	}
+	let list = $('passkey-list');
+	list.innerHTML = '';
+	for (const passkey of await response.json()) {
+		let item = document.createElement('li');
+		item.textContent = `${passkey.name} (added ${new Date(passkey.created * 1000).toLocaleDateString()}) `;
+		let button = document.createElement('button');
+		button.type = 'button';
+		button.textContent = 'Delete';
+		button.onclick = function(e) {
+			if (confirm(`Are you sure you want to delete the passkey "${passkey.name}"?`)) {
+				deletePasskey(passkey.id);
+			}
+		};
+		item.appendChild(button);
+		list.appendChild(item);
+	}
+}
+
+async function deletePasskey(id) {
+	let response = await passkeyPost('delete', { id: id });
+	if (response.status !== 200) {
+		let errInfo = await response.json();
+		console.log(errInfo);
+		alert('Error: Failed to delete passkey:<br>' + errInfo.msg);
+	}
+	passkeys();
+}
+
+async function addPasskey() {
+	let form = Object.fromEntries(new FormData($('addpasskey')));
+	let response = await passkeyPost('register/start', form);
+	if (response.status !== 200) {
+		let errInfo = await response.json();
+		console.log(errInfo);
+		alert('Error: Failed to add passkey:<br>' + errInfo.msg);
+		return;
+	}
+	let resp = await response.json();
+	let credential = await createPasskey(resp.options);
+	response = await passkeyPost('register/finish', { id: resp.id, credential: credential });
+	if (response.status !== 200) {
+		let errInfo = await response.json();
+		console.log(errInfo);
+		alert('Error: Failed to add passkey:<br>' + errInfo.msg);
+	} else {
+		$('addpasskey').reset();
+		alert('Passkey added.');
+	}
+	passkeys();
+}
+
+async function apiTokenPost(action, body) {
+	return await fetch(prefix + `api/auth/apitokens/${action}`, {
+		method: 'POST',
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+		headers: {
+			'Content-Type': 'application/json',
+		},
+		body: JSON.stringify(body),
+	});
+}
+
+async function apiTokens() {
+	let response = await fetch(prefix + 'api/auth/apitokens/list', {
+		method: 'GET',
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+	});
+	if (response.status !== 200) {
+		console.log(await response.text());
+		return;
+	}
+	let list = $('apitoken-list');
+	list.innerHTML = '';
+	for (const token of await response.json()) {
+		let item = document.createElement('li');
+		let expires = new Date(token.expires * 1000).toLocaleDateString();
+		let lastUsed = token.last_used === null ? 'never used' : `last used ${new Date(token.last_used * 1000).toLocaleString()}`;
+		item.textContent = `${token.name} (${token.scopes.join(', ')}), expires ${expires}, ${lastUsed} `;
+		let button = document.createElement('button');
+		button.type = 'button';
+		button.textContent = 'Revoke';
+		button.onclick = function(e) {
+			if (confirm(`Are you sure you want to revoke the token "${token.name}"? Scripts using it will stop working.`)) {
+				revokeApiToken(token.id);
+			}
+		};
+		item.appendChild(button);
+		list.appendChild(item);
+	}
+}
+
+async function revokeApiToken(id) {
+	let response = await apiTokenPost('revoke', { id: id });
+	if (response.status !== 200) {
+		let errInfo = await response.json();
+		console.log(errInfo);
+		alert('Error: Failed to revoke token:<br>' + errInfo.msg);
+	}
+	apiTokens();
+}
+
+async function addApiToken() {
+	let data = new FormData($('addapitoken'));
+	let form = {
+		name: data.get('name'),
+		days: parseInt(data.get('days')),
+		scopes: data.getAll('scope'),
+		password: data.get('password'),
+	};
+	let response = await apiTokenPost('new', form);
+	if (response.status !== 200) {
+		let errInfo = await response.json();
+		console.log(errInfo);
+		alert('Error: Failed to create token:<br>' + errInfo.msg);
+		return;
+	}
+	let resp = await response.json();
+	$('addapitoken').reset();
+	$('new-apitoken').textContent = resp.token;
+	$('apitoken-res').hidden = false;
+	apiTokens();
+}
+
 window.onload = function() {
 	navbar_onload();
+	usage();
+	sessions();
+	if ($('addpasskey')) {
+		passkeys();
+		$('addpasskey').onsubmit = function(e) {
+			e.preventDefault();
+			addPasskey().catch(function(error) {
+				console.log(error);
+				alert('The passkey was not added, check logs for more info if this persists');
+			});
+			return false;
+		};
+	}
+	if ($('addapitoken')) {
+		apiTokens();
+		$('addapitoken').onsubmit = function(e) {
+			e.preventDefault();
+			addApiToken().catch(function(error) {
+				console.log(error);
+				alert('An error occurred, check logs for more info and open an issue if this persists');
+			});
+			return false;
+		};
+	}
 	$('logout').onclick = function(e) {
 		if (confirm('Are you sure you want to logout?')) {
 			get('logout');
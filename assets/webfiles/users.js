@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+async function post(type, payload) {
+	let response = await fetch(prefix + `api/admin/users/${type}`, {
+		method: 'POST',
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+		headers: {
+			'Content-Type': 'application/json',
+		},
+		body: JSON.stringify(payload),
+	});
+	if (response.status !== 200) {
+		let errInfo = await response.json();
+		console.log(errInfo);
+		alert(`Error: Failed to ${type} :(\n` + errInfo.msg);
+		return null;
+	}
+	return response;
+}
+
+async function resettotp(user) {
+	let as_qr = confirm('Do you want to receive the TOTP secret as a QR code? (Cancel to receive it as a URL)');
+	let response = await post('resettotp', { user: user, totp_as_qr: as_qr });
+	if (response === null) {
+		return;
+	}
+	let resp = await response.json();
+	$('totp-user').textContent = `New TOTP secret of ${user}:`;
+	if (as_qr) {
+		$('totp-qr').src = 'data:image/png;base64, ' + resp.totp_qr;
+		$('totp-url').textContent = '';
+	} else {
+		$('totp-qr').src = '';
+		$('totp-url').textContent = resp.totp_url;
+	}
+	$('recovery-codes').textContent = resp.recovery_codes.join('\n');
+	$('userlist').hidden = true;
+	$('totp-res').hidden = false;
+}
+
+async function setquota(user) {
+	let quota = prompt(`New storage quota of ${user} in GB (leave empty to use the default one):`);
+	if (quota === null) {
+		return false;
+	}
+	let payload = { user: user, quota: null };
+	if (quota.trim() !== '') {
+		let gb = Number(quota);
+		if (isNaN(gb) || gb < 0) {
+			alert('Error: Invalid quota.');
+			return false;
+		}
+		payload.quota = Math.round(gb * 1000000000);
+	}
+	return await post('setquota', payload) !== null;
+}
+
+async function sessions(user) {
+	let response = await post('sessions', { user: user });
+	if (response === null) {
+		return;
+	}
+	let list = $('session-list');
+	list.innerHTML = '';
+	for (const session of await response.json()) {
+		let item = document.createElement('li');
+		let lastSeen = new Date(session.last_seen * 1000).toLocaleString();
+		item.textContent = `${session.user_agent} from ${session.ip}, last seen ${lastSeen} `;
+		item.appendChild(button('Log out', async function(e) {
+			if (confirm(`Are you sure you want to log out this session of ${user}?`)) {
+				if (await post('killsession', { user: user, id: session.id }) !== null) {
+					sessions(user);
+				}
+			}
+		}));
+		list.appendChild(item);
+	}
+	$('sessions-user').textContent = list.childElementCount === 0 ? `${user} has no active sessions.` : `Sessions of ${user}:`;
+	$('userlist').hidden = true;
+	$('sessions-res').hidden = false;
+}
+
+function button(text, onclick) {
+	let btn = document.createElement('button');
+	btn.type = 'button';
+	btn.className = 'action';
+	btn.textContent = text;
+	btn.onclick = onclick;
+	return btn;
+}
+
+async function load() {
+	let response = await fetch(prefix + 'api/admin/users/list', {
+		method: 'GET',
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+	});
+	if (response.status !== 200) {
+		let errInfo = await response.json();
+		console.log(errInfo);
+		alert('Error: Failed to get users :(\n' + errInfo.msg);
+		return;
+	}
+	let users = await response.json();
+	let rows = $('userrows');
+	rows.innerHTML = '';
+	for (const u of users) {
+		let row = document.createElement('tr');
+		let name = document.createElement('td');
+		name.textContent = u.user;
+		let admin = document.createElement('td');
+		admin.textContent = u.is_admin ? 'Yes' : 'No';
+		let storage = document.createElement('td');
+		storage.textContent = humanSize(u.used) + ' / ' + (u.quota === null ? 'default' : humanSize(u.quota));
+		let actions = document.createElement('td');
+		actions.appendChild(button(u.is_admin ? 'Demote' : 'Promote', async function(e) {
+			if (confirm(`Are you sure you want to ${u.is_admin ? 'demote' : 'promote'} ${u.user}?`)) {
+				if (await post('setadmin', { user: u.user, is_admin: !u.is_admin }) !== null) {
+					load();
+				}
+			}
+		}));
+		actions.appendChild(button('Set quota', async function(e) {
+			if (await setquota(u.user)) {
+				load();
+			}
+		}));
+		actions.appendChild(button('Sessions', function(e) {
+			sessions(u.user);
+		}));
+		actions.appendChild(button('Log out', async function(e) {
+			if (confirm(`Are you sure you want to log out every session of ${u.user}?`)) {
+				if (await post('logout', { user: u.user }) !== null) {
+					alert(`${u.user} has been logged out.`);
+				}
+			}
+		}));
+		actions.appendChild(button('Reset TOTP', function(e) {
+			if (confirm(`Are you sure you want to reset the TOTP secret of ${u.user}? They will be logged out.`)) {
+				resettotp(u.user);
+			}
+		}));
+		let del = button('Delete', async function(e) {
+			if (confirm(`If you delete ${u.user} all of their files will be deleted. Are you sure you want to continue?`)) {
+				if (await post('delete', { user: u.user }) !== null) {
+					load();
+				}
+			}
+		});
+		del.classList.add('delete');
+		actions.appendChild(del);
+		row.appendChild(name);
+		row.appendChild(admin);
+		row.appendChild(storage);
+		row.appendChild(actions);
+		rows.appendChild(row);
+	}
+}
+
+window.onload = function() {
+	navbar_onload();
+	$('sessions-btn').onclick = function(e) {
+		$('sessions-res').hidden = true;
+		$('userlist').hidden = false;
+	};
+	$('totp-btn').onclick = function(e) {
+		$('totp-res').hidden = true;
+		$('totp-qr').src = '';
+		$('totp-url').textContent = '';
+		$('recovery-codes').textContent = '';
+		$('userlist').hidden = false;
+	};
+	try {
+		load();
+	} catch (error) {
+		console.log(error);
+		alert('An error occurred, check logs for more info and open an issue if this persists');
+	}
+}
@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+function fromBase64url(str) {
+	let base64 = str.replace(/-/g, '+').replace(/_/g, '/');
+	let binary = atob(base64 + '='.repeat((4 - base64.length % 4) % 4));
+	return Uint8Array.from(binary, c => c.charCodeAt(0)).buffer;
+}
+
+function toBase64url(buf) {
+	let binary = String.fromCharCode(...new Uint8Array(buf));
+	return btoa(binary).replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+}
+
+async function passkeyPost(path, payload) {
+	return await fetch(prefix + `api/auth/webauthn/${path}`, {
+		method: 'POST',
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+		headers: {
+			'Content-Type': 'application/json',
+		},
+		body: JSON.stringify(payload),
+	});
+}
+
+// Creates a passkey with the options sent by the server and returns it in the format it expects
+async function createPasskey(options) {
+	let pk = options.publicKey;
+	pk.challenge = fromBase64url(pk.challenge);
+	pk.user.id = fromBase64url(pk.user.id);
+	for (const cred of pk.excludeCredentials || []) {
+		cred.id = fromBase64url(cred.id);
+	}
+	let cred = await navigator.credentials.create(options);
+	return {
+		id: cred.id,
+		rawId: toBase64url(cred.rawId),
+		type: cred.type,
+		extensions: cred.getClientExtensionResults(),
+		response: {
+			attestationObject: toBase64url(cred.response.attestationObject),
+			clientDataJSON: toBase64url(cred.response.clientDataJSON),
+		},
+	};
+}
+
+// Signs the challenge sent by the server with a passkey and returns the assertion in the format it expects
+async function getPasskey(options) {
+	let pk = options.publicKey;
+	pk.challenge = fromBase64url(pk.challenge);
+	for (const cred of pk.allowCredentials || []) {
+		cred.id = fromBase64url(cred.id);
+	}
+	let cred = await navigator.credentials.get(options);
+	return {
+		id: cred.id,
+		rawId: toBase64url(cred.rawId),
+		type: cred.type,
+		extensions: cred.getClientExtensionResults(),
+		response: {
+			authenticatorData: toBase64url(cred.response.authenticatorData),
+			clientDataJSON: toBase64url(cred.response.clientDataJSON),
+			signature: toBase64url(cred.response.signature),
+			userHandle: cred.response.userHandle ? toBase64url(cred.response.userHandle) : null,
+		},
+	};
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::error::ArchiveError;
+use crate::path::{check_name, resolve, resolve_entry, resolve_link};
+use common_library::actix_files::NamedFile;
+use common_library::actix_multipart::form::tempfile::TempFile;
+use common_library::serde_json::{Value, json};
+use serde::Deserialize;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use tokio::fs;
+
+/// Request sent by the client to the archive
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Request {
+    /// Lists the content of a directory
+    List { path: String },
+    /// Creates a new directory
+    Mkdir { path: String },
+    /// Renames a file or directory, keeping it in the same directory
+    Rename { path: String, name: String },
+    /// Moves a file or directory inside another directory
+    Move { from: String, to: String },
+    /// Deletes a file or a directory with all of its content
+    Delete { path: String },
+}
+
+/// Info sent along with an uploaded file
+#[derive(Deserialize)]
+pub struct UploadInfo {
+    /// Directory where the file will be saved
+    pub path: String,
+    /// Name of the file, if not given the name sent in the form is used
+    pub name: Option<String>,
+}
+
+pub async fn list(root: &Path, path: &str) -> Result<Value, ArchiveError> {
+    let dir = resolve(root, path).await?;
+    let mut entries = fs::read_dir(&dir).await.map_err(|e| ArchiveError::from_io(e, "read directory"))?;
+    let mut list = Vec::new();
+    while let Some(entry) = entries.next_entry().await.map_err(|e| ArchiveError::from_io(e, "read directory"))? {
+        let meta = entry.metadata().await.map_err(|e| ArchiveError::from_io(e, "read metadata"))?;
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|m| m.as_secs());
+        list.push(json!({
+            "name": entry.file_name().to_string_lossy(),
+            "is_dir": meta.is_dir(),
+            "size": meta.len(),
+            "modified": modified,
+        }));
+    }
+    list.sort_by(|a, b| {
+        b["is_dir"]
+            .as_bool()
+            .cmp(&a["is_dir"].as_bool())
+            .then_with(|| a["name"].as_str().cmp(&b["name"].as_str()))
+    });
+    Ok(Value::Array(list))
+}
+
+pub async fn mkdir(root: &Path, path: &str) -> Result<(), ArchiveError> {
+    let dir = resolve_entry(root, path).await?;
+    fs::create_dir(dir).await.map_err(|e| ArchiveError::from_io(e, "create directory"))
+}
+
+pub async fn rename(root: &Path, path: &str, name: &str) -> Result<(), ArchiveError> {
+    check_name(name)?;
+    let from = resolve_link(root, path).await?;
+    let to = from.with_file_name(name);
+    if fs::try_exists(&to).await.map_err(|e| ArchiveError::from_io(e, "rename"))? {
+        return Err(ArchiveError::AlreadyExists);
+    }
+    fs::rename(from, to).await.map_err(|e| ArchiveError::from_io(e, "rename"))
+}
+
+pub async fn move_entry(root: &Path, from: &str, to: &str) -> Result<(), ArchiveError> {
+    let from = resolve_link(root, from).await?;
+    let dir = resolve(root, to).await?;
+    if !fs::metadata(&dir).await.map_err(|e| ArchiveError::from_io(e, "move"))?.is_dir() {
+        return Err(ArchiveError::BadRequest("Destination is not a directory".into()));
+    }
+    if dir.starts_with(&from) {
+        return Err(ArchiveError::BadRequest("A directory cannot be moved inside itself".into()));
+    }
+    let to = dir.join(from.file_name().ok_or(ArchiveError::InvalidPath)?);
+    if fs::try_exists(&to).await.map_err(|e| ArchiveError::from_io(e, "move"))? {
+        return Err(ArchiveError::AlreadyExists);
+    }
+    fs::rename(from, to).await.map_err(|e| ArchiveError::from_io(e, "move"))
+}
+
+pub async fn delete(root: &Path, path: &str) -> Result<(), ArchiveError> {
+    let path = resolve_link(root, path).await?;
+    let meta = fs::symlink_metadata(&path).await.map_err(|e| ArchiveError::from_io(e, "delete"))?;
+    if meta.is_dir() {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_file(path).await
+    }
+    .map_err(|e| ArchiveError::from_io(e, "delete"))
+}
+
+pub async fn download(root: &Path, path: &str) -> Result<NamedFile, ArchiveError> {
+    let path = resolve_entry(root, path).await?;
+    if fs::metadata(&path)
+        .await
+        .map_err(|e| ArchiveError::from_io(e, "download"))?
+        .is_dir()
+    {
+        return Err(ArchiveError::BadRequest("Directories cannot be downloaded".into()));
+    }
+    NamedFile::open_async(path).await.map_err(|e| ArchiveError::from_io(e, "download"))
+}
+
+pub async fn upload(root: &Path, file: TempFile, info: UploadInfo) -> Result<(), ArchiveError> {
+    let name = info
+        .name
+        .or(file.file_name.clone())
+        .ok_or(ArchiveError::BadRequest("No file name was given".into()))?;
+    check_name(&name)?;
+    let dir = resolve(root, &info.path).await?;
+    if !fs::metadata(&dir).await.map_err(|e| ArchiveError::from_io(e, "upload"))?.is_dir() {
+        return Err(ArchiveError::BadRequest("Destination is not a directory".into()));
+    }
+    let dest = dir.join(name);
+    if fs::try_exists(&dest).await.map_err(|e| ArchiveError::from_io(e, "upload"))? {
+        return Err(ArchiveError::AlreadyExists);
+    }
+    // Renaming fails if the temporary directory is on another filesystem, in that case it gets copied
+    if let Err(e) = file.file.persist_noclobber(&dest) {
+        if e.error.kind() == std::io::ErrorKind::AlreadyExists {
+            return Err(ArchiveError::AlreadyExists);
+        }
+        // The destination is created exclusively, so a file that appeared since the check above is never replaced
+        let mut dest_file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&dest)
+            .await
+            .map_err(|e| ArchiveError::from_io(e, "upload"))?;
+        let copied = match fs::File::open(e.file.path()).await {
+            Ok(mut src) => tokio::io::copy(&mut src, &mut dest_file).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&dest).await;
+            return Err(ArchiveError::from_io(e, "upload"));
+        }
+    }
+    Ok(())
+}
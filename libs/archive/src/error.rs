@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use common_library::actix_web::{HttpResponse, HttpResponseBuilder};
+use common_library::error::ErrToResponse;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("An internal server error occurred")]
+    InternalError(String),
+    #[error("You must be logged in to use the archive")]
+    Unauthorized,
+    #[error("Invalid request: {0}")]
+    BadRequest(String),
+    #[error("Invalid path")]
+    InvalidPath,
+    #[error("File or directory was not found")]
+    NotFound,
+    #[error("File or directory already exists")]
+    AlreadyExists,
+}
+
+impl ArchiveError {
+    /// Maps an IO error into the most fitting error
+    pub fn from_io(err: std::io::Error, action: &str) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound,
+            std::io::ErrorKind::AlreadyExists => Self::AlreadyExists,
+            _ => Self::InternalError(format!("Failed to {action}: {err}")),
+        }
+    }
+}
+
+impl ErrToResponse for ArchiveError {
+    fn error(&self) -> &'static str {
+        "ArchiveError"
+    }
+
+    fn err_type(&self) -> &'static str {
+        match self {
+            Self::InternalError(_) => stringify!(InternalError),
+            Self::Unauthorized => stringify!(Unauthorized),
+            Self::BadRequest(_) => stringify!(BadRequest),
+            Self::InvalidPath => stringify!(InvalidPath),
+            Self::NotFound => stringify!(NotFound),
+            Self::AlreadyExists => stringify!(AlreadyExists),
+        }
+    }
+
+    fn msg(&self) -> String {
+        self.to_string()
+    }
+
+    fn http_code(&self) -> HttpResponseBuilder {
+        match self {
+            Self::InternalError(_) => HttpResponse::InternalServerError(),
+            Self::Unauthorized => HttpResponse::Unauthorized(),
+            Self::BadRequest(_) => HttpResponse::BadRequest(),
+            Self::InvalidPath => HttpResponse::BadRequest(),
+            Self::NotFound => HttpResponse::NotFound(),
+            Self::AlreadyExists => HttpResponse::Conflict(),
+        }
+    }
+
+    fn handle(&self) {
+        if let Self::InternalError(err) = self {
+            log::error!("An internal server error occurred in the archive: {err}");
+        }
+    }
+}
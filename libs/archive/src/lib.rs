@@ -1,22 +1,74 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+mod actions;
+mod error;
+mod path;
+
+use actions::{Request, UploadInfo};
+use common_library::actix_files::NamedFile;
+use common_library::actix_multipart::form::tempfile::TempFile;
+use common_library::actix_web::HttpResponse;
+use common_library::error::ErrToResponse;
+use common_library::plugin::{PluginInfo, User, WebUI};
+use common_library::serde_json;
+use common_library::tiny_args::{ArgName, ArgValue, Command, ParsedCommand, arg, value};
+use common_library::toml::Table;
+use common_library::{Json, Toml};
+use common_library::{async_trait, plugin::Plugin};
+use error::ArchiveError;
 use std::path::PathBuf;
+use tokio::fs;
 
-use tcloud_library::actix_multipart::form::tempfile::TempFile;
-use tcloud_library::actix_web::HttpResponse;
-use tcloud_library::plugin::{PluginInfo, User, WebUI};
-use tcloud_library::tiny_args::{arg, value, ArgName, ArgValue, Command, ParsedCommand};
-use tcloud_library::toml::Table;
-use tcloud_library::{async_trait, plugin::Plugin};
-use tcloud_library::{Json, Toml};
+const HTML: &str = include_str!("../webui/archive.html");
+const JS: &str = include_str!("../webui/archive.js");
+const CSS: &str = include_str!("../webui/archive.css");
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ArchivePlugin;
 
 impl ArchivePlugin {
     pub fn new() -> Self {
         ArchivePlugin {}
     }
+
+    async fn handle_request(&self, user: Option<User>, body: Json, path: PathBuf) -> Result<HttpResponse, ArchiveError> {
+        if user.is_none() {
+            return Err(ArchiveError::Unauthorized);
+        }
+        let request: Request = serde_json::from_value(body).map_err(|e| ArchiveError::BadRequest(e.to_string()))?;
+        fs::create_dir_all(&path)
+            .await
+            .map_err(|e| ArchiveError::InternalError(format!("Failed to create user directory: {e}")))?;
+        match request {
+            Request::List { path: dir } => {
+                let list = actions::list(&path, &dir).await?;
+                return Ok(HttpResponse::Ok().content_type("application/json").body(list.to_string()));
+            }
+            Request::Mkdir { path: dir } => actions::mkdir(&path, &dir).await?,
+            Request::Rename { path: entry, name } => actions::rename(&path, &entry, &name).await?,
+            Request::Move { from, to } => actions::move_entry(&path, &from, &to).await?,
+            Request::Delete { path: entry } => actions::delete(&path, &entry).await?,
+        }
+        Ok(HttpResponse::Ok().body(""))
+    }
+
+    async fn handle_file(&self, user: Option<User>, file: TempFile, info: Json, path: PathBuf) -> Result<(), ArchiveError> {
+        if user.is_none() {
+            return Err(ArchiveError::Unauthorized);
+        }
+        let info: UploadInfo = serde_json::from_value(info).map_err(|e| ArchiveError::BadRequest(e.to_string()))?;
+        fs::create_dir_all(&path)
+            .await
+            .map_err(|e| ArchiveError::InternalError(format!("Failed to create user directory: {e}")))?;
+        actions::upload(&path, file, info).await
+    }
+
+    async fn handle_download(&self, user: Option<User>, file: String, path: PathBuf) -> Result<NamedFile, ArchiveError> {
+        if user.is_none() {
+            return Err(ArchiveError::Unauthorized);
+        }
+        actions::download(&path, &file).await
+    }
 }
 
 #[async_trait]
@@ -51,39 +103,88 @@ impl Plugin for ArchivePlugin {
         }
     }
 
-    fn init(&mut self, config: Option<&Toml>) -> Result<(), String> {
+    fn init(&mut self, _config: Option<&Toml>) -> Result<(), String> {
         Ok(())
     }
 
     async fn webui(&self) -> WebUI {
         WebUI {
-            html: "".into(),
-            js: "",
-            css: "",
+            html: HTML.into(),
+            js: JS,
+            css: CSS,
         }
     }
 
     async fn request(&self, user: Option<User>, body: Json, path: PathBuf) -> HttpResponse {
-        todo!()
+        self.handle_request(user, body, path).await.unwrap_or_else(|e| e.to_response())
+    }
+
+    async fn file(&self, user: Option<User>, file: TempFile, info: Json, path: PathBuf) -> HttpResponse {
+        match self.handle_file(user, file, info, path).await {
+            Ok(()) => HttpResponse::Ok().body(""),
+            Err(e) => e.to_response(),
+        }
     }
 
-    async fn file(
-        &self,
-        user: Option<User>,
-        file: TempFile,
-        info: Json,
-        path: PathBuf,
-    ) -> HttpResponse {
-        todo!()
+    async fn download(&self, user: Option<User>, file: String, path: PathBuf) -> Result<NamedFile, HttpResponse> {
+        self.handle_download(user, file, path).await.map_err(|e| e.to_response())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
 
     #[tokio::test]
     async fn init_test() {
         println!("{:?}", ArchivePlugin::new());
     }
+
+    #[test]
+    fn join_rejects_traversal() {
+        let root = Path::new("/data/users/test/archive");
+        assert_eq!(path::join(root, "/docs/a.txt").unwrap(), root.join("docs/a.txt"));
+        assert_eq!(path::join(root, "./docs/").unwrap(), root.join("docs"));
+        assert!(path::join(root, "../other").is_err());
+        assert!(path::join(root, "docs/../../other").is_err());
+        assert!(path::check_name("a.txt").is_ok());
+        assert!(path::check_name("..").is_err());
+        assert!(path::check_name("a/b").is_err());
+    }
+
+    #[tokio::test]
+    async fn resolve_stays_inside_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("user");
+        fs::create_dir_all(root.join("docs")).await.unwrap();
+        fs::create_dir_all(dir.path().join("other")).await.unwrap();
+        fs::symlink(dir.path().join("other"), root.join("escape")).await.unwrap();
+
+        assert!(path::resolve(&root, "docs/new").await.is_ok());
+        assert!(path::resolve(&root, "escape").await.is_err());
+        assert!(path::resolve(&root, "escape/file").await.is_err());
+        assert!(path::resolve(&root, "missing/file").await.is_err());
+        assert!(path::resolve_entry(&root, "/").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn links_are_deleted_and_renamed_themselves() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("user");
+        fs::create_dir_all(root.join("docs")).await.unwrap();
+        fs::create_dir_all(dir.path().join("other")).await.unwrap();
+        fs::write(dir.path().join("other/file"), b"kept").await.unwrap();
+        fs::symlink(dir.path().join("other"), root.join("escape")).await.unwrap();
+        fs::symlink(root.join("docs"), root.join("shortcut")).await.unwrap();
+
+        assert!(path::resolve_link(&root, "/").await.is_err());
+        assert!(path::resolve_link(&root, "escape/file").await.is_err());
+        actions::rename(&root, "shortcut", "renamed").await.unwrap();
+        assert!(fs::symlink_metadata(root.join("renamed")).await.unwrap().is_symlink());
+        assert!(fs::metadata(root.join("docs")).await.unwrap().is_dir());
+        actions::delete(&root, "escape").await.unwrap();
+        assert!(!fs::try_exists(root.join("escape")).await.unwrap());
+        assert_eq!(fs::read(dir.path().join("other/file")).await.unwrap(), b"kept");
+    }
 }
@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::error::ArchiveError;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+
+/// Joins a path sent by the client to the user's directory.
+/// Only plain names are accepted as components, a leading `/` refers to the user's directory.
+pub fn join(root: &Path, path: &str) -> Result<PathBuf, ArchiveError> {
+    if path.contains('\0') {
+        return Err(ArchiveError::InvalidPath);
+    }
+    let mut joined = root.to_path_buf();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => joined.push(name),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return Err(ArchiveError::InvalidPath),
+        }
+    }
+    Ok(joined)
+}
+
+/// Checks that a file name is a single plain component.
+pub fn check_name(name: &str) -> Result<(), ArchiveError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        Err(ArchiveError::InvalidPath)
+    } else {
+        Ok(())
+    }
+}
+
+/// Resolves the user's directory itself
+async fn canonical_root(root: &Path) -> Result<PathBuf, ArchiveError> {
+    fs::canonicalize(root)
+        .await
+        .map_err(|e| ArchiveError::InternalError(format!("Failed to resolve user directory: {e}")))
+}
+
+/// Resolves a path sent by the client and makes sure it does not leave the user's directory,
+/// even through symbolic links.
+/// The path itself may not exist, but its parent directory must.
+pub async fn resolve(root: &Path, path: &str) -> Result<PathBuf, ArchiveError> {
+    let root = canonical_root(root).await?;
+    let joined = join(&root, path)?;
+    let resolved = match fs::canonicalize(&joined).await {
+        Ok(resolved) => resolved,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let name = joined.file_name().ok_or(ArchiveError::InvalidPath)?;
+            let parent = joined.parent().ok_or(ArchiveError::InvalidPath)?;
+            fs::canonicalize(parent).await.map_err(|_| ArchiveError::NotFound)?.join(name)
+        }
+        Err(e) => return Err(ArchiveError::from_io(e, "resolve path")),
+    };
+    if resolved.starts_with(&root) {
+        Ok(resolved)
+    } else {
+        Err(ArchiveError::InvalidPath)
+    }
+}
+
+/// Same as [`resolve`], but fails if the path is the user's directory itself.
+pub async fn resolve_entry(root: &Path, path: &str) -> Result<PathBuf, ArchiveError> {
+    let resolved = resolve(root, path).await?;
+    let root = canonical_root(root).await?;
+    if resolved == root {
+        Err(ArchiveError::InvalidPath)
+    } else {
+        Ok(resolved)
+    }
+}
+
+/// Same as [`resolve_entry`], but the last component is not followed if it is a symbolic link,
+/// so that deleting or renaming it acts on the link itself. Only its parent has to be inside the user's directory.
+pub async fn resolve_link(root: &Path, path: &str) -> Result<PathBuf, ArchiveError> {
+    let root = canonical_root(root).await?;
+    let joined = join(&root, path)?;
+    if joined == root {
+        return Err(ArchiveError::InvalidPath);
+    }
+    let name = joined.file_name().ok_or(ArchiveError::InvalidPath)?;
+    let parent = joined.parent().ok_or(ArchiveError::InvalidPath)?;
+    let parent = fs::canonicalize(parent)
+        .await
+        .map_err(|e| ArchiveError::from_io(e, "resolve path"))?;
+    if parent.starts_with(&root) {
+        Ok(parent.join(name))
+    } else {
+        Err(ArchiveError::InvalidPath)
+    }
+}
@@ -0,0 +1,227 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+var cwd = '/';
+
+function setMsg(msg, error) {
+	$('msg').style.color = error ? 'red' : 'white';
+	$('msg').textContent = msg;
+}
+
+function join(dir, name) {
+	return (dir.endsWith('/') ? dir : dir + '/') + name;
+}
+
+async function request(payload) {
+	let response = await fetch(prefix + 'api/p/archive', {
+		method: 'POST',
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+		headers: {
+			'Content-Type': 'application/json',
+		},
+		body: JSON.stringify(payload),
+	});
+	if (response.status !== 200) {
+		let errInfo = await response.json();
+		console.log(errInfo);
+		setMsg(`Failed to ${payload.action}: ${errInfo.msg}`, true);
+		return null;
+	}
+	setMsg('', false);
+	return response;
+}
+
+function download(path) {
+	let a = document.createElement('a');
+	a.href = prefix + 'api/down/archive' + path.split('/').map(encodeURIComponent).join('/');
+	a.download = path.split('/').pop();
+	a.click();
+}
+
+function button(text, onclick) {
+	let btn = document.createElement('button');
+	btn.type = 'button';
+	btn.className = 'action';
+	btn.textContent = text;
+	btn.onclick = onclick;
+	return btn;
+}
+
+async function load(dir) {
+	let response = await request({ action: 'list', path: dir });
+	if (response === null) {
+		return;
+	}
+	cwd = dir;
+	$('cwd').textContent = cwd;
+	let rows = $('rows');
+	rows.innerHTML = '';
+	for (const entry of await response.json()) {
+		let path = join(cwd, entry.name);
+		let row = document.createElement('tr');
+		let name = document.createElement('td');
+		let link = document.createElement('a');
+		link.href = '#';
+		link.textContent = entry.is_dir ? entry.name + '/' : entry.name;
+		link.onclick = function(e) {
+			e.preventDefault();
+			entry.is_dir ? load(path) : download(path);
+		};
+		name.appendChild(link);
+		let size = document.createElement('td');
+		size.textContent = entry.is_dir ? '' : humanSize(entry.size);
+		let modified = document.createElement('td');
+		modified.textContent = entry.modified ? new Date(entry.modified * 1000).toLocaleString() : '';
+		let actions = document.createElement('td');
+		actions.appendChild(button('Rename', async function(e) {
+			let newName = prompt('New name:', entry.name);
+			if (newName && await request({ action: 'rename', path: path, name: newName }) !== null) {
+				load(cwd);
+			}
+		}));
+		actions.appendChild(button('Move', async function(e) {
+			let to = prompt('Move to directory:', cwd);
+			if (to && await request({ action: 'move', from: path, to: to }) !== null) {
+				load(cwd);
+			}
+		}));
+		let del = button('Delete', async function(e) {
+			if (confirm(`Are you sure you want to delete ${entry.name}?`)) {
+				if (await request({ action: 'delete', path: path }) !== null) {
+					load(cwd);
+				}
+			}
+		});
+		del.classList.add('delete');
+		actions.appendChild(del);
+		row.appendChild(name);
+		row.appendChild(size);
+		row.appendChild(modified);
+		row.appendChild(actions);
+		rows.appendChild(row);
+	}
+}
+
+const CHUNK_SIZE = 8 * 1000 * 1000;
+const MAX_RETRIES = 5;
+
+function sleep(ms) {
+	return new Promise(resolve => setTimeout(resolve, ms));
+}
+
+async function uploadRequest(path, method, headers, body) {
+	return await fetch(prefix + 'api/upload/archive/' + path, {
+		method: method,
+		mode: 'same-origin',
+		cache: 'no-cache',
+		credentials: 'same-origin',
+		redirect: 'follow',
+		referrerPolicy: 'no-referrer',
+		headers: headers,
+		body: body,
+	});
+}
+
+async function uploadError(response) {
+	let errInfo = await response.json();
+	console.log(errInfo);
+	setMsg('Failed to upload: ' + errInfo.msg, true);
+}
+
+// Uploads the file in chunks, resuming from the last offset received by the server if the connection drops
+async function sendChunks(id, file) {
+	let offset = 0;
+	let retries = 0;
+	while (offset < file.size) {
+		try {
+			let response = await uploadRequest(id, 'PATCH', {
+				'Upload-Offset': offset.toString(),
+				'Content-Type': 'application/offset+octet-stream',
+			}, file.slice(offset, offset + CHUNK_SIZE));
+			if (response.status !== 204) {
+				await uploadError(response);
+				return false;
+			}
+			offset = parseInt(response.headers.get('Upload-Offset'));
+			retries = 0;
+			setMsg(`Uploading ${file.name}... ${Math.floor(offset * 100 / file.size)}%`, false);
+		} catch (error) {
+			console.log(error);
+			if (++retries > MAX_RETRIES) {
+				setMsg('Failed to upload: connection lost', true);
+				return false;
+			}
+			setMsg(`Connection lost, retrying (${retries}/${MAX_RETRIES})...`, true);
+			await sleep(2000 * retries);
+			try {
+				let response = await uploadRequest(id, 'HEAD', {});
+				if (response.status === 200) {
+					offset = parseInt(response.headers.get('Upload-Offset'));
+				}
+			} catch (error) {
+				console.log(error);
+			}
+		}
+	}
+	return true;
+}
+
+async function upload() {
+	let file = $('file').files[0];
+	if (!file) {
+		return;
+	}
+	$('upbtn').disabled = true;
+	setMsg(`Uploading ${file.name}...`, false);
+	let response = await uploadRequest('new', 'POST', {
+		'Content-Type': 'application/json',
+	}, JSON.stringify({ size: file.size, name: file.name, info: { path: cwd } }));
+	if (response.status !== 201) {
+		await uploadError(response);
+		$('upbtn').disabled = false;
+		return;
+	}
+	let id = (await response.json()).id;
+	if (await sendChunks(id, file)) {
+		response = await uploadRequest(id + '/finish', 'POST', {});
+		if (response.status !== 200) {
+			await uploadError(response);
+		} else {
+			setMsg('', false);
+			$('upload').reset();
+			load(cwd);
+		}
+	} else {
+		// If the server can't be reached the partial upload will be removed later on
+		uploadRequest(id, 'DELETE', {}).catch(console.log);
+	}
+	$('upbtn').disabled = false;
+}
+
+window.onload = function() {
+	navbar_onload();
+	$('up').onclick = function(e) {
+		let parent = cwd.replace(/\/[^\/]*\/?$/, '');
+		load(parent === '' ? '/' : parent);
+	};
+	$('mkdir').onclick = async function(e) {
+		let name = prompt('Folder name:');
+		if (name && await request({ action: 'mkdir', path: join(cwd, name) }) !== null) {
+			load(cwd);
+		}
+	};
+	$('upload').onsubmit = function(e) {
+		e.preventDefault();
+		try {
+			upload();
+		} catch (error) {
+			console.log(error);
+			setMsg('An error occurred, check logs for more info and open an issue if this persists', true);
+		}
+		return false;
+	};
+	load('/');
+}
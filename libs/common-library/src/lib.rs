@@ -3,6 +3,7 @@
 pub mod error;
 pub mod plugin;
 
+pub use actix_files;
 pub use actix_multipart;
 pub use actix_web;
 pub use async_trait::async_trait;
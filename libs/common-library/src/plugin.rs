@@ -3,6 +3,7 @@
 use std::path::PathBuf;
 
 use crate::*;
+use actix_files::NamedFile;
 use actix_multipart::form::tempfile::TempFile;
 use actix_web::HttpResponse;
 use serde::Serialize;
@@ -50,7 +51,7 @@ pub trait Plugin: Send + Sync {
     /// See the [`tiny_args`] crate to see how to make a commandline.
     /// If you don't want to implement any sub command simply return [`None`].
     ///
-    /// THE SUB COMMAND'S NAME MUST BE THE SAME NAME RETURNED BY [`Plugin::name`].
+    /// THE SUB COMMAND'S NAME MUST BE THE SAME NAME GIVEN IN [`PluginInfo::name`].
     fn subcmd(&self) -> Option<tiny_args::Command>;
 
     /// Returns the default configuration of the plugin.
@@ -89,6 +90,22 @@ pub trait Plugin: Send + Sync {
         info: Json,
         path: PathBuf,
     ) -> HttpResponse;
+
+    /// Returns a file to be downloaded by the client.
+    /// The file is streamed to the client, with support for range requests and caching headers.
+    /// By default plugins do not allow downloads.
+    ///
+    /// - `file`: Path of the requested file, as sent by the client.
+    /// - `path`: Path where the plugin can manage its files for the specific user.
+    async fn download(
+        &self,
+        user: Option<User>,
+        file: String,
+        path: PathBuf,
+    ) -> Result<NamedFile, HttpResponse> {
+        let _ = (user, file, path);
+        Err(HttpResponse::NotFound().body(""))
+    }
 }
 
 #[cfg(test)]
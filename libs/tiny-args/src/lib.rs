@@ -543,6 +543,12 @@ impl Command {
         self
     }
 
+    /// Returns the name of the command.
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
     fn add_parents(&mut self, grandparents: Vec<&'static str>, parent: &'static str) {
         let mut parents = grandparents;
         parents.push(parent);
@@ -63,6 +63,13 @@
 //! log::error!("An error");
 //! // --------------------------
 //!
+//! // Levels can be changed at any time, e.g. when the configuration is reloaded.
+//! tiny_logs::set_levels(
+//!     LevelFilter::Warn,
+//!     LevelFilter::Info,
+//!     #[cfg(feature = "syslog")] LevelFilter::Error
+//! );
+//!
 //! // Remember to always close the logger at the end of the program
 //! // to ensure that everything is written and closed correctly.
 //! tiny_logs::end().await;
@@ -73,7 +80,14 @@
 #[cfg(feature = "syslog")]
 mod syslog;
 
-use std::{cmp::max, pin::Pin, sync::LazyLock};
+use std::{
+    cmp::max,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        LazyLock,
+    },
+};
 
 use log::{Level, LevelFilter, Metadata, Record};
 use owo_colors::{colors::css::DimGray, OwoColorize, Stream::Stdout};
@@ -91,6 +105,54 @@ use tokio::{
 
 static LOGGER_HANDLER: LazyLock<Mutex<Option<LoggerHandler>>> = LazyLock::new(|| Mutex::new(None));
 
+/// Level filters of the logger, kept apart from it so that [`set_levels`] can change them.
+struct Levels {
+    stdout: AtomicUsize,
+    file: AtomicUsize,
+    #[cfg(feature = "syslog")]
+    syslog: AtomicUsize,
+    has_file: AtomicBool,
+    #[cfg(feature = "syslog")]
+    has_syslog: AtomicBool,
+}
+
+static LEVELS: Levels = Levels {
+    stdout: AtomicUsize::new(0),
+    file: AtomicUsize::new(0),
+    #[cfg(feature = "syslog")]
+    syslog: AtomicUsize::new(0),
+    has_file: AtomicBool::new(false),
+    #[cfg(feature = "syslog")]
+    has_syslog: AtomicBool::new(false),
+};
+
+fn load_level(level: &AtomicUsize) -> LevelFilter {
+    LevelFilter::iter()
+        .nth(level.load(Ordering::Relaxed))
+        .unwrap_or(LevelFilter::Off)
+}
+
+fn store_levels(
+    level: LevelFilter,
+    file_level: LevelFilter,
+    #[cfg(feature = "syslog")] syslog_level: LevelFilter,
+) {
+    LEVELS.stdout.store(level as usize, Ordering::Relaxed);
+    LEVELS.file.store(file_level as usize, Ordering::Relaxed);
+    #[cfg(feature = "syslog")]
+    LEVELS
+        .syslog
+        .store(syslog_level as usize, Ordering::Relaxed);
+
+    #[cfg(not(feature = "syslog"))]
+    let max_level = max(level, file_level);
+
+    #[cfg(feature = "syslog")]
+    let max_level = max(level, max(file_level, syslog_level));
+
+    log::set_max_level(max_level);
+}
+
 static DATE_FMT: &[FormatItem] =
     format_description!("[year]/[month]/[day]-[hour]:[minute]:[second].[subsecond digits:2]");
 
@@ -243,26 +305,28 @@ pub async fn init(
     let (send, recv) = unbounded_channel::<LogMsg>();
 
     let logger = Box::new(TinyLogger {
-        level,
-        file_level,
-        #[cfg(feature = "syslog")]
-        syslog_level,
         sender: send.clone(),
     });
 
     #[cfg(feature = "syslog")]
     syslog::init(syslog_level).await;
 
-    let joinhandle = task::spawn(async move { writer(recv, file).await });
-    
-    #[cfg(not(feature = "syslog"))]
-    let max_level = max(level, file_level);
-    
+    LEVELS.has_file.store(file.is_some(), Ordering::Relaxed);
     #[cfg(feature = "syslog")]
-    let max_level = max(level, max(file_level, syslog_level));
+    LEVELS
+        .has_syslog
+        .store(syslog_level != LevelFilter::Off, Ordering::Relaxed);
+    let joinhandle = task::spawn(async move { writer(recv, file).await });
 
     log::set_boxed_logger(logger)
-        .map(|_| log::set_max_level(max_level))
+        .map(|_| {
+            store_levels(
+                level,
+                file_level,
+                #[cfg(feature = "syslog")]
+                syslog_level,
+            )
+        })
         .map_err(|e| format!("Failed to initialize logger: {e}"))?;
 
     let handler = &mut *LOGGER_HANDLER.lock().await;
@@ -273,6 +337,34 @@ pub async fn init(
     Ok(())
 }
 
+/// Changes the level filters of the logger, with the same meaning they have in [`init`].
+///
+/// The log file can't be changed, so `file_level` is ignored if the logger was initialized without one.
+/// The same goes for `syslog_level`, since the system logger is only opened by [`init`] if its level is not `off`.
+pub fn set_levels(
+    level: LevelFilter,
+    file_level: LevelFilter,
+    #[cfg(feature = "syslog")] syslog_level: LevelFilter,
+) {
+    let file_level = if LEVELS.has_file.load(Ordering::Relaxed) {
+        file_level
+    } else {
+        LevelFilter::Off
+    };
+    #[cfg(feature = "syslog")]
+    let syslog_level = if LEVELS.has_syslog.load(Ordering::Relaxed) {
+        syslog_level
+    } else {
+        LevelFilter::Off
+    };
+    store_levels(
+        level,
+        file_level,
+        #[cfg(feature = "syslog")]
+        syslog_level,
+    );
+}
+
 /// Must be called at the end of your program.
 pub async fn end() {
     let handler = &mut *LOGGER_HANDLER.lock().await;
@@ -307,10 +399,6 @@ impl LoggerHandler {
 /// You don't have to use this struct directly. [`init`] initializes the logger on its own.
 /// After initializing, use the [`log`] crate and its macros for logging.
 pub struct TinyLogger {
-    level: LevelFilter,
-    file_level: LevelFilter,
-    #[cfg(feature = "syslog")]
-    syslog_level: LevelFilter,
     sender: UnboundedSender<LogMsg>,
 }
 
@@ -320,12 +408,14 @@ impl log::Log for TinyLogger {
 
         #[cfg(feature = "syslog")]
         {
-            lvl <= self.level || lvl <= self.file_level || lvl <= self.syslog_level
+            lvl <= load_level(&LEVELS.stdout)
+                || lvl <= load_level(&LEVELS.file)
+                || lvl <= load_level(&LEVELS.syslog)
         }
 
         #[cfg(not(feature = "syslog"))]
         {
-            lvl <= self.level || lvl <= self.file_level
+            lvl <= load_level(&LEVELS.stdout) || lvl <= load_level(&LEVELS.file)
         }
     }
 
@@ -339,20 +429,20 @@ impl log::Log for TinyLogger {
         let args = format!("{}", record.args());
         let lvl = metadata.level();
 
-        if lvl <= self.level {
+        if lvl <= load_level(&LEVELS.stdout) {
             let _ = self
                 .sender
                 .send(LogMsg::Stdout(create_log_colored(record, &args, &now)));
         }
 
-        if lvl <= self.file_level {
+        if lvl <= load_level(&LEVELS.file) {
             let _ = self
                 .sender
                 .send(LogMsg::File(create_log(record, &args, &now)));
         }
 
         #[cfg(feature = "syslog")]
-        if lvl <= self.syslog_level {
+        if lvl <= load_level(&LEVELS.syslog) {
             let _ = self.sender.send(LogMsg::Syslog(lvl, args));
         }
     }
@@ -369,7 +459,7 @@ mod tests {
     use tempfile::NamedTempFile;
     use tokio::{fs::File, io::AsyncReadExt};
 
-    use crate::{end, init};
+    use crate::{end, init, set_levels};
 
     #[tokio::test(flavor = "multi_thread")]
     async fn logging1() {
@@ -500,6 +590,52 @@ mod tests {
         tmp.close().unwrap();
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn set_levels1() {
+        let tmp = NamedTempFile::new().unwrap();
+        let path = tmp.path().as_os_str().to_str().unwrap();
+        init(
+            LevelFilter::Off,
+            Some(path.to_string()),
+            LevelFilter::Error,
+            #[cfg(feature = "syslog")]
+            LevelFilter::Off,
+        )
+        .await
+        .unwrap();
+        log::warn!("set_levels1");
+        set_levels(
+            LevelFilter::Off,
+            LevelFilter::Warn,
+            #[cfg(feature = "syslog")]
+            LevelFilter::Off,
+        );
+        log::info!("set_levels1");
+        log::warn!("set_levels1");
+        end().await;
+
+        // Test output
+        let mut file = File::open(path).await.unwrap();
+        let mut content = String::new();
+        file.read_to_string(&mut content).await.unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("[WARN]") && lines[0].ends_with("set_levels1"));
+        tmp.close().unwrap();
+    }
+
+    #[cfg(feature = "syslog")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn set_levels_syslog() {
+        init(LevelFilter::Off, None, LevelFilter::Off, LevelFilter::Off)
+            .await
+            .unwrap();
+        set_levels(LevelFilter::Off, LevelFilter::Off, LevelFilter::Trace);
+        assert_eq!(log::max_level(), LevelFilter::Off);
+        assert!(!log::logger().enabled(&log::Metadata::builder().level(log::Level::Error).build()));
+        end().await;
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn level3() {
         let tmp = NamedTempFile::new().unwrap();
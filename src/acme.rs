@@ -0,0 +1,373 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+mod client;
+
+use crate::config;
+use crate::config::{Acme, AcmeChallenge, Tls};
+use crate::tls;
+use actix_web::{HttpResponse, Responder, get, web};
+use client::{Account, Status};
+use rcgen::{CertificateParams, CustomExtension, KeyPair};
+use ring::digest::{SHA256, digest};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{fs, io::AsyncWriteExt};
+use x509_parser::{extensions::GeneralName, pem::parse_x509_pem};
+
+mutually_exclusive_features::none_or_one_of!("acme", "no-tls");
+
+/// Time between two checks of the certificate
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// Time before trying again after a failed order
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Key authorizations of the HTTP-01 challenges in progress, by token
+static HTTP_CHALLENGES: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn acme_dir(data_directory: &str) -> PathBuf {
+    let mut path = PathBuf::from(data_directory);
+    path.push("acme");
+    path
+}
+
+/// Files of the certificate obtained by ACME, used as the `tls` table
+pub fn tls_files(data_directory: &str) -> Tls {
+    Tls {
+        privkey_path: format!("{data_directory}/acme/privkey.pem"),
+        cert_path: format!("{data_directory}/acme/cert.pem"),
+    }
+}
+
+/// Serves the key authorizations of HTTP-01 challenges, at the root of the server whatever its `url_prefix`
+#[get("/.well-known/acme-challenge/{token}")]
+pub async fn http_challenge(token: web::Path<String>) -> impl Responder {
+    let challenges = HTTP_CHALLENGES.lock().unwrap_or_else(PoisonError::into_inner);
+    match challenges.get(token.as_str()) {
+        Some(key_authorization) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(key_authorization.clone()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Challenge answered by the server until it is dropped
+enum Pending {
+    Http(String),
+    TlsAlpn(String),
+}
+
+impl Pending {
+    fn start(kind: AcmeChallenge, domain: &str, token: &str, key_authorization: String) -> Result<Self, String> {
+        match kind {
+            AcmeChallenge::Http01 => {
+                HTTP_CHALLENGES
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(token.to_string(), key_authorization);
+                Ok(Self::Http(token.to_string()))
+            }
+            AcmeChallenge::TlsAlpn01 => {
+                // Self-signed certificate carrying the digest of the key authorization, as described by RFC 8737
+                let key = KeyPair::generate().map_err(|e| format!("Failed to create challenge key: {e}"))?;
+                let mut params =
+                    CertificateParams::new(vec![domain.to_string()]).map_err(|e| format!("Invalid domain `{domain}`: {e}"))?;
+                params.custom_extensions = vec![CustomExtension::new_acme_identifier(
+                    digest(&SHA256, key_authorization.as_bytes()).as_ref(),
+                )];
+                let cert = params
+                    .self_signed(&key)
+                    .map_err(|e| format!("Failed to create challenge certificate: {e}"))?;
+                tls::set_challenge(domain, cert.pem().as_bytes(), key.serialize_pem().as_bytes())?;
+                Ok(Self::TlsAlpn(domain.to_string()))
+            }
+        }
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        match self {
+            Self::Http(token) => {
+                HTTP_CHALLENGES.lock().unwrap_or_else(PoisonError::into_inner).remove(token);
+            }
+            Self::TlsAlpn(domain) => tls::remove_challenge(domain),
+        }
+    }
+}
+
+/// Proves control of every domain, then gets the certificate and its new private key, both in PEM format.
+/// Runs the whole exchange with the ACME server, so it blocks.
+fn order(acme: &Acme, ca_cert: Option<Vec<u8>>, account_key: &[u8]) -> Result<(String, String), String> {
+    let contact = acme.contact.as_deref().unwrap_or_default();
+    let mut account = Account::open(&acme.directory_url, ca_cert.as_deref(), account_key, contact)?;
+    let order = account.new_order(&acme.domains)?;
+    for url in &order.authorizations {
+        let authorization = account.authorization(url)?;
+        let domain = authorization.identifier.value.clone();
+        if authorization.status == Status::Valid {
+            log::info!("Control of `{domain}` was already proven to the ACME server");
+            continue;
+        }
+        let name = acme.challenge.as_str();
+        let challenge = authorization
+            .challenges
+            .into_iter()
+            .find(|c| c.kind == name)
+            .ok_or(format!("ACME server does not offer a {name} challenge for `{domain}`"))?;
+        let key_authorization = account.key_authorization(&challenge.token);
+        let _pending = Pending::start(acme.challenge, &domain, &challenge.token, key_authorization)?;
+        log::info!("Proving control of `{domain}` with a {name} challenge");
+        account.validate(url, &challenge)?;
+    }
+
+    let key = KeyPair::generate().map_err(|e| format!("Failed to create private key: {e}"))?;
+    let csr = CertificateParams::new(acme.domains.clone())
+        .and_then(|params| params.serialize_request(&key))
+        .map_err(|e| format!("Failed to create certificate signing request: {e}"))?;
+    let cert = account.finalize(order, csr.der())?;
+    Ok((cert, key.serialize_pem()))
+}
+
+/// Why the current certificate must be replaced, if it must
+fn renewal_reason(acme: &Acme, cert_pem: &[u8]) -> Option<String> {
+    let pem = match parse_x509_pem(cert_pem) {
+        Ok((_, pem)) => pem,
+        Err(e) => return Some(format!("the current certificate can't be read: {e}")),
+    };
+    let cert = match pem.parse_x509() {
+        Ok(cert) => cert,
+        Err(e) => return Some(format!("the current certificate can't be read: {e}")),
+    };
+    let mut names: Vec<&str> = match cert.subject_alternative_name() {
+        Ok(Some(names)) => names
+            .value
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) => Some(*name),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let mut domains: Vec<&str> = acme.domains.iter().map(String::as_str).collect();
+    names.sort_unstable();
+    domains.sort_unstable();
+    if names != domains {
+        return Some("the configured domains changed".into());
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+    let days_left = (cert.validity().not_after.timestamp() - now) / (24 * 60 * 60);
+    if days_left < 0 {
+        return Some("the current certificate has expired".into());
+    }
+    (days_left < acme.renew_days as i64).then(|| format!("the current certificate expires in {days_left} days"))
+}
+
+/// Writes `content` next to `path` then moves it there, so that the file is never read half written
+async fn write_file(path: &Path, content: &str, private: bool) -> Result<(), String> {
+    let mut new_path = path.to_path_buf().into_os_string();
+    new_path.push(".new");
+    let new_path = PathBuf::from(new_path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let mut file = options
+        .open(&new_path)
+        .await
+        .map_err(|e| format!("Failed to create `{}`: {e}", new_path.display()))?;
+    file.write_all(content.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to write `{}`: {e}", new_path.display()))?;
+    file.sync_all()
+        .await
+        .map_err(|e| format!("Failed to write `{}`: {e}", new_path.display()))?;
+    fs::rename(&new_path, path)
+        .await
+        .map_err(|e| format!("Failed to move `{}` to `{}`: {e}", new_path.display(), path.display()))
+}
+
+/// Writes the private key first, a certificate not matching it yet is not reloaded
+async fn write_certificate(tls: &Tls, cert: &str, key: &str) -> Result<(), String> {
+    write_file(Path::new(&tls.privkey_path), key, true).await?;
+    write_file(Path::new(&tls.cert_path), cert, false).await
+}
+
+/// Reads the key of the ACME account, creating it the first time
+async fn account_key() -> Result<Vec<u8>, String> {
+    let path = acme_dir(config!(data_directory)).join("account.key");
+    if let Ok(pem) = fs::read_to_string(&path).await {
+        let key = KeyPair::from_pem(&pem).map_err(|e| format!("Failed to read ACME account key `{}`: {e}", path.display()))?;
+        return Ok(key.serialize_der());
+    }
+    let key = KeyPair::generate().map_err(|e| format!("Failed to create ACME account key: {e}"))?;
+    write_file(&path, &key.serialize_pem(), true).await?;
+    log::info!("Created ACME account key `{}`", path.display());
+    Ok(key.serialize_der())
+}
+
+/// Creates the directory of the ACME files, along with a self-signed certificate for the server to start with.
+/// It is already expired, so that it is replaced by the first order.
+pub async fn init() -> Result<(), String> {
+    let Some(acme) = config!(acme) else {
+        return Ok(());
+    };
+    fs::create_dir_all(acme_dir(config!(data_directory)))
+        .await
+        .map_err(|e| format!("Failed to create ACME directory: {e}"))?;
+    let tls = tls_files(config!(data_directory));
+    if fs::try_exists(&tls.cert_path).await.unwrap_or(false) {
+        return Ok(());
+    }
+    let key = KeyPair::generate().map_err(|e| format!("Failed to create private key: {e}"))?;
+    let mut params = CertificateParams::new(acme.domains.clone()).map_err(|e| format!("Invalid `acme.domains`: {e}"))?;
+    params.not_after = params.not_before;
+    let cert = params
+        .self_signed(&key)
+        .map_err(|e| format!("Failed to create self-signed certificate: {e}"))?;
+    write_certificate(&tls, &cert.pem(), &key.serialize_pem()).await?;
+    log::info!("No certificate obtained with ACME yet, starting with a self-signed one");
+    Ok(())
+}
+
+/// Reads the certificate the ACME server is trusted with, if it is not one of the usual ones
+async fn ca_cert(acme: &Acme) -> Result<Option<Vec<u8>>, String> {
+    match &acme.ca_cert_path {
+        Some(path) => fs::read(path)
+            .await
+            .map(Some)
+            .map_err(|e| format!("Failed to read `acme.ca_cert_path` `{path}`: {e}")),
+        None => Ok(None),
+    }
+}
+
+/// Orders a new certificate if the current one is about to expire or does not cover the configured domains
+async fn renew_if_needed() -> Result<(), String> {
+    let Some(acme) = config!(acme).clone() else {
+        return Ok(());
+    };
+    let tls = tls_files(config!(data_directory));
+    let cert_pem = fs::read(&tls.cert_path).await.unwrap_or_default();
+    let Some(reason) = renewal_reason(&acme, &cert_pem) else {
+        return Ok(());
+    };
+    log::info!("Ordering a certificate from {}, since {reason}", acme.directory_url);
+
+    let account_key = account_key().await?;
+    let ca_cert = ca_cert(&acme).await?;
+    let domains = acme.domains.join(", ");
+    let (cert, key) = tokio::task::spawn_blocking(move || order(&acme, ca_cert, &account_key))
+        .await
+        .map_err(|e| format!("ACME order stopped: {e}"))??;
+    write_certificate(&tls, &cert, &key).await?;
+    tls::reload(&tls)?;
+    log::info!("Obtained a certificate for {domains} with ACME");
+    Ok(())
+}
+
+/// Checks the certificate every 12 hours and renews it when needed, retrying every hour after a failure
+pub async fn renew() {
+    loop {
+        let wait = match renew_if_needed().await {
+            Ok(()) => CHECK_INTERVAL,
+            Err(e) => {
+                log::error!("Failed to obtain a certificate with ACME: {e}");
+                RETRY_INTERVAL
+            }
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn acme(domains: &[&str]) -> Acme {
+        Acme {
+            domains: domains.iter().map(|d| d.to_string()).collect(),
+            contact: None,
+            directory_url: String::new(),
+            challenge: AcmeChallenge::Http01,
+            ca_cert_path: None,
+            renew_days: 30,
+        }
+    }
+
+    /// Self-signed certificate for `domains`, expiring in `days` days
+    fn certificate(domains: &[&str], days: i64) -> Vec<u8> {
+        let mut params = CertificateParams::new(domains.iter().map(|d| d.to_string()).collect::<Vec<_>>()).unwrap();
+        let now = rcgen::date_time_ymd(1970, 1, 1) + SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let shift = Duration::from_secs(days.unsigned_abs() * 24 * 60 * 60);
+        params.not_after = if days < 0 { now - shift } else { now + shift };
+        params.self_signed(&KeyPair::generate().unwrap()).unwrap().pem().into_bytes()
+    }
+
+    #[test]
+    fn valid_certificates_are_kept() {
+        let acme = acme(&["b.example.com", "a.example.com"]);
+        assert_eq!(renewal_reason(&acme, &certificate(&["a.example.com", "b.example.com"], 60)), None);
+    }
+
+    #[test]
+    fn certificates_are_renewed_before_they_expire() {
+        let acme = acme(&["example.com"]);
+        let reason = renewal_reason(&acme, &certificate(&["example.com"], 10)).unwrap();
+        assert!(reason.starts_with("the current certificate expires in "), "{reason}");
+        assert_eq!(
+            renewal_reason(&acme, &certificate(&["example.com"], -1)).as_deref(),
+            Some("the current certificate has expired")
+        );
+    }
+
+    #[test]
+    fn certificates_are_renewed_when_domains_change() {
+        let acme = acme(&["example.com", "www.example.com"]);
+        assert_eq!(
+            renewal_reason(&acme, &certificate(&["example.com"], 60)).as_deref(),
+            Some("the configured domains changed")
+        );
+    }
+
+    #[test]
+    fn unreadable_certificates_are_renewed() {
+        let acme = acme(&["example.com"]);
+        for pem in [&b""[..], b"-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n"] {
+            let reason = renewal_reason(&acme, pem).unwrap();
+            assert!(reason.starts_with("the current certificate can't be read"), "{reason}");
+        }
+    }
+
+    /// Orders a certificate from a local pebble server started with `PEBBLE_VA_ALWAYS_VALID=1`, trusting the CA
+    /// certificate given by `PEBBLE_CA_CERT`, e.g. `test/certs/pebble.minica.pem` in its repository.
+    /// `PEBBLE_DIRECTORY` gives the directory of a server not listening on the default port.
+    #[actix_web::test]
+    #[ignore = "needs a pebble server"]
+    async fn orders_from_pebble() {
+        let acme = Acme {
+            directory_url: env::var("PEBBLE_DIRECTORY").unwrap_or("https://localhost:14000/dir".into()),
+            ca_cert_path: Some(env::var("PEBBLE_CA_CERT").expect("PEBBLE_CA_CERT must give the CA certificate of pebble")),
+            ..acme(&["tiny-cloud.example.com", "www.tiny-cloud.example.com"])
+        };
+        let ca_cert = ca_cert(&acme).await.unwrap();
+        let account_key = KeyPair::generate().unwrap().serialize_der();
+        let (cert, key) = {
+            let acme = acme.clone();
+            tokio::task::spawn_blocking(move || order(&acme, ca_cert, &account_key))
+                .await
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(renewal_reason(&acme, cert.as_bytes()), None);
+        KeyPair::from_pem(&key).unwrap();
+        assert!(HTTP_CHALLENGES.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use common_library::serde_json::{self, json};
+use ring::{
+    digest::{SHA256, digest},
+    rand::SystemRandom,
+    signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair},
+};
+use serde::{Deserialize, de::DeserializeOwned};
+use std::{fmt, thread, time::Duration};
+use ureq::{
+    Agent,
+    http::Response,
+    tls::{Certificate, RootCerts, TlsConfig},
+};
+
+/// Times an authorization or an order is polled before giving up
+const POLL_ATTEMPTS: u32 = 30;
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+    Deactivated,
+    Expired,
+    Revoked,
+}
+
+/// Error returned by the ACME server, as described by RFC 7807
+#[derive(Deserialize, Default)]
+struct Problem {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.detail, self.kind)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Order {
+    #[serde(skip)]
+    url: String,
+    status: Status,
+    pub authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Problem>,
+}
+
+#[derive(Deserialize)]
+pub struct Identifier {
+    pub value: String,
+}
+
+#[derive(Deserialize)]
+pub struct Authorization {
+    pub identifier: Identifier,
+    pub status: Status,
+    pub challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+pub struct Challenge {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub url: String,
+    pub token: String,
+    error: Option<Problem>,
+}
+
+/// Account on an ACME server, every request is signed with its key as described by RFC 8555
+pub struct Account {
+    agent: Agent,
+    directory: Directory,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+    /// Public key of the account, serialized with its members in the order the thumbprint needs
+    jwk: String,
+    /// URL of the account, given instead of the key once it is known
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+fn base64(data: impl AsRef<[u8]>) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+/// JWK of a P-256 public key, serialized with its members in the order the thumbprint needs
+fn jwk(public_key: &[u8]) -> String {
+    // Uncompressed point: 0x04, then 32 bytes of each coordinate
+    format!(
+        r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+        base64(&public_key[1..33]),
+        base64(&public_key[33..65])
+    )
+}
+
+/// Thumbprint of a JWK as described by RFC 7638, the JWK must already be serialized with its required members only,
+/// sorted and without whitespace
+fn thumbprint(jwk: &str) -> String {
+    base64(digest(&SHA256, jwk.as_bytes()))
+}
+
+fn header(response: &Response<ureq::Body>, name: &str) -> Option<String> {
+    response.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+}
+
+fn read<T: DeserializeOwned>(response: &mut Response<ureq::Body>, url: &str) -> Result<T, String> {
+    let body = response
+        .body_mut()
+        .read_to_string()
+        .map_err(|e| format!("Failed to read answer of {url}: {e}"))?;
+    serde_json::from_str(&body).map_err(|e| format!("Unexpected answer from {url}: {e}"))
+}
+
+impl Account {
+    /// Creates the account of `key`, a PKCS#8 ECDSA P-256 key, or finds it if it already exists.
+    pub fn open(directory_url: &str, ca_cert: Option<&[u8]>, key: &[u8], contact: &[String]) -> Result<Self, String> {
+        let mut tls = TlsConfig::builder();
+        if let Some(pem) = ca_cert {
+            let cert = Certificate::from_pem(pem).map_err(|e| format!("Failed to read CA certificate of ACME server: {e}"))?;
+            tls = tls.root_certs(RootCerts::new_with_certs(&[cert]));
+        }
+        let agent: Agent = Agent::config_builder()
+            .http_status_as_error(false)
+            .timeout_global(Some(Duration::from_secs(30)))
+            .tls_config(tls.build())
+            .build()
+            .into();
+        let mut response = agent
+            .get(directory_url)
+            .call()
+            .map_err(|e| format!("Failed to get ACME directory {directory_url}: {e}"))?;
+        let directory = read(&mut response, directory_url)?;
+
+        let rng = SystemRandom::new();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, key, &rng)
+            .map_err(|e| format!("Failed to read ACME account key: {e}"))?;
+        let jwk = jwk(key.public_key().as_ref());
+        let mut account = Self {
+            agent,
+            directory,
+            key,
+            rng,
+            jwk,
+            kid: None,
+            nonce: None,
+        };
+
+        let url = account.directory.new_account.clone();
+        let response = account.post(
+            &url,
+            Some(json!({
+                "termsOfServiceAgreed": true,
+                "contact": contact,
+            })),
+        )?;
+        account.kid = Some(header(&response, "location").ok_or("ACME server did not give the URL of the account")?);
+        Ok(account)
+    }
+
+    fn new_nonce(&self) -> Result<String, String> {
+        let response = self
+            .agent
+            .head(&self.directory.new_nonce)
+            .call()
+            .map_err(|e| format!("Failed to get ACME nonce: {e}"))?;
+        header(&response, "replay-nonce").ok_or("ACME server did not give a nonce".into())
+    }
+
+    /// Sends a signed request, `payload` is `None` for POST-as-GET requests.
+    /// A nonce refused by the server is replaced once.
+    fn post(&mut self, url: &str, payload: Option<serde_json::Value>) -> Result<Response<ureq::Body>, String> {
+        let payload = payload.map_or(String::new(), |p| base64(p.to_string()));
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.new_nonce()?,
+            };
+            let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+            match &self.kid {
+                Some(kid) => protected["kid"] = json!(kid),
+                None => {
+                    protected["jwk"] = serde_json::from_str(&self.jwk).map_err(|e| format!("Invalid account key: {e}"))?;
+                }
+            }
+            let protected = base64(protected.to_string());
+            let signature = self
+                .key
+                .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+                .map_err(|e| format!("Failed to sign ACME request: {e}"))?;
+            let body = json!({
+                "protected": protected,
+                "payload": payload,
+                "signature": base64(signature),
+            });
+
+            let mut response = self
+                .agent
+                .post(url)
+                .header("content-type", "application/jose+json")
+                .send(body.to_string())
+                .map_err(|e| format!("Failed to send ACME request to {url}: {e}"))?;
+            self.nonce = header(&response, "replay-nonce");
+            if response.status().is_success() {
+                return Ok(response);
+            }
+            let status = response.status();
+            let problem: Problem = read(&mut response, url).unwrap_or_default();
+            if problem.kind == "urn:ietf:params:acme:error:badNonce" && !retried {
+                retried = true;
+                continue;
+            }
+            return Err(format!("ACME server answered {status} to {url}: {problem}"));
+        }
+    }
+
+    fn get<T: DeserializeOwned>(&mut self, url: &str) -> Result<T, String> {
+        let mut response = self.post(url, None)?;
+        read(&mut response, url)
+    }
+
+    pub fn new_order(&mut self, domains: &[String]) -> Result<Order, String> {
+        let identifiers: Vec<_> = domains.iter().map(|d| json!({ "type": "dns", "value": d })).collect();
+        let url = self.directory.new_order.clone();
+        let mut response = self.post(&url, Some(json!({ "identifiers": identifiers })))?;
+        let mut order: Order = read(&mut response, &url)?;
+        order.url = header(&response, "location").ok_or("ACME server did not give the URL of the order")?;
+        Ok(order)
+    }
+
+    pub fn authorization(&mut self, url: &str) -> Result<Authorization, String> {
+        self.get(url)
+    }
+
+    /// Value proving to the server that this account answers the challenge with `token`
+    pub fn key_authorization(&self, token: &str) -> String {
+        format!("{token}.{}", thumbprint(&self.jwk))
+    }
+
+    /// Tells the server the challenge can be checked, then waits until the authorization is valid
+    pub fn validate(&mut self, authorization: &str, challenge: &Challenge) -> Result<(), String> {
+        self.post(&challenge.url, Some(json!({})))?;
+        for _ in 0..POLL_ATTEMPTS {
+            thread::sleep(POLL_INTERVAL);
+            let current = self.authorization(authorization)?;
+            match current.status {
+                Status::Pending => continue,
+                Status::Valid => return Ok(()),
+                _ => {
+                    let error = current
+                        .challenges
+                        .iter()
+                        .find(|c| c.kind == challenge.kind)
+                        .and_then(|c| c.error.as_ref())
+                        .map_or(String::new(), |e| format!(": {e}"));
+                    return Err(format!("Challenge for `{}` failed{error}", current.identifier.value));
+                }
+            }
+        }
+        Err(format!("Timed out waiting for the challenge of {authorization}"))
+    }
+
+    /// Sends the certificate signing request once every authorization is valid, then downloads the certificate chain
+    pub fn finalize(&mut self, order: Order, csr: &[u8]) -> Result<String, String> {
+        let mut order = order;
+        self.post(&order.finalize.clone(), Some(json!({ "csr": base64(csr) })))?;
+        let mut attempts = 0;
+        let certificate = loop {
+            let url = order.url;
+            order = self.get(&url)?;
+            order.url = url;
+            match (order.status, order.certificate.take()) {
+                (Status::Valid, Some(certificate)) => break certificate,
+                (Status::Invalid, _) => {
+                    let error = order.error.as_ref().map_or(String::new(), |e| format!(": {e}"));
+                    return Err(format!("Order failed{error}"));
+                }
+                _ if attempts < POLL_ATTEMPTS => attempts += 1,
+                _ => return Err(format!("Timed out waiting for the order {}", order.url)),
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        let mut response = self.post(&certificate, None)?;
+        response
+            .body_mut()
+            .read_to_string()
+            .map_err(|e| format!("Failed to download certificate: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thumbprints_follow_rfc_7638() {
+        // Example of RFC 7638, section 3.1
+        let jwk = concat!(
+            r#"{"e":"AQAB","kty":"RSA","n":""#,
+            "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W",
+            "-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNL",
+            "yrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw",
+            r#""}"#
+        );
+        assert_eq!(thumbprint(jwk), "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs");
+    }
+
+    #[test]
+    fn key_authorizations_use_the_account_thumbprint() {
+        // P-256 key of RFC 7515, appendix A.3
+        let decode = |data| URL_SAFE_NO_PAD.decode(data).unwrap();
+        let point = [
+            vec![4],
+            decode("f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU"),
+            decode("x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0"),
+        ]
+        .concat();
+        let private = decode("jpsQnnGQmL-YBIffH1136cspYG6-0iY7X1fCE9-E9LI");
+        let rng = SystemRandom::new();
+        let account = Account {
+            agent: Agent::new_with_defaults(),
+            directory: Directory {
+                new_nonce: String::new(),
+                new_account: String::new(),
+                new_order: String::new(),
+            },
+            key: EcdsaKeyPair::from_private_key_and_public_key(&ECDSA_P256_SHA256_FIXED_SIGNING, &private, &point, &rng).unwrap(),
+            rng,
+            jwk: jwk(&point),
+            kid: None,
+            nonce: None,
+        };
+        assert_eq!(
+            account.jwk,
+            r#"{"crv":"P-256","kty":"EC","x":"f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU","y":"x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0"}"#
+        );
+        assert_eq!(
+            account.key_authorization("evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA"),
+            "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA.oKIywvGUpTVTyxMQ3bwIIeQUudfr_CkLMjCE19ECD-U"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+pub mod error;
+use crate::auth::{
+    error::AuthError,
+    totp::{self, NewTotp},
+};
+use crate::database::{auth, session};
+use crate::quota;
+use async_sqlite::Pool;
+use common_library::serde_json::{Value, json};
+use error::AdminError;
+
+/// Returns every user with its admin status, storage quota and usage.
+pub async fn list_users(pool: &Pool) -> Result<Vec<Value>, AdminError> {
+    let users = auth::get_all_users(pool).await.map_err(|e| e.into())?;
+    let mut list = Vec::with_capacity(users.len());
+    for user in users {
+        let used = quota::usage(&user.username)
+            .await
+            .map_err(|e| AdminError::InternalError(e.to_string()))?;
+        list.push(json!({
+            "user": user.username,
+            "is_admin": user.is_admin,
+            "quota": user.quota,
+            "used": used,
+        }));
+    }
+    Ok(list)
+}
+
+/// Promotes or demotes a user. Admins cannot change their own status.
+pub async fn set_admin(pool: &Pool, admin: &str, username: String, is_admin: bool) -> Result<(), AdminError> {
+    if admin == username {
+        return Err(AdminError::SelfTarget);
+    }
+    auth::set_admin(pool, username.clone(), is_admin).await.map_err(|e| e.into())?;
+    log::warn!("admin `{admin}` set admin status of `{username}` to {is_admin}");
+    Ok(())
+}
+
+/// Sets the storage quota of a user in bytes, None restores the default one.
+pub async fn set_quota(pool: &Pool, admin: &str, username: String, quota: Option<u64>) -> Result<(), AdminError> {
+    auth::set_quota(pool, username.clone(), quota).await.map_err(|e| e.into())?;
+    log::warn!("admin `{admin}` set storage quota of `{username}` to {quota:?}");
+    Ok(())
+}
+
+/// Logs out every session of a user.
+pub async fn logout_user(pool: &Pool, admin: &str, username: String) -> Result<(), AdminError> {
+    session::delete_all(pool, username.clone()).await.map_err(|e| e.into())?;
+    log::warn!("admin `{admin}` logged out every session of `{username}`");
+    Ok(())
+}
+
+/// Returns the sessions of a user, from the most recent.
+pub async fn list_sessions(pool: &Pool, username: String) -> Result<Vec<Value>, AdminError> {
+    let (sessions, _) = session::list(pool, username, None).await.map_err(|e| e.into())?;
+    Ok(sessions
+        .into_iter()
+        .map(|s| json!({ "id": s.id, "created": s.created, "last_seen": s.last_seen, "ip": s.ip, "user_agent": s.user_agent }))
+        .collect())
+}
+
+/// Ends one of the sessions of a user.
+pub async fn kill_session(pool: &Pool, admin: &str, username: String, id: i64) -> Result<(), AdminError> {
+    if !session::delete(pool, username.clone(), id).await.map_err(|e| e.into())? {
+        return Err(AdminError::SessionNotFound);
+    }
+    log::warn!("admin `{admin}` logged out a session of `{username}`");
+    Ok(())
+}
+
+/// Regenerates a user's TOTP secret and recovery codes and logs out all of its sessions.
+/// Returns them once all of it is saved, since the admin must hand them over to the user.
+pub async fn reset_totp(pool: &Pool, admin: &str, username: String) -> Result<NewTotp, AdminError> {
+    let into_admin_err = |e: AuthError| match e {
+        AuthError::InternalError(err) => AdminError::InternalError(err),
+        AuthError::InvalidSession => AdminError::UserNotFound,
+        _ => AdminError::InternalError(e.to_string()),
+    };
+    let new_totp = totp::generate(username.clone()).map_err(into_admin_err)?;
+    let (recovery_codes, hashes) = totp::generate_recovery();
+    auth::reset_totp(pool, username.clone(), new_totp.get_url(), hashes)
+        .await
+        .map_err(|e| e.into())?;
+    log::warn!("admin `{admin}` reset the TOTP secret of `{username}`");
+    Ok(NewTotp {
+        totp: new_totp,
+        recovery_codes,
+    })
+}
+
+/// Deletes a user and all of its files. Admins cannot delete themselves from here.
+pub async fn delete_user(pool: &Pool, admin: &str, username: String) -> Result<(), AdminError> {
+    if admin == username {
+        return Err(AdminError::SelfTarget);
+    }
+    auth::delete_user_by_name(pool, username.clone()).await.map_err(|e| e.into())?;
+    log::warn!("admin `{admin}` deleted user `{username}`");
+    Ok(())
+}
@@ -0,0 +1,51 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use common_library::error::ErrToResponse;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AdminError {
+    #[error("An internal server error occurred")]
+    InternalError(String),
+    #[error("User was not found")]
+    UserNotFound,
+    #[error("Admins cannot do this on their own account")]
+    SelfTarget,
+    #[error("Session was not found")]
+    SessionNotFound,
+}
+
+impl ErrToResponse for AdminError {
+    fn error(&self) -> &'static str {
+        "AdminError"
+    }
+
+    fn err_type(&self) -> &'static str {
+        match self {
+            Self::InternalError(_) => stringify!(InternalError),
+            Self::UserNotFound => stringify!(UserNotFound),
+            Self::SelfTarget => stringify!(SelfTarget),
+            Self::SessionNotFound => stringify!(SessionNotFound),
+        }
+    }
+
+    fn msg(&self) -> String {
+        self.to_string()
+    }
+
+    fn http_code(&self) -> HttpResponseBuilder {
+        match self {
+            Self::InternalError(_) => HttpResponse::InternalServerError(),
+            Self::UserNotFound => HttpResponse::NotFound(),
+            Self::SelfTarget => HttpResponse::BadRequest(),
+            Self::SessionNotFound => HttpResponse::NotFound(),
+        }
+    }
+
+    fn handle(&self) {
+        if let Self::InternalError(err) = self {
+            log::error!("An internal server error occurred while managing users: {err}");
+        }
+    }
+}
@@ -1,12 +1,21 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-
+pub mod admin;
+pub mod api_token;
 pub mod auth;
 pub mod plugins;
+pub mod quota;
 pub mod token;
-use crate::config;
+pub mod upload;
+#[cfg(feature = "webauthn")]
+pub mod webauthn;
+use crate::{
+    auth::{Credentials, api_token::Scope, validate},
+    config,
+};
 use actix_web::{HttpResponse, Responder, get};
-use common_library::serde_json::json;
+use async_sqlite::Pool;
+use common_library::{error::ErrToResponse, serde_json::json};
 use std::sync::LazyLock;
 
 static INFO: LazyLock<String> = LazyLock::new(|| {
@@ -25,3 +34,13 @@ static INFO: LazyLock<String> = LazyLock::new(|| {
 pub async fn info() -> impl Responder {
     HttpResponse::Ok().content_type("application/json").body(INFO.to_owned())
 }
+
+/// Checks whether the user is an admin and returns its username.
+/// Returns the response to send back otherwise.
+pub async fn is_admin(pool: &Pool, user: Credentials) -> Result<String, HttpResponse> {
+    match validate(pool, user, Scope::Admin).await {
+        Ok((username, is_admin)) if is_admin => Ok(username),
+        Ok(_) => Err(HttpResponse::Forbidden().body("")),
+        Err(e) => Err(e.to_response()),
+    }
+}
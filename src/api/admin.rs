@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::{auth::return_totp_response, is_admin};
+use crate::admin;
+use crate::auth::Credentials;
+use actix_web::{HttpResponse, Responder, get, post, web};
+use async_sqlite::Pool;
+use common_library::error::ErrToResponse;
+use common_library::serde_json::Value;
+use serde::Deserialize;
+
+/// User selected by the admin
+#[derive(Deserialize)]
+pub struct Target {
+    user: String,
+}
+
+/// Payload to promote or demote a user
+#[derive(Deserialize)]
+pub struct SetAdmin {
+    user: String,
+    is_admin: bool,
+}
+
+/// Payload to change a user's storage quota
+#[derive(Deserialize)]
+pub struct SetQuota {
+    user: String,
+    /// Quota in bytes, null restores the default one
+    quota: Option<u64>,
+}
+
+/// Session of a user selected by the admin
+#[derive(Deserialize)]
+pub struct KillSession {
+    user: String,
+    id: i64,
+}
+
+/// Payload to reset a user's TOTP secret
+#[derive(Deserialize)]
+pub struct ResetTotp {
+    user: String,
+    totp_as_qr: bool,
+}
+
+/// Returns a list of every user with their admin status, storage quota and usage
+#[get("/list")]
+pub async fn list(user: Credentials, pool: web::Data<Pool>) -> impl Responder {
+    let pool = pool.into_inner();
+    if let Err(e) = is_admin(&pool, user).await {
+        return e;
+    }
+    match admin::list_users(&pool).await {
+        Ok(users) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(Value::Array(users).to_string()),
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Promotes or demotes a user
+#[post("/setadmin")]
+pub async fn setadmin(user: Credentials, pool: web::Data<Pool>, payload: web::Json<SetAdmin>) -> impl Responder {
+    let pool = pool.into_inner();
+    let payload = payload.into_inner();
+    let admin = match is_admin(&pool, user).await {
+        Ok(admin) => admin,
+        Err(e) => return e,
+    };
+    if let Err(e) = admin::set_admin(&pool, &admin, payload.user, payload.is_admin).await {
+        return e.to_response();
+    }
+    HttpResponse::Ok().body("")
+}
+
+/// Changes a user's storage quota
+#[post("/setquota")]
+pub async fn setquota(user: Credentials, pool: web::Data<Pool>, payload: web::Json<SetQuota>) -> impl Responder {
+    let pool = pool.into_inner();
+    let payload = payload.into_inner();
+    let admin = match is_admin(&pool, user).await {
+        Ok(admin) => admin,
+        Err(e) => return e,
+    };
+    if let Err(e) = admin::set_quota(&pool, &admin, payload.user, payload.quota).await {
+        return e.to_response();
+    }
+    HttpResponse::Ok().body("")
+}
+
+/// Logs out every session of a user
+#[post("/logout")]
+pub async fn logout(user: Credentials, pool: web::Data<Pool>, target: web::Json<Target>) -> impl Responder {
+    let pool = pool.into_inner();
+    let admin = match is_admin(&pool, user).await {
+        Ok(admin) => admin,
+        Err(e) => return e,
+    };
+    if let Err(e) = admin::logout_user(&pool, &admin, target.into_inner().user).await {
+        return e.to_response();
+    }
+    HttpResponse::Ok().body("")
+}
+
+/// Returns the sessions of a user
+#[post("/sessions")]
+pub async fn sessions(user: Credentials, pool: web::Data<Pool>, target: web::Json<Target>) -> impl Responder {
+    let pool = pool.into_inner();
+    if let Err(e) = is_admin(&pool, user).await {
+        return e;
+    }
+    match admin::list_sessions(&pool, target.into_inner().user).await {
+        Ok(sessions) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(Value::Array(sessions).to_string()),
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Logs out one of the sessions of a user
+#[post("/killsession")]
+pub async fn killsession(user: Credentials, pool: web::Data<Pool>, payload: web::Json<KillSession>) -> impl Responder {
+    let pool = pool.into_inner();
+    let payload = payload.into_inner();
+    let admin = match is_admin(&pool, user).await {
+        Ok(admin) => admin,
+        Err(e) => return e,
+    };
+    if let Err(e) = admin::kill_session(&pool, &admin, payload.user, payload.id).await {
+        return e.to_response();
+    }
+    HttpResponse::Ok().body("")
+}
+
+/// Resets a user's TOTP secret and returns it as a url or qr code depending on the request
+#[post("/resettotp")]
+pub async fn resettotp(user: Credentials, pool: web::Data<Pool>, payload: web::Json<ResetTotp>) -> impl Responder {
+    let pool = pool.into_inner();
+    let payload = payload.into_inner();
+    let admin = match is_admin(&pool, user).await {
+        Ok(admin) => admin,
+        Err(e) => return e,
+    };
+    match admin::reset_totp(&pool, &admin, payload.user).await {
+        Ok(totp) => return_totp_response(totp, payload.totp_as_qr),
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Deletes a user and all of its files
+#[post("/delete")]
+pub async fn delete(user: Credentials, pool: web::Data<Pool>, target: web::Json<Target>) -> impl Responder {
+    let pool = pool.into_inner();
+    let admin = match is_admin(&pool, user).await {
+        Ok(admin) => admin,
+        Err(e) => return e,
+    };
+    if let Err(e) = admin::delete_user(&pool, &admin, target.into_inner().user).await {
+        return e.to_response();
+    }
+    HttpResponse::Ok().body("")
+}
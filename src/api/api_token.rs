@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::{auth::api_token, config};
+use actix_identity::Identity;
+use actix_web::{HttpResponse, Responder, get, post, web};
+use async_sqlite::Pool;
+use common_library::{
+    error::ErrToResponse,
+    serde_json::{Value, json},
+};
+use serde::Deserialize;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Payload to create an API token
+#[derive(Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct NewApiToken {
+    password: String,
+    name: String,
+    /// Scopes given to the token: "plugin:NAME", "quota" or "admin"
+    scopes: Vec<String>,
+    /// Days the token stays valid
+    days: u32,
+}
+
+/// API token selected by the user
+#[derive(Deserialize)]
+pub struct Target {
+    id: i64,
+}
+
+/// Creates an API token and returns it, it is never shown again
+#[post("/new")]
+pub async fn new(user: Identity, pool: web::Data<Pool>, payload: web::Json<NewApiToken>) -> impl Responder {
+    if config!(api_tokens).is_none() {
+        return HttpResponse::NotFound().body("");
+    }
+    let pool = pool.into_inner();
+    let payload = payload.into_inner();
+    match api_token::create(
+        &pool,
+        user,
+        payload.password.as_bytes(),
+        payload.name.clone(),
+        payload.scopes.clone(),
+        payload.days,
+    )
+    .await
+    {
+        Ok(token) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!({ "token": token }).to_string()),
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Returns the API tokens of the user, without the tokens themselves
+#[get("/list")]
+pub async fn list(user: Identity, pool: web::Data<Pool>) -> impl Responder {
+    if config!(api_tokens).is_none() {
+        return HttpResponse::NotFound().body("");
+    }
+    let pool = pool.into_inner();
+    match api_token::list(&pool, user).await {
+        Ok(tokens) => HttpResponse::Ok().content_type("application/json").body(
+            Value::Array(
+                tokens
+                    .into_iter()
+                    .map(|t| {
+                        json!({
+                            "id": t.id,
+                            "name": t.name,
+                            "scopes": t.scopes.split(' ').collect::<Vec<_>>(),
+                            "created": t.created,
+                            "expires": t.expires,
+                            "last_used": t.last_used,
+                        })
+                    })
+                    .collect(),
+            )
+            .to_string(),
+        ),
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Revokes an API token of the user
+#[post("/revoke")]
+pub async fn revoke(user: Identity, pool: web::Data<Pool>, target: web::Json<Target>) -> impl Responder {
+    if config!(api_tokens).is_none() {
+        return HttpResponse::NotFound().body("");
+    }
+    let pool = pool.into_inner();
+    match api_token::revoke(&pool, user, target.id).await {
+        Ok(()) => HttpResponse::Ok().body(""),
+        Err(e) => e.to_response(),
+    }
+}
@@ -1,17 +1,18 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+#[cfg(not(feature = "no-tls"))]
+use crate::tls::PeerCertificate;
 use crate::{
-    auth::{self, error::AuthError},
+    auth::{self, error::AuthError, throttle, totp::NewTotp},
     config,
     utils::{get_ip, sanitize_user},
 };
 use actix_identity::Identity;
-use actix_web::{HttpMessage, HttpRequest, HttpResponse, Responder, dev::ConnectionInfo, get, post, web};
+use actix_web::{HttpRequest, HttpResponse, Responder, dev::ConnectionInfo, get, post, web};
 use async_sqlite::Pool;
 use common_library::error::ErrToResponse;
 use serde::Deserialize;
 use zeroize::{Zeroize, ZeroizeOnDrop};
-use totp_rs::TOTP;
 
 /// Username and password sent by the client to login.
 #[non_exhaustive]
@@ -50,6 +51,12 @@ pub struct ChangePwd {
     change_method: ChangeMethod,
 }
 
+/// Session selected by the user
+#[derive(Deserialize)]
+pub struct Revoke {
+    id: i64,
+}
+
 /// Payload to change user's TOTP secret
 #[derive(Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct ChangeTotp {
@@ -57,17 +64,19 @@ pub struct ChangeTotp {
     totp_as_qr: bool
 }
 
-fn return_totp_response(totp: TOTP, as_qr: bool) -> HttpResponse {
+/// Sends the TOTP secret to the client as a URL or as a QR code, along with the recovery codes
+pub fn return_totp_response(new_totp: NewTotp, as_qr: bool) -> HttpResponse {
     use common_library::serde_json::json;
+    let NewTotp { totp, recovery_codes } = new_totp;
     let mut resp = HttpResponse::Ok();
     resp.content_type("application/json");
     if as_qr {
         match totp.get_qr_base64() {
-            Ok(qr) => resp.body(json!({ "totp_qr": qr }).to_string()),
+            Ok(qr) => resp.body(json!({ "totp_qr": qr, "recovery_codes": recovery_codes }).to_string()),
             Err(e) => AuthError::InternalError(format!("Failed to get TOTP QR code image as base64: {e}")).to_response(),
         }
     } else {
-        resp.body(json!({ "totp_url": totp.get_url() }).to_string())
+        resp.body(json!({ "totp_url": totp.get_url(), "recovery_codes": recovery_codes }).to_string())
     }
 }
 
@@ -91,10 +100,10 @@ pub async fn register(
         )
         .await
         {
-            Ok((totp, userid)) => {
+            Ok(totp) => {
                 log::warn!("client [{}] registered as `{}`", get_ip(&conn), sanitize_user(&credentials.user));
-                if let Err(err) = Identity::login(&req.extensions(), userid) {
-                    return AuthError::InternalError(format!("Failed to build identity during registration: {err}")).to_response();
+                if let Err(err) = auth::start_session(&pool, &req, get_ip(&conn), credentials.user.clone()).await {
+                    return err.to_response();
                 }
                 return_totp_response(totp, credentials.totp_as_qr)
             }
@@ -117,16 +126,54 @@ pub async fn register(
 pub async fn login(req: HttpRequest, conn: ConnectionInfo, login: web::Json<Login>, pool: web::Data<Pool>) -> impl Responder {
     let login = login.into_inner();
     let pool = pool.into_inner();
+    let ip = get_ip(&conn);
+    let username = login.user.clone();
+    let attempt = match throttle::check(ip, &username) {
+        Ok(attempt) => attempt,
+        Err(err) => {
+            log::warn!("client [{ip}] tried to login as `{}` while locked out", sanitize_user(&username));
+            return err.to_response();
+        }
+    };
     match auth::check(&pool, login).await {
         Ok(user) => {
-            log::warn!("client [{}] logged in as `{}`", get_ip(&conn), sanitize_user(&user));
-            if let Err(err) = Identity::login(&req.extensions(), user) {
-                return AuthError::InternalError(format!("Failed to build identity during registration: {err}")).to_response();
+            log::warn!("client [{ip}] logged in as `{}`", sanitize_user(&user));
+            attempt.succeeded();
+            if let Err(err) = auth::start_session(&pool, &req, ip, user).await {
+                return err.to_response();
+            }
+            HttpResponse::Ok().body("")
+        }
+        Err(err) => {
+            log::warn!("client [{ip}] failed to login");
+            if matches!(err, AuthError::InvalidCredentials | AuthError::InvalidTOTP) {
+                attempt.failed();
+            }
+            err.to_response()
+        }
+    }
+}
+
+/// Logins with the client certificate of the connection and starts a new session.
+/// Available only when `client_auth.login` is set.
+#[cfg(not(feature = "no-tls"))]
+#[post("/certificate")]
+pub async fn certificate(req: HttpRequest, conn: ConnectionInfo, pool: web::Data<Pool>) -> impl Responder {
+    let Some(name) = config!(client_auth).as_ref().and_then(|c| c.login) else {
+        return HttpResponse::NotFound().body("");
+    };
+    let pool = pool.into_inner();
+    let ip = get_ip(&conn);
+    match auth::check_certificate(&pool, req.conn_data::<PeerCertificate>(), name).await {
+        Ok(user) => {
+            log::warn!("client [{ip}] logged in as `{}` with a client certificate", sanitize_user(&user));
+            if let Err(err) = auth::start_session(&pool, &req, ip, user).await {
+                return err.to_response();
             }
             HttpResponse::Ok().body("")
         }
         Err(err) => {
-            log::warn!("client [{}] failed to login", get_ip(&conn));
+            log::warn!("client [{ip}] failed to login with a client certificate");
             err.to_response()
         }
     }
@@ -134,16 +181,59 @@ pub async fn login(req: HttpRequest, conn: ConnectionInfo, login: web::Json<Logi
 
 /// Logs out and ends current session
 #[get("/logout")]
-pub async fn logout(user: Identity) -> impl Responder {
-    user.logout();
-    HttpResponse::Ok()
+pub async fn logout(user: Identity, pool: web::Data<Pool>) -> impl Responder {
+    let pool = pool.into_inner();
+    if let Err(err) = auth::logout(&pool, user).await {
+        err.to_response()
+    } else {
+        HttpResponse::Ok().body("")
+    }
 }
 
-/// Changes session id and causes all active sessions to logout.
+/// Ends all the sessions of the user, including the current one.
 #[get("/logoutall")]
 pub async fn logoutall(user: Identity, pool: web::Data<Pool>) -> impl Responder {
     let pool = pool.into_inner();
-    if let Err(err) = auth::change_sessionid(&pool, user).await {
+    if let Err(err) = auth::logout_all(&pool, user).await {
+        err.to_response()
+    } else {
+        HttpResponse::Ok().body("")
+    }
+}
+
+/// Returns the sessions of the user, marking the current one
+#[get("/sessions")]
+pub async fn sessions(user: Identity, pool: web::Data<Pool>) -> impl Responder {
+    use common_library::serde_json::{Value, json};
+    let pool = pool.into_inner();
+    match auth::sessions(&pool, user).await {
+        Ok((sessions, current)) => HttpResponse::Ok().content_type("application/json").body(
+            Value::Array(
+                sessions
+                    .into_iter()
+                    .map(|s| {
+                        json!({
+                            "id": s.id,
+                            "created": s.created,
+                            "last_seen": s.last_seen,
+                            "ip": s.ip,
+                            "user_agent": s.user_agent,
+                            "current": Some(s.id) == current,
+                        })
+                    })
+                    .collect(),
+            )
+            .to_string(),
+        ),
+        Err(err) => err.to_response(),
+    }
+}
+
+/// Ends one of the sessions of the user
+#[post("/revoke")]
+pub async fn revoke(user: Identity, pool: web::Data<Pool>, session: web::Json<Revoke>) -> impl Responder {
+    let pool = pool.into_inner();
+    if let Err(err) = auth::revoke_session(&pool, user, session.id).await {
         err.to_response()
     } else {
         HttpResponse::Ok().body("")
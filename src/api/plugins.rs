@@ -1,12 +1,15 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use actix_identity::Identity;
 use actix_multipart::form::{MultipartForm, json::Json as MpJson, tempfile::TempFile};
-use actix_web::{Responder, post, web};
+use actix_web::{HttpRequest, HttpResponse, Responder, get, post, web};
 use async_sqlite::Pool;
 use common_library::{Json, error::ErrToResponse, plugin::User};
 
-use crate::{auth::validate_user, plugins::Plugins};
+use crate::{
+    auth::{Credentials, api_token::Scope, validate},
+    plugins::Plugins,
+    quota,
+};
 
 #[derive(MultipartForm)]
 pub struct FileForm {
@@ -14,6 +17,18 @@ pub struct FileForm {
     pub info: MpJson<Json>,
 }
 
+/// Gets the user making a request to `plugin`, if there is one.
+/// Returns the response to send back if the session or the API token is not valid.
+pub async fn get_user(pool: &Pool, user: Option<Credentials>, plugin: &str) -> Result<Option<User>, HttpResponse> {
+    match user {
+        Some(user) => match validate(pool, user, Scope::Plugin(plugin)).await {
+            Ok((username, is_admin)) => Ok(Some(User { name: username, is_admin })),
+            Err(e) => Err(e.to_response()),
+        },
+        None => Ok(None),
+    }
+}
+
 /// Handles plugins
 #[post("/p/{plugin}")]
 pub async fn handler(
@@ -21,18 +36,15 @@ pub async fn handler(
     plugin: web::Path<String>,
     body: web::Json<Json>,
     plugins: web::Data<Plugins>,
-    user: Option<Identity>,
+    user: Option<Credentials>,
 ) -> impl Responder {
     let pool = pool.into_inner();
     let plugin = plugin.into_inner();
     let plugins = plugins.into_inner();
     let body = body.into_inner();
-    let user: Option<User> = match user {
-        Some(user) => match validate_user(&pool, user).await {
-            Ok((username, is_admin)) => Some(User { name: username, is_admin }),
-            Err(e) => return e.to_response(),
-        },
-        None => None,
+    let user = match get_user(&pool, user, &plugin).await {
+        Ok(user) => user,
+        Err(e) => return e,
     };
     plugins.request(plugin, user, body).await
 }
@@ -43,18 +55,37 @@ pub async fn file(
     pool: web::Data<Pool>,
     plugins: web::Data<Plugins>,
     plugin: web::Path<String>,
-    user: Option<Identity>,
+    user: Option<Credentials>,
     MultipartForm(form): MultipartForm<FileForm>,
 ) -> impl Responder {
     let pool = pool.into_inner();
     let plugin = plugin.into_inner();
     let plugins = plugins.into_inner();
-    let user: Option<User> = match user {
-        Some(user) => match validate_user(&pool, user).await {
-            Ok((username, is_admin)) => Some(User { name: username, is_admin }),
-            Err(e) => return e.to_response(),
-        },
-        None => None,
+    let user = match get_user(&pool, user, &plugin).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+    if let Err(e) = quota::check(&pool, &user, form.file.size as u64).await {
+        return e.to_response();
+    }
+    plugins.file(plugin, user, form.file, form.info.into_inner()).await
+}
+
+/// Handles file downloads for plugins
+#[get("/down/{plugin}/{file:.*}")]
+pub async fn download(
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+    plugins: web::Data<Plugins>,
+    path: web::Path<(String, String)>,
+    user: Option<Credentials>,
+) -> impl Responder {
+    let pool = pool.into_inner();
+    let (plugin, filename) = path.into_inner();
+    let plugins = plugins.into_inner();
+    let user = match get_user(&pool, user, &plugin).await {
+        Ok(user) => user,
+        Err(e) => return e,
     };
-    plugins.file(plugin, user, form).await
+    plugins.download(&req, plugin, user, filename).await
 }
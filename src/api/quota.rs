@@ -0,0 +1,30 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::{
+    auth::{Credentials, api_token::Scope, validate},
+    quota,
+};
+use actix_web::{HttpResponse, Responder, get, web};
+use async_sqlite::Pool;
+use common_library::{error::ErrToResponse, serde_json::json};
+
+/// Returns the bytes used by the user and its quota, which is null if there is no limit
+#[get("/usage")]
+pub async fn usage(user: Credentials, pool: web::Data<Pool>) -> impl Responder {
+    let pool = pool.into_inner();
+    let username = match validate(&pool, user, Scope::Quota).await {
+        Ok((username, _)) => username,
+        Err(e) => return e.to_response(),
+    };
+    let limit = match quota::limit(&pool, &username).await {
+        Ok(limit) => limit,
+        Err(e) => return e.to_response(),
+    };
+    match quota::usage(&username).await {
+        Ok(used) => HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header(("Cache-Control", "no-store"))
+            .body(json!({ "used": used, "quota": limit }).to_string()),
+        Err(e) => e.to_response(),
+    }
+}
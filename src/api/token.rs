@@ -1,10 +1,10 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use crate::auth::validate_user;
+use super::is_admin;
+use crate::auth::Credentials;
 use crate::config;
 use crate::database;
 use crate::token::{self, error::TokenError};
-use actix_identity::Identity;
 use actix_web::{HttpResponse, Responder, get, post, web};
 use async_sqlite::Pool;
 use common_library::error::ErrToResponse;
@@ -25,17 +25,9 @@ struct TokenInfo {
     token: Option<String>,
 }
 
-async fn is_admin(pool: &Pool, user: Identity) -> Result<(), HttpResponse> {
-    match validate_user(pool, user).await {
-        Ok((_, is_admin)) if is_admin => Ok(()),
-        Ok(_) => Err(HttpResponse::Forbidden().body("")),
-        Err(e) => Err(e.to_response()),
-    }
-}
-
 /// Creates a new token
 #[post("/new")]
-pub async fn new(user: Identity, pool: web::Data<Pool>, info: web::Json<NewToken>) -> impl Responder {
+pub async fn new(user: Credentials, pool: web::Data<Pool>, info: web::Json<NewToken>) -> impl Responder {
     if let Some(registration) = config!(registration) {
         let pool = pool.into_inner();
         if let Err(e) = is_admin(&pool, user).await {
@@ -54,7 +46,7 @@ pub async fn new(user: Identity, pool: web::Data<Pool>, info: web::Json<NewToken
 
 /// Deletes a token
 #[post("/delete")]
-pub async fn delete(user: Identity, pool: web::Data<Pool>, token: web::Json<TokenInfo>) -> impl Responder {
+pub async fn delete(user: Credentials, pool: web::Data<Pool>, token: web::Json<TokenInfo>) -> impl Responder {
     if config!(registration).is_some() {
         let pool = pool.into_inner();
         let token = token.into_inner();
@@ -72,7 +64,7 @@ pub async fn delete(user: Identity, pool: web::Data<Pool>, token: web::Json<Toke
 
 /// Returns a list of every token with their expire dates
 #[get("/list")]
-pub async fn list(user: Identity, pool: web::Data<Pool>) -> impl Responder {
+pub async fn list(user: Credentials, pool: web::Data<Pool>) -> impl Responder {
     if config!(registration).is_some() {
         let pool = pool.into_inner();
         if let Err(e) = is_admin(&pool, user).await {
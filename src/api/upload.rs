@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::plugins::get_user;
+use crate::{
+    auth::Credentials,
+    plugins::Plugins,
+    quota,
+    upload::{self, error::UploadError},
+};
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, head, patch, post, web};
+use async_sqlite::Pool;
+use common_library::{Json, error::ErrToResponse, serde_json::json};
+use serde::Deserialize;
+
+/// Information of a new resumable upload
+#[derive(Deserialize)]
+pub struct NewUpload {
+    /// Total size of the file in bytes
+    size: u64,
+    /// Name of the file
+    name: String,
+    /// Info handed to the plugin along with the file
+    info: Json,
+}
+
+/// Creates a new resumable upload and returns its id
+#[post("/{plugin}/new")]
+pub async fn new(
+    pool: web::Data<Pool>,
+    plugins: web::Data<Plugins>,
+    plugin: web::Path<String>,
+    user: Option<Credentials>,
+    body: web::Json<NewUpload>,
+) -> impl Responder {
+    let plugin = plugin.into_inner();
+    let body = body.into_inner();
+    let user = match get_user(&pool, user, &plugin).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+    if !plugins.allows(&plugin, &user) {
+        return HttpResponse::NotFound().body("");
+    }
+    let owner = user.as_ref().map(|u| u.name.clone());
+    let id = match upload::create(owner.clone(), plugin.clone(), body.size, body.name, body.info).await {
+        Ok(id) => id,
+        Err(e) => return e.to_response(),
+    };
+    // The whole size is reserved now, since the data of the upload is not in the user's directory yet
+    if let Err(e) = quota::reserve(&pool, &user, &id, body.size).await {
+        if let Err(err) = upload::cancel(&id, &owner, &plugin).await {
+            err.handle();
+        }
+        return e.to_response();
+    }
+    HttpResponse::Created()
+        .content_type("application/json")
+        .insert_header(("Upload-Offset", "0"))
+        .body(json!({ "id": id }).to_string())
+}
+
+/// Returns the current offset and the total size of an upload in the `Upload-Offset` and
+/// `Upload-Length` headers
+#[head("/{plugin}/{id}")]
+pub async fn status(pool: web::Data<Pool>, path: web::Path<(String, String)>, user: Option<Credentials>) -> impl Responder {
+    let (plugin, id) = path.into_inner();
+    let owner = match get_user(&pool, user, &plugin).await {
+        Ok(user) => user.map(|u| u.name),
+        Err(e) => return e,
+    };
+    match upload::offset(&id, &owner, &plugin).await {
+        Ok((offset, size)) => HttpResponse::Ok()
+            .insert_header(("Upload-Offset", offset.to_string()))
+            .insert_header(("Upload-Length", size.to_string()))
+            .insert_header(("Cache-Control", "no-store"))
+            .finish(),
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Appends the body to an upload. The `Upload-Offset` header must contain the current offset.
+#[patch("/{plugin}/{id}")]
+pub async fn append(
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+    path: web::Path<(String, String)>,
+    user: Option<Credentials>,
+    payload: web::Payload,
+) -> impl Responder {
+    let (plugin, id) = path.into_inner();
+    let owner = match get_user(&pool, user, &plugin).await {
+        Ok(user) => user.map(|u| u.name),
+        Err(e) => return e,
+    };
+    let Some(offset) = req
+        .headers()
+        .get("Upload-Offset")
+        .and_then(|o| o.to_str().ok())
+        .and_then(|o| o.parse::<u64>().ok())
+    else {
+        return UploadError::BadRequest("Missing or invalid `Upload-Offset` header".into()).to_response();
+    };
+    match upload::append(&id, &owner, &plugin, offset, payload).await {
+        Ok(offset) => HttpResponse::NoContent()
+            .insert_header(("Upload-Offset", offset.to_string()))
+            .finish(),
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Completes an upload and hands the file to the plugin if it still fits in the user's quota
+#[post("/{plugin}/{id}/finish")]
+pub async fn finish(
+    pool: web::Data<Pool>,
+    plugins: web::Data<Plugins>,
+    path: web::Path<(String, String)>,
+    user: Option<Credentials>,
+) -> impl Responder {
+    let (plugin, id) = path.into_inner();
+    let user = match get_user(&pool, user, &plugin).await {
+        Ok(user) => user,
+        Err(e) => return e,
+    };
+    let owner = user.as_ref().map(|u| u.name.clone());
+    match upload::finish(&id, &owner, &plugin).await {
+        Ok((file, info)) => match quota::finish(&pool, &user, &id, file.size as u64).await {
+            Ok(()) => plugins.file(plugin, user, file, info).await,
+            Err(e) => e.to_response(),
+        },
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Cancels an upload and deletes what was uploaded so far
+#[delete("/{plugin}/{id}")]
+pub async fn cancel(pool: web::Data<Pool>, path: web::Path<(String, String)>, user: Option<Credentials>) -> impl Responder {
+    let (plugin, id) = path.into_inner();
+    let owner = match get_user(&pool, user, &plugin).await {
+        Ok(user) => user.map(|u| u.name),
+        Err(e) => return e,
+    };
+    match upload::cancel(&id, &owner, &plugin).await {
+        Ok(()) => HttpResponse::Ok().body(""),
+        Err(e) => e.to_response(),
+    }
+}
@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::{
+    auth::{self, throttle, webauthn},
+    utils::{get_ip, sanitize_user},
+};
+use actix_identity::Identity;
+use actix_web::{HttpRequest, HttpResponse, Responder, dev::ConnectionInfo, get, post, web};
+use async_sqlite::Pool;
+use common_library::{
+    error::ErrToResponse,
+    serde_json::{Value, json},
+};
+use serde::Deserialize;
+use webauthn_rs::prelude::{PublicKeyCredential, RegisterPublicKeyCredential};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Payload to start adding a passkey
+#[derive(Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct RegisterStart {
+    password: String,
+    name: String,
+}
+
+/// Passkey created by the browser
+#[derive(Deserialize)]
+pub struct RegisterFinish {
+    id: String,
+    credential: RegisterPublicKeyCredential,
+}
+
+/// Payload to start a passkey login. Only the field the passkey doesn't replace is needed.
+#[derive(Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct LoginStart {
+    user: String,
+    #[serde(default)]
+    password: String,
+    #[serde(default)]
+    totp: String,
+}
+
+/// Assertion made by the browser
+#[derive(Deserialize)]
+pub struct LoginFinish {
+    id: String,
+    credential: PublicKeyCredential,
+}
+
+/// Passkey selected by the user
+#[derive(Deserialize)]
+pub struct Target {
+    id: String,
+}
+
+/// Starts adding a passkey and returns the ceremony id with the options for the browser
+#[post("/register/start")]
+pub async fn register_start(user: Identity, pool: web::Data<Pool>, payload: web::Json<RegisterStart>) -> impl Responder {
+    let pool = pool.into_inner();
+    let payload = payload.into_inner();
+    match webauthn::start_registration(&pool, user, payload.password.as_bytes(), payload.name.clone()).await {
+        Ok((id, options)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!({ "id": id, "options": options }).to_string()),
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Saves the passkey created by the browser
+#[post("/register/finish")]
+pub async fn register_finish(user: Identity, pool: web::Data<Pool>, payload: web::Json<RegisterFinish>) -> impl Responder {
+    let pool = pool.into_inner();
+    let payload = payload.into_inner();
+    match webauthn::finish_registration(&pool, user, &payload.id, payload.credential).await {
+        Ok(()) => HttpResponse::Ok().body(""),
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Starts a passkey login and returns the ceremony id with the options for the browser
+#[post("/login/start")]
+pub async fn login_start(conn: ConnectionInfo, pool: web::Data<Pool>, payload: web::Json<LoginStart>) -> impl Responder {
+    let pool = pool.into_inner();
+    let payload = payload.into_inner();
+    let ip = get_ip(&conn);
+    let attempt = match throttle::check(ip, &payload.user) {
+        Ok(attempt) => attempt,
+        Err(err) => {
+            log::warn!(
+                "client [{ip}] tried to login as `{}` while locked out",
+                sanitize_user(&payload.user)
+            );
+            return err.to_response();
+        }
+    };
+    match webauthn::start_login(&pool, attempt, payload.user.clone(), payload.password.as_bytes(), &payload.totp).await {
+        Ok((id, options)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json!({ "id": id, "options": options }).to_string()),
+        Err(e) => {
+            log::warn!("client [{ip}] failed to start a passkey login");
+            e.to_response()
+        }
+    }
+}
+
+/// Verifies the assertion of the browser and starts a new session
+#[post("/login/finish")]
+pub async fn login_finish(
+    req: HttpRequest,
+    conn: ConnectionInfo,
+    pool: web::Data<Pool>,
+    payload: web::Json<LoginFinish>,
+) -> impl Responder {
+    let pool = pool.into_inner();
+    let payload = payload.into_inner();
+    let ip = get_ip(&conn);
+    match webauthn::finish_login(&pool, ip, &payload.id, payload.credential).await {
+        Ok(username) => {
+            log::warn!("client [{ip}] logged in with a passkey as `{}`", sanitize_user(&username));
+            if let Err(err) = auth::start_session(&pool, &req, ip, username).await {
+                return err.to_response();
+            }
+            HttpResponse::Ok().body("")
+        }
+        Err(e) => {
+            log::warn!("client [{ip}] failed a passkey login");
+            e.to_response()
+        }
+    }
+}
+
+/// Returns the passkeys of the user
+#[get("/list")]
+pub async fn list(user: Identity, pool: web::Data<Pool>) -> impl Responder {
+    let pool = pool.into_inner();
+    match webauthn::list(&pool, user).await {
+        Ok(passkeys) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(Value::Array(passkeys).to_string()),
+        Err(e) => e.to_response(),
+    }
+}
+
+/// Deletes a passkey of the user
+#[post("/delete")]
+pub async fn delete(user: Identity, pool: web::Data<Pool>, target: web::Json<Target>) -> impl Responder {
+    let pool = pool.into_inner();
+    match webauthn::delete(&pool, user, target.into_inner().id).await {
+        Ok(()) => HttpResponse::Ok().body(""),
+        Err(e) => e.to_response(),
+    }
+}
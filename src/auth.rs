@@ -1,20 +1,37 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+pub mod api_token;
 pub mod cli;
 pub mod error;
 mod hash;
-mod totp;
+pub mod store;
+pub mod throttle;
+pub mod totp;
+#[cfg(feature = "webauthn")]
+pub mod webauthn;
 
 use crate::api::auth::Login;
 use crate::config;
-use crate::database::auth;
+#[cfg(not(feature = "no-tls"))]
+use crate::config::CertificateName;
+use crate::config::PasskeyMode;
+use crate::database::auth::{self, UserAuth};
+use crate::database::session::{self, Session};
+#[cfg(not(feature = "no-tls"))]
+use crate::tls::PeerCertificate;
 use crate::token;
 use actix_identity::Identity;
 use actix_identity::error::GetIdentityError;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, dev::Payload, http::header};
+use api_token::Scope;
 use async_sqlite::Pool;
 use common_library::error::ErrToResponse;
 use error::AuthError;
-use totp_rs::TOTP;
+use std::future::{Ready, ready};
+use totp::NewTotp;
+
+/// Maximum number of characters of a user agent saved along with its session
+const MAX_USER_AGENT_SIZE: usize = 256;
 
 fn check_validity(username: &str, password: &[u8]) -> Result<(), AuthError> {
     let user_len = username.len();
@@ -41,57 +58,122 @@ fn check_validity(username: &str, password: &[u8]) -> Result<(), AuthError> {
     Ok(())
 }
 
-/// Checks a user's password and validates the TOTP token.
-/// Returns user's username on success.
-pub async fn check(pool: &Pool, login: Login) -> Result<String, AuthError> {
-    check_validity(&login.user, login.password.as_bytes())?;
-    let dummy_hash = hash::create(login.password.as_bytes()).await?;
-    match auth::get_auth(pool, login.user.clone()).await.map_err(|e| e.into())? {
+/// Validates a TOTP token, rejecting already used ones, or consumes a recovery code.
+async fn check_second_factor(pool: &Pool, username: String, totp: String, token: &str) -> Result<(), AuthError> {
+    if let Some(hash) = self::totp::recovery_hash(token) {
+        if !auth::use_recovery_code(pool, username.clone(), hash).await.map_err(|e| e.into())? {
+            return Err(AuthError::InvalidTOTP);
+        }
+        log::warn!("user `{username}` used a recovery code to login");
+        return Ok(());
+    }
+    let step = self::totp::check(totp, token)?;
+    if auth::use_totp_step(pool, username, step).await.map_err(|e| e.into())? {
+        Ok(())
+    } else {
+        Err(AuthError::InvalidTOTP)
+    }
+}
+
+/// Returns what passkeys replace when logging in, none if they cannot be used
+pub fn passkey_mode() -> Option<PasskeyMode> {
+    if cfg!(feature = "webauthn") {
+        config!(webauthn).as_ref().map(|w| w.replaces)
+    } else {
+        None
+    }
+}
+
+/// Generates new recovery codes for the user, replacing the old ones, and returns them
+pub async fn new_recovery_codes(pool: &Pool, username: String) -> Result<Vec<String>, AuthError> {
+    let (codes, hashes) = self::totp::generate_recovery();
+    auth::set_recovery_codes(pool, username, hashes).await.map_err(|e| e.into())?;
+    Ok(codes)
+}
+
+/// Checks a user's password and returns its authentication data.
+async fn check_password(pool: &Pool, username: &str, password: &[u8]) -> Result<UserAuth, AuthError> {
+    check_validity(username, password)?;
+    let dummy_hash = hash::create(password).await?;
+    match auth::get_auth(pool, username.into()).await.map_err(|e| e.into())? {
         Some(user) => {
-            hash::verify(login.password.as_bytes(), user.pass_hash).await?;
-            self::totp::check(user.totp, &login.totp)?;
-            Ok(user.userid)
+            hash::verify(password, user.pass_hash.clone()).await?;
+            Ok(user)
         }
         None => {
             // Dummy verification to keep the same response timings when the user is not found.
             // Keeps malicious attackers from scanning the server for usernames
-            let _ = hash::verify(login.password.as_bytes(), dummy_hash).await;
+            let _ = hash::verify(password, dummy_hash).await;
             Err(AuthError::InvalidCredentials)
         }
     }
 }
 
-/// Adds a new user and returns its TOTP. Fails if username already exists.
+/// Checks a user's password and validates the TOTP token or recovery code.
+/// Returns user's username on success.
+pub async fn check(pool: &Pool, login: Login) -> Result<String, AuthError> {
+    let user = check_password(pool, &login.user, login.password.as_bytes()).await?;
+    check_second_factor(pool, login.user.clone(), user.totp, &login.totp).await?;
+    Ok(user.username)
+}
+
+/// Checks that the client certificate of the connection names an existing user, without password or TOTP token.
+/// Returns user's username on success.
+#[cfg(not(feature = "no-tls"))]
+pub async fn check_certificate(pool: &Pool, cert: Option<&PeerCertificate>, name: CertificateName) -> Result<String, AuthError> {
+    let username = cert.and_then(|cert| cert.name(name)).ok_or(AuthError::InvalidCredentials)?;
+    match auth::get_auth(pool, username).await.map_err(|e| e.into())? {
+        Some(user) => Ok(user.username),
+        None => Err(AuthError::InvalidCredentials),
+    }
+}
+
+/// Starts a new session for an authenticated user, recording the IP address and user agent of the client.
+pub async fn start_session(pool: &Pool, req: &HttpRequest, ip: &str, username: String) -> Result<(), AuthError> {
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .unwrap_or("unknown")
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_USER_AGENT_SIZE)
+        .collect();
+    let userid = session::create(pool, username, ip.into(), user_agent).await.map_err(|e| e.into())?;
+    Identity::login(&req.extensions(), userid)
+        .map(|_| ())
+        .map_err(|e| AuthError::InternalError(format!("Failed to build identity: {e}")))
+}
+
+/// Adds a new user and returns its TOTP with its recovery codes. Fails if username already exists.
 /// Used when adding user manually.
-pub async fn add_user(pool: &Pool, username: String, password: &[u8], is_admin: bool) -> Result<TOTP, AuthError> {
+pub async fn add_user(pool: &Pool, username: String, password: &[u8], is_admin: bool) -> Result<NewTotp, AuthError> {
     check_validity(&username, password)?;
     let passwd_hash = hash::create(password).await?;
     let totp = self::totp::generate(username.clone())?;
-    auth::add_user(pool, username, passwd_hash, totp.get_url(), is_admin)
+    auth::add_user(pool, username.clone(), passwd_hash, totp.get_url(), is_admin)
         .await
         .map_err(|e| e.into())?;
-    Ok(totp)
+    let recovery_codes = new_recovery_codes(pool, username).await?;
+    Ok(NewTotp { totp, recovery_codes })
 }
 
-/// Registers a new user with a token and returns its TOTP, and returns the userid used during the
-/// session.
+/// Registers a new user with a token and returns its TOTP with its recovery codes.
 /// Fails if username already exists or if token is not valid
-pub async fn register_user(
-    pool: &Pool,
-    username: String,
-    password: &[u8],
-    token: String,
-) -> Result<(TOTP, String), Box<dyn ErrToResponse>> {
+pub async fn register_user(pool: &Pool, username: String, password: &[u8], token: String) -> Result<NewTotp, Box<dyn ErrToResponse>> {
     check_validity(&username, password).map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
     token::check_token(pool, token)
         .await
         .map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
     let passwd_hash = hash::create(password).await.map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
     let totp = self::totp::generate(username.clone()).map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
-    let userid = auth::add_user(pool, username, passwd_hash, totp.get_url(), false)
+    auth::add_user(pool, username.clone(), passwd_hash, totp.get_url(), false)
         .await
         .map_err(|e| Box::new(Into::<AuthError>::into(e)) as Box<dyn ErrToResponse>)?;
-    Ok((totp, userid))
+    let recovery_codes = new_recovery_codes(pool, username)
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn ErrToResponse>)?;
+    Ok(NewTotp { totp, recovery_codes })
 }
 
 /// Unwraps id and returns its string or its error as a response
@@ -126,16 +208,82 @@ pub async fn validate_user(pool: &Pool, user: Identity) -> Result<(String, bool)
         .inspect_err(|_| user.logout())
 }
 
-/// Changes user's sessionid. Logs out on success.
-pub async fn change_sessionid(pool: &Pool, user: Identity) -> Result<(), AuthError> {
-    auth::change_sessionid(pool, user.id().map_err(|e| id_err_into(e))?)
+/// Credentials of a request: the API token sent as `Authorization: Bearer`, or else the identity of its session.
+/// Extracting it fails like [`Identity`] when there is neither.
+pub enum Credentials {
+    Session(Identity),
+    ApiToken(String),
+}
+
+impl FromRequest for Credentials {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim().to_string());
+        match bearer {
+            Some(token) if config!(api_tokens).is_some() => ready(Ok(Self::ApiToken(token))),
+            _ => ready(Identity::from_request(req, payload).into_inner().map(Self::Session)),
+        }
+    }
+}
+
+/// Checks the credentials of a request like [`validate_user`] for a session,
+/// or checks that the API token is valid and allows `scope`.
+/// Returns the user's username and admin status.
+pub async fn validate(pool: &Pool, credentials: Credentials, scope: Scope<'_>) -> Result<(String, bool), AuthError> {
+    match credentials {
+        Credentials::Session(user) => validate_user(pool, user).await,
+        Credentials::ApiToken(token) => api_token::check(pool, &token, scope).await,
+    }
+}
+
+/// Ends the current session and logs out.
+pub async fn logout(pool: &Pool, user: Identity) -> Result<(), AuthError> {
+    let (username, sessionid) = auth::unpack(user.id().map_err(id_err_into)?).map_err(|e| e.into())?;
+    user.logout();
+    session::delete_current(pool, username, sessionid).await.map_err(|e| e.into())
+}
+
+/// Ends all the sessions of the user. Logs out on success.
+pub async fn logout_all(pool: &Pool, user: Identity) -> Result<(), AuthError> {
+    let (username, sessionid) = auth::unpack(user.id().map_err(id_err_into)?).map_err(|e| e.into())?;
+    if !session::touch(pool, username.clone(), sessionid).await.map_err(|e| e.into())? {
+        user.logout();
+        return Err(AuthError::InvalidSession);
+    }
+    session::delete_all(pool, username)
         .await
         .map_err(|e| e.into())
         .inspect(|_| user.logout())
 }
 
+/// Returns the sessions of the user along with the id of the current one.
+pub async fn sessions(pool: &Pool, user: Identity) -> Result<(Vec<Session>, Option<i64>), AuthError> {
+    let (_, sessionid) = auth::unpack(user.id().map_err(id_err_into)?).map_err(|e| e.into())?;
+    let (username, _) = validate_user(pool, user).await?;
+    session::list(pool, username, Some(sessionid)).await.map_err(|e| e.into())
+}
+
+/// Ends a session of the user, which may be the current one.
+pub async fn revoke_session(pool: &Pool, user: Identity, id: i64) -> Result<(), AuthError> {
+    let (username, _) = validate_user(pool, user).await?;
+    if session::delete(pool, username.clone(), id).await.map_err(|e| e.into())? {
+        log::warn!("user `{username}` revoked one of its sessions");
+        Ok(())
+    } else {
+        Err(AuthError::SessionNotFound)
+    }
+}
+
 /// Changes user's password.
-/// After the password has been changed it changes sessionid to log out older sessions.
+/// After the password has been changed it ends all the sessions of the user.
 /// If the session is not correct it logs out.
 pub async fn change_pwd(pool: &Pool, user: Identity, new_pwd: &[u8], old_pwd: &[u8]) -> Result<(), AuthError> {
     let (username, sessionid) = auth::unpack(user.id().map_err(|e| id_err_into(e))?).map_err(|e| e.into())?;
@@ -144,7 +292,7 @@ pub async fn change_pwd(pool: &Pool, user: Identity, new_pwd: &[u8], old_pwd: &[
             hash::verify(old_pwd, pass_hash).await?;
             let new_pwd = hash::create(new_pwd).await?;
             auth::change_passhash(pool, username, new_pwd).await.map_err(|e| e.into())?;
-            change_sessionid(pool, user).await
+            logout_all(pool, user).await
         }
         None => {
             user.logout();
@@ -169,16 +317,22 @@ pub async fn change_pwd_token(pool: &Pool, user: Identity, new_pwd: &[u8], token
     Ok(())
 }
 
-/// Regenerates TOTP secret, logs out from all sessions and returns the new secret to be sent to the client.
-pub async fn change_totp(pool: &Pool, user: Identity, pwd: &[u8]) -> Result<TOTP, AuthError> {
+/// Regenerates TOTP secret and recovery codes, logs out from all sessions and returns them to be sent to the client.
+pub async fn change_totp(pool: &Pool, user: Identity, pwd: &[u8]) -> Result<NewTotp, AuthError> {
     let (username, sessionid) = auth::unpack(user.id().map_err(|e| id_err_into(e))?).map_err(|e| e.into())?;
     match auth::get_passhash(pool, username.clone(), sessionid).await.map_err(|e| e.into())? {
         Some(pass_hash) => {
             hash::verify(pwd, pass_hash).await?;
             let new_totp = totp::generate(username.clone())?;
-            auth::change_totp(pool, username, new_totp.get_url()).await.map_err(|e| e.into())?;
-            change_sessionid(pool, user).await?;
-            Ok(new_totp)
+            auth::change_totp(pool, username.clone(), new_totp.get_url())
+                .await
+                .map_err(|e| e.into())?;
+            let recovery_codes = new_recovery_codes(pool, username).await?;
+            logout_all(pool, user).await?;
+            Ok(NewTotp {
+                totp: new_totp,
+                recovery_codes,
+            })
         }
         None => {
             user.logout();
@@ -186,3 +340,44 @@ pub async fn change_totp(pool: &Pool, user: Identity, pwd: &[u8]) -> Result<TOTP
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+
+    #[actix_web::test]
+    async fn totp_tokens_are_not_replayed() {
+        let pool = database::memory().await;
+        let totp = totp::generate("replay".into()).unwrap();
+        auth::add_user(&pool, "replay".into(), String::new(), totp.get_url(), false)
+            .await
+            .unwrap();
+        let token = totp.generate_current().unwrap();
+        check_second_factor(&pool, "replay".into(), totp.get_url(), &token).await.unwrap();
+        let replayed = check_second_factor(&pool, "replay".into(), totp.get_url(), &token).await;
+        assert!(matches!(replayed, Err(AuthError::InvalidTOTP)));
+    }
+
+    #[actix_web::test]
+    async fn recovery_codes_are_used_once() {
+        let pool = database::memory().await;
+        let totp = totp::generate("recovery".into()).unwrap();
+        auth::add_user(&pool, "recovery".into(), String::new(), totp.get_url(), false)
+            .await
+            .unwrap();
+        let codes = new_recovery_codes(&pool, "recovery".into()).await.unwrap();
+        check_second_factor(&pool, "recovery".into(), totp.get_url(), &codes[0].to_uppercase())
+            .await
+            .unwrap();
+        let reused = check_second_factor(&pool, "recovery".into(), totp.get_url(), &codes[0]).await;
+        assert!(matches!(reused, Err(AuthError::InvalidTOTP)));
+        check_second_factor(&pool, "recovery".into(), totp.get_url(), &codes[1])
+            .await
+            .unwrap();
+        // New codes replace the old ones
+        new_recovery_codes(&pool, "recovery".into()).await.unwrap();
+        let replaced = check_second_factor(&pool, "recovery".into(), totp.get_url(), &codes[2]).await;
+        assert!(matches!(replaced, Err(AuthError::InvalidTOTP)));
+    }
+}
@@ -0,0 +1,215 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::{error::AuthError, hash, id_err_into, validate_user};
+use crate::config;
+use crate::database::api_token::{self as db, ApiToken};
+use crate::database::{auth, utils::calc_expire};
+use crate::plugins;
+use actix_identity::Identity;
+use async_sqlite::Pool;
+use rand::{Rng, distr::Alphanumeric};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+/// Prefix of every token, which makes them easy to spot in scripts and logs
+const TOKEN_PREFIX: &str = "tc_";
+/// Length of a token in characters, without its prefix
+const TOKEN_SIZE: usize = 40;
+/// Maximum number of characters of a token name
+const MAX_NAME_SIZE: usize = 64;
+
+/// What a request made with a token needs to be allowed
+#[derive(Clone, Copy)]
+pub enum Scope<'a> {
+    /// Requests, uploads and downloads of a plugin
+    Plugin(&'a str),
+    /// Storage used by the user
+    Quota,
+    /// Admin APIs, only if the user is still an admin
+    Admin,
+}
+
+impl Scope<'_> {
+    /// Name of the scope as given when creating a token
+    fn name(&self) -> String {
+        match self {
+            Self::Plugin(plugin) => format!("plugin:{plugin}"),
+            Self::Quota => "quota".into(),
+            Self::Admin => "admin".into(),
+        }
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Checks that a scope given when creating a token exists and can be given by the user
+fn check_scope(scope: &str, is_admin: bool) -> Result<(), AuthError> {
+    let valid = match scope.strip_prefix("plugin:") {
+        Some(plugin) => plugins::list().iter().any(|p| p.name == plugin),
+        None => scope == Scope::Quota.name() || (scope == Scope::Admin.name() && is_admin),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(AuthError::BadCredentials(format!(
+            "Scope `{scope}` does not exist or can't be given"
+        )))
+    }
+}
+
+/// Checks a token sent as `Authorization: Bearer` and whether it allows `scope`.
+/// Returns the username and admin status of its user on success.
+pub async fn check(pool: &Pool, token: &str, scope: Scope<'_>) -> Result<(String, bool), AuthError> {
+    let owner = db::check(pool, hash_token(token))
+        .await
+        .map_err(|e| e.into())?
+        .ok_or(AuthError::InvalidCredentials)?;
+    let scope = scope.name();
+    if owner.scopes.split(' ').any(|s| s == scope) {
+        Ok((owner.username, owner.is_admin))
+    } else {
+        Err(AuthError::OutOfScope)
+    }
+}
+
+/// Creates a token for the user after checking its password, and returns it.
+/// Only its hash is stored, so it can't be shown again.
+pub async fn create(
+    pool: &Pool,
+    user: Identity,
+    password: &[u8],
+    name: String,
+    scopes: Vec<String>,
+    days: u32,
+) -> Result<String, AuthError> {
+    let max_days = config!(api_tokens).as_ref().map_or(0, |t| t.max_days);
+    let name: String = name.trim().chars().filter(|c| !c.is_control()).collect();
+    if name.is_empty() || name.chars().count() > MAX_NAME_SIZE {
+        return Err(AuthError::BadCredentials(format!(
+            "Token name must be between 1 and {MAX_NAME_SIZE} characters"
+        )));
+    }
+    if days == 0 || days > max_days {
+        return Err(AuthError::BadCredentials(format!(
+            "Token lifetime must be between 1 and {max_days} days"
+        )));
+    }
+    if scopes.is_empty() {
+        return Err(AuthError::BadCredentials("Token must have at least one scope".into()));
+    }
+    let (_, sessionid) = auth::unpack(user.id().map_err(id_err_into)?).map_err(|e| e.into())?;
+    let (username, is_admin) = validate_user(pool, user).await?;
+    for scope in &scopes {
+        check_scope(scope, is_admin)?;
+    }
+    let pass_hash = auth::get_passhash(pool, username.clone(), sessionid)
+        .await
+        .map_err(|e| e.into())?
+        .ok_or(AuthError::InvalidSession)?;
+    hash::verify(password, pass_hash).await?;
+
+    let token: String = rand::rng().sample_iter(&Alphanumeric).take(TOKEN_SIZE).map(char::from).collect();
+    let token = format!("{TOKEN_PREFIX}{token}");
+    let expires = calc_expire(Duration::from_secs(u64::from(days) * 24 * 60 * 60)).map_err(|e| e.into())?;
+    db::create(pool, username.clone(), name, hash_token(&token), scopes.join(" "), expires)
+        .await
+        .map_err(|e| e.into())?;
+    log::warn!("user `{username}` created an API token");
+    Ok(token)
+}
+
+/// Returns the tokens of the user
+pub async fn list(pool: &Pool, user: Identity) -> Result<Vec<ApiToken>, AuthError> {
+    let (username, _) = validate_user(pool, user).await?;
+    db::list(pool, username).await.map_err(|e| e.into())
+}
+
+/// Deletes a token of the user, requests made with it are refused right away
+pub async fn revoke(pool: &Pool, user: Identity, id: i64) -> Result<(), AuthError> {
+    let (username, _) = validate_user(pool, user).await?;
+    if db::delete(pool, username.clone(), id).await.map_err(|e| e.into())? {
+        log::warn!("user `{username}` revoked an API token");
+        Ok(())
+    } else {
+        Err(AuthError::ApiTokenNotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{memory, utils::now};
+
+    async fn token(pool: &Pool, username: &str, scopes: &str, expires: u64) -> String {
+        let token = format!("{TOKEN_PREFIX}{username}{scopes}{expires}");
+        db::create(pool, username.into(), "script".into(), hash_token(&token), scopes.into(), expires)
+            .await
+            .unwrap();
+        token
+    }
+
+    #[test]
+    fn scopes_must_exist_and_be_allowed() {
+        plugins::use_none();
+        assert!(check_scope("quota", false).is_ok());
+        assert!(check_scope("admin", true).is_ok());
+        assert!(check_scope("admin", false).is_err());
+        assert!(check_scope("plugin:missing", true).is_err());
+        assert!(check_scope("plugin:", true).is_err());
+        assert!(check_scope("everything", true).is_err());
+    }
+
+    #[actix_web::test]
+    async fn tokens_allow_their_scopes_only() {
+        let pool = memory().await;
+        auth::add_user(&pool, "alice".into(), String::new(), String::new(), false)
+            .await
+            .unwrap();
+        let token = token(&pool, "alice", "quota plugin:files", now().unwrap() + 60).await;
+
+        assert_eq!(check(&pool, &token, Scope::Quota).await.unwrap(), ("alice".into(), false));
+        assert!(check(&pool, &token, Scope::Plugin("files")).await.is_ok());
+        assert!(matches!(
+            check(&pool, &token, Scope::Plugin("file")).await,
+            Err(AuthError::OutOfScope)
+        ));
+        assert!(matches!(check(&pool, &token, Scope::Admin).await, Err(AuthError::OutOfScope)));
+        assert!(matches!(
+            check(&pool, "tc_unknown", Scope::Quota).await,
+            Err(AuthError::InvalidCredentials)
+        ));
+    }
+
+    #[actix_web::test]
+    async fn admin_scope_follows_the_user() {
+        let pool = memory().await;
+        auth::add_user(&pool, "alice".into(), String::new(), String::new(), true)
+            .await
+            .unwrap();
+        let token = token(&pool, "alice", "admin", now().unwrap() + 60).await;
+        assert_eq!(check(&pool, &token, Scope::Admin).await.unwrap(), ("alice".into(), true));
+
+        auth::set_admin(&pool, "alice".into(), false).await.unwrap();
+        assert_eq!(check(&pool, &token, Scope::Admin).await.unwrap(), ("alice".into(), false));
+        auth::delete_user_by_name(&pool, "alice".into()).await.unwrap();
+        assert!(matches!(
+            check(&pool, &token, Scope::Admin).await,
+            Err(AuthError::InvalidCredentials)
+        ));
+    }
+
+    #[actix_web::test]
+    async fn expired_tokens_are_refused() {
+        let pool = memory().await;
+        auth::add_user(&pool, "alice".into(), String::new(), String::new(), false)
+            .await
+            .unwrap();
+        let token = token(&pool, "alice", "quota", now().unwrap()).await;
+        assert!(matches!(
+            check(&pool, &token, Scope::Quota).await,
+            Err(AuthError::InvalidCredentials)
+        ));
+    }
+}
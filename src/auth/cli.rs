@@ -42,7 +42,7 @@ pub async fn create_user(pool: &Pool) -> Result<(), String> {
     let is_admin = input.trim().to_string().to_lowercase() == "y";
 
     // Add user to DB
-    let totp = add_user(&pool, user.clone(), &password, is_admin).await.map_err(|e| match e {
+    let new_totp = add_user(&pool, user.clone(), &password, is_admin).await.map_err(|e| match e {
         AuthError::InvalidRegCredentials => format!("{e}"),
         AuthError::InternalError(ref err) => format!("{e}: {err}"),
         _ => e.to_string(),
@@ -59,6 +59,7 @@ pub async fn create_user(pool: &Pool) -> Result<(), String> {
     input.clear();
     io::stdin().read_line(&mut input).expect("Failed to read TOTP request");
     let path = input.trim().to_string();
+    let totp = new_totp.totp;
     if path.is_empty() {
         println!("{}", totp.get_url());
     } else {
@@ -75,5 +76,10 @@ pub async fn create_user(pool: &Pool) -> Result<(), String> {
         println!("QR code image written.");
     }
 
+    println!("Recovery codes, each of them can be used once in place of a TOTP token:");
+    for code in new_totp.recovery_codes {
+        println!("{code}");
+    }
+
     Ok(())
 }
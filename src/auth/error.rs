@@ -18,6 +18,14 @@ pub enum AuthError {
     InvalidSession,
     #[error("Invalid TOTP token")]
     InvalidTOTP,
+    #[error("Too many failed attempts, retry in {0} seconds")]
+    TooManyAttempts(u64),
+    #[error("Session was not found")]
+    SessionNotFound,
+    #[error("The API token does not allow this request")]
+    OutOfScope,
+    #[error("API token was not found")]
+    ApiTokenNotFound,
 }
 
 impl ErrToResponse for AuthError {
@@ -33,6 +41,10 @@ impl ErrToResponse for AuthError {
             Self::InvalidSession => stringify!(InvalidSession),
             Self::InternalError(_) => stringify!(InternalError),
             Self::InvalidTOTP => stringify!(InvalidTOTP),
+            Self::TooManyAttempts(_) => stringify!(TooManyAttempts),
+            Self::SessionNotFound => stringify!(SessionNotFound),
+            Self::OutOfScope => stringify!(OutOfScope),
+            Self::ApiTokenNotFound => stringify!(ApiTokenNotFound),
         }
     }
 
@@ -48,6 +60,14 @@ impl ErrToResponse for AuthError {
             Self::InvalidSession => HttpResponse::Unauthorized(),
             Self::InvalidTOTP => HttpResponse::Unauthorized(),
             Self::InternalError(_) => HttpResponse::InternalServerError(),
+            Self::TooManyAttempts(secs) => {
+                let mut res = HttpResponse::TooManyRequests();
+                res.insert_header(("Retry-After", secs.to_string()));
+                res
+            }
+            Self::SessionNotFound => HttpResponse::NotFound(),
+            Self::OutOfScope => HttpResponse::Forbidden(),
+            Self::ApiTokenNotFound => HttpResponse::NotFound(),
         }
     }
 
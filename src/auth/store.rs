@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::config;
+use crate::database::{auth, session};
+use actix_session::storage::{CookieSessionStore, LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use async_sqlite::Pool;
+use common_library::serde_json;
+use rand::{Rng, distr::Alphanumeric};
+use std::collections::HashMap;
+
+/// Key under which actix-identity keeps the userid in the session state
+const ID_KEY: &str = "actix_identity.user_id";
+const SESSION_KEY_SIZE: usize = 64;
+
+/// Backend of the session middleware, the database is used when `session_store` is set in the config.
+pub enum Store {
+    Cookie(CookieSessionStore),
+    Database(Pool),
+}
+
+impl Store {
+    pub fn new(pool: Pool) -> Self {
+        if config!(session_store).is_some() {
+            Self::Database(pool)
+        } else {
+            Self::Cookie(CookieSessionStore::default())
+        }
+    }
+}
+
+/// Username and session id of the identity held by a session, if it is logged in
+fn owner(state: &HashMap<String, String>) -> Option<(String, i64)> {
+    let userid: String = serde_json::from_str(state.get(ID_KEY)?).ok()?;
+    auth::unpack(userid).ok()
+}
+
+fn expiry(ttl: &Duration) -> Result<u64, anyhow::Error> {
+    Ok(crate::database::utils::now()? + ttl.whole_seconds().max(0) as u64)
+}
+
+fn new_key() -> Result<SessionKey, anyhow::Error> {
+    let key: String = rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(SESSION_KEY_SIZE)
+        .map(char::from)
+        .collect();
+    Ok(SessionKey::try_from(key)?)
+}
+
+async fn save(
+    pool: &Pool,
+    key: &SessionKey,
+    state: &HashMap<String, String>,
+    ttl: &Duration,
+    update: bool,
+) -> Result<bool, anyhow::Error> {
+    let serialized = serde_json::to_string(state)?;
+    Ok(session::save_state(pool, key.as_ref().into(), serialized, owner(state), expiry(ttl)?, update).await?)
+}
+
+impl SessionStore for Store {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<HashMap<String, String>>, LoadError> {
+        match self {
+            Self::Cookie(store) => store.load(session_key).await,
+            Self::Database(pool) => {
+                let Some(state) = session::load_state(pool, session_key.as_ref().into())
+                    .await
+                    .map_err(|e| LoadError::Other(e.into()))?
+                else {
+                    return Ok(None);
+                };
+                serde_json::from_str(&state)
+                    .map(Some)
+                    .map_err(|e| LoadError::Deserialization(e.into()))
+            }
+        }
+    }
+
+    async fn save(&self, session_state: HashMap<String, String>, ttl: &Duration) -> Result<SessionKey, SaveError> {
+        match self {
+            Self::Cookie(store) => store.save(session_state, ttl).await,
+            Self::Database(pool) => {
+                let key = new_key().map_err(SaveError::Other)?;
+                save(pool, &key, &session_state, ttl, false).await.map_err(SaveError::Other)?;
+                Ok(key)
+            }
+        }
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        match self {
+            Self::Cookie(store) => store.update(session_key, session_state, ttl).await,
+            Self::Database(pool) => {
+                if save(pool, &session_key, &session_state, ttl, true)
+                    .await
+                    .map_err(UpdateError::Other)?
+                {
+                    return Ok(session_key);
+                }
+                // The session was swept or killed in the meantime, its state is saved under a new key
+                let key = new_key().map_err(UpdateError::Other)?;
+                save(pool, &key, &session_state, ttl, false).await.map_err(UpdateError::Other)?;
+                Ok(key)
+            }
+        }
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Cookie(store) => store.update_ttl(session_key, ttl).await,
+            Self::Database(pool) => Ok(session::set_state_expiry(pool, session_key.as_ref().into(), expiry(ttl)?).await?),
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Cookie(store) => store.delete(session_key).await,
+            Self::Database(pool) => Ok(session::delete_state(pool, session_key.as_ref().into()).await?),
+        }
+    }
+}
+
+/// Periodically deletes the expired sessions of the database every `session_store.sweep_minutes`
+pub async fn sweep(pool: Pool) {
+    let Some(store) = config!(session_store) else {
+        return;
+    };
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(store.sweep_minutes.max(1) * 60));
+    loop {
+        interval.tick().await;
+        match session::sweep_states(&pool).await {
+            Ok(0) => {}
+            Ok(swept) => log::info!("Deleted {swept} expired sessions"),
+            Err(e) => log::error!("Failed to delete expired sessions: {e}"),
+        }
+    }
+}
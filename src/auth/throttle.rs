@@ -0,0 +1,267 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::error::AuthError;
+use crate::{config, config::LoginThrottle, utils::sanitize_user};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Failed login attempts of IP addresses and usernames
+static ATTEMPTS: LazyLock<Mutex<Throttle>> = LazyLock::new(|| Mutex::new(Throttle::default()));
+
+#[derive(Default)]
+struct Throttle {
+    ips: HashMap<String, Attempts>,
+    users: HashMap<String, Attempts>,
+}
+
+/// Failed attempts within the sliding window, attempts still being checked and the end of the current lockout
+#[derive(Default)]
+struct Attempts {
+    failures: VecDeque<Instant>,
+    pending: u32,
+    locked_until: Option<Instant>,
+}
+
+impl Attempts {
+    fn prune(&mut self, now: Instant, window: Duration) {
+        while self.failures.front().is_some_and(|f| now.duration_since(*f) > window) {
+            self.failures.pop_front();
+        }
+    }
+
+    fn retry_after(&self, now: Instant) -> Option<Duration> {
+        self.locked_until.filter(|until| *until > now).map(|until| until - now)
+    }
+
+    /// Whether another attempt may start, counting the ones still being checked as failures
+    fn allows(&mut self, now: Instant, limit: u32, conf: &LoginThrottle) -> bool {
+        self.prune(now, Duration::from_secs(conf.window_seconds));
+        (self.failures.len() as u32).saturating_add(self.pending) < limit
+    }
+
+    /// Records a failure and returns the new lockout if the limit was reached.
+    /// Every failure past the limit doubles the lockout.
+    fn fail(&mut self, now: Instant, limit: u32, conf: &LoginThrottle) -> Option<Duration> {
+        self.prune(now, Duration::from_secs(conf.window_seconds));
+        self.failures.push_back(now);
+        let over = (self.failures.len() as u32).checked_sub(limit)?;
+        let lockout = conf
+            .lockout_seconds
+            .saturating_mul(1u64.checked_shl(over).unwrap_or(u64::MAX))
+            .min(conf.max_lockout_seconds);
+        let lockout = Duration::from_secs(lockout);
+        self.locked_until = Some(now + lockout);
+        Some(lockout)
+    }
+
+    fn is_stale(&self, now: Instant) -> bool {
+        self.failures.is_empty() && self.pending == 0 && self.retry_after(now).is_none()
+    }
+}
+
+fn retry_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// A login attempt that was let through, counted against the limits until it ends.
+/// Dropping it without calling [`Attempt::failed`] or [`Attempt::succeeded`] forgets it.
+pub struct Attempt {
+    /// IP address and sanitized username, None if login throttling is disabled
+    target: Option<(String, String)>,
+}
+
+impl Attempt {
+    /// Records the attempt as a failure of the IP address and the username
+    pub fn failed(mut self) {
+        let (Some((ip, username)), Some(conf)) = (self.target.take(), config!(login_throttle)) else {
+            return;
+        };
+        let Ok(mut attempts) = ATTEMPTS.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        let ip_attempts = attempts.ips.entry(ip.clone()).or_default();
+        ip_attempts.pending = ip_attempts.pending.saturating_sub(1);
+        if let Some(lockout) = ip_attempts.fail(now, conf.max_ip_attempts, conf) {
+            log::warn!("client [{ip}] is locked out of login for {} seconds", lockout.as_secs());
+        }
+        let user_attempts = attempts.users.entry(username.clone()).or_default();
+        user_attempts.pending = user_attempts.pending.saturating_sub(1);
+        if let Some(lockout) = user_attempts.fail(now, conf.max_user_attempts, conf) {
+            log::warn!("user `{username}` is locked out of login for {} seconds", lockout.as_secs());
+        }
+    }
+
+    /// Forgets the failed attempts of the username after a successful login.
+    /// Failures of the IP address are kept, so one valid account cannot be used to reset them.
+    pub fn succeeded(mut self) {
+        let Some((ip, username)) = self.target.take() else {
+            return;
+        };
+        if let Ok(mut attempts) = ATTEMPTS.lock() {
+            if let Some(ip_attempts) = attempts.ips.get_mut(&ip) {
+                ip_attempts.pending = ip_attempts.pending.saturating_sub(1);
+            }
+            attempts.users.remove(&username);
+        }
+    }
+}
+
+impl Drop for Attempt {
+    fn drop(&mut self) {
+        let Some((ip, username)) = self.target.take() else {
+            return;
+        };
+        if let Ok(mut attempts) = ATTEMPTS.lock() {
+            let attempts = &mut *attempts;
+            for a in [attempts.ips.get_mut(&ip), attempts.users.get_mut(&username)].into_iter().flatten() {
+                a.pending = a.pending.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// Checks whether the IP address or the username are locked out, before spending any time on the password.
+/// Attempts that are still being checked count as failures, so that concurrent requests can't go past the limits.
+pub fn check(ip: &str, username: &str) -> Result<Attempt, AuthError> {
+    let Some(conf) = config!(login_throttle) else {
+        return Ok(Attempt { target: None });
+    };
+    let now = Instant::now();
+    let username = sanitize_user(username);
+    let mut attempts = ATTEMPTS
+        .lock()
+        .map_err(|_| AuthError::InternalError("Login attempts lock was poisoned".into()))?;
+    let attempts = &mut *attempts;
+    let retry_after = [attempts.ips.get(ip), attempts.users.get(&username)]
+        .into_iter()
+        .flatten()
+        .filter_map(|a| a.retry_after(now))
+        .max();
+    if let Some(retry_after) = retry_after {
+        return Err(AuthError::TooManyAttempts(retry_secs(retry_after)));
+    }
+    let ip_attempts = attempts.ips.entry(ip.into()).or_default();
+    let ip_allows = ip_attempts.allows(now, conf.max_ip_attempts, conf);
+    let user_attempts = attempts.users.entry(username.clone()).or_default();
+    if !ip_allows || !user_attempts.allows(now, conf.max_user_attempts, conf) {
+        // Only reached while the last allowed attempts are being checked, which takes about a second
+        return Err(AuthError::TooManyAttempts(1));
+    }
+    user_attempts.pending += 1;
+    attempts.ips.entry(ip.into()).or_default().pending += 1;
+    Ok(Attempt {
+        target: Some((ip.into(), username)),
+    })
+}
+
+/// Periodically forgets IP addresses and usernames without recent failures or lockouts
+pub async fn collect_garbage() {
+    let Some(conf) = config!(login_throttle) else {
+        return;
+    };
+    let window = Duration::from_secs(conf.window_seconds);
+    let mut interval = tokio::time::interval(window.max(Duration::from_secs(60)));
+    loop {
+        interval.tick().await;
+        let Ok(mut attempts) = ATTEMPTS.lock() else {
+            return;
+        };
+        let attempts = &mut *attempts;
+        let now = Instant::now();
+        for map in [&mut attempts.ips, &mut attempts.users] {
+            map.retain(|_, a| {
+                a.prune(now, window);
+                !a.is_stale(now)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common_library::error::ErrToResponse;
+
+    fn conf() -> LoginThrottle {
+        LoginThrottle {
+            window_seconds: 60,
+            max_ip_attempts: 10,
+            max_user_attempts: 3,
+            lockout_seconds: 10,
+            max_lockout_seconds: 25,
+        }
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_max() {
+        let conf = conf();
+        let now = Instant::now();
+        let mut attempts = Attempts::default();
+        assert_eq!(attempts.fail(now, 3, &conf), None);
+        assert_eq!(attempts.fail(now, 3, &conf), None);
+        assert_eq!(attempts.fail(now, 3, &conf), Some(Duration::from_secs(10)));
+        assert_eq!(attempts.fail(now, 3, &conf), Some(Duration::from_secs(20)));
+        assert_eq!(attempts.fail(now, 3, &conf), Some(Duration::from_secs(25)));
+        assert_eq!(attempts.retry_after(now), Some(Duration::from_secs(25)));
+        assert_eq!(attempts.retry_after(now + Duration::from_secs(25)), None);
+    }
+
+    #[test]
+    fn failures_leave_the_window() {
+        let conf = conf();
+        let start = Instant::now();
+        let mut attempts = Attempts::default();
+        attempts.fail(start, 3, &conf);
+        attempts.fail(start, 3, &conf);
+        let later = start + Duration::from_secs(61);
+        assert!(attempts.allows(later, 3, &conf));
+        assert!(attempts.failures.is_empty());
+        assert_eq!(attempts.fail(later, 3, &conf), None);
+        assert_eq!(attempts.failures.len(), 1);
+    }
+
+    #[test]
+    fn pending_attempts_count_as_failures() {
+        let conf = conf();
+        let now = Instant::now();
+        let mut attempts = Attempts::default();
+        attempts.fail(now, 3, &conf);
+        attempts.pending = 2;
+        assert!(!attempts.allows(now, 3, &conf));
+        attempts.pending = 1;
+        assert!(attempts.allows(now, 3, &conf));
+    }
+
+    #[test]
+    fn locked_out_with_retry_after() {
+        config::use_default();
+        let max = config!(login_throttle).as_ref().unwrap().max_user_attempts;
+        let lockout = config!(login_throttle).as_ref().unwrap().lockout_seconds;
+        for _ in 0..max {
+            check("192.0.2.1", "throttled").unwrap().failed();
+        }
+        let err = match check("192.0.2.2", "throttled") {
+            Err(err) => err,
+            Ok(_) => panic!("locked out user was let through"),
+        };
+        assert!(matches!(err, AuthError::TooManyAttempts(secs) if secs > 0 && secs <= lockout));
+        let res = err.to_response();
+        assert_eq!(res.status(), 429);
+        let retry_after: u64 = res.headers().get("Retry-After").unwrap().to_str().unwrap().parse().unwrap();
+        assert!(retry_after > 0 && retry_after <= lockout);
+    }
+
+    #[test]
+    fn concurrent_attempts_stop_at_the_limit() {
+        config::use_default();
+        let max = config!(login_throttle).as_ref().unwrap().max_user_attempts;
+        let attempts: Vec<Attempt> = (0..max).map(|_| check("192.0.2.3", "concurrent").unwrap()).collect();
+        assert!(matches!(check("192.0.2.3", "concurrent"), Err(AuthError::TooManyAttempts(1))));
+        drop(attempts);
+        let attempt = check("192.0.2.3", "concurrent").unwrap();
+        attempt.succeeded();
+        assert!(!ATTEMPTS.lock().unwrap().users.contains_key("concurrent"));
+    }
+}
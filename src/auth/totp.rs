@@ -1,12 +1,26 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use rand::RngCore;
+use rand::{Rng, RngCore, distr::Alphanumeric};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
 use totp_rs::{Rfc6238, TOTP};
 
 use crate::config;
 
 use super::error::AuthError;
 
+/// Number of recovery codes given to a user
+const RECOVERY_CODES: usize = 10;
+/// Length of a recovery code, without the separator
+const RECOVERY_CODE_SIZE: usize = 10;
+
+/// A new TOTP secret along with the recovery codes that can replace its tokens
+pub struct NewTotp {
+    pub totp: TOTP,
+    /// One-time recovery codes, only their hashes are stored
+    pub recovery_codes: Vec<String>,
+}
+
 pub fn generate(user: String) -> Result<TOTP, AuthError> {
     let mut secret = [0u8; 16];
     rand::rng().fill_bytes(&mut secret);
@@ -17,14 +31,91 @@ pub fn generate(user: String) -> Result<TOTP, AuthError> {
     TOTP::from_rfc6238(rfc).map_err(|e| AuthError::InternalError(format!("Failed to generate new TOTP: {e}")))
 }
 
-pub fn check(totp: String, token: &str) -> Result<(), AuthError> {
-    let totp = TOTP::from_url(totp).map_err(|e| AuthError::InternalError(format!("Invalid TOTP url was given: {e}")))?;
-    if totp
-        .check_current(&token)
+/// Checks a TOTP token and returns the time step it belongs to,
+/// which must be greater than the last accepted one to prevent replays.
+pub fn check(totp: String, token: &str) -> Result<u64, AuthError> {
+    let mut totp = TOTP::from_url(totp).map_err(|e| AuthError::InternalError(format!("Invalid TOTP url was given: {e}")))?;
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
         .map_err(|e| AuthError::InternalError(format!("Time error: {e}")))?
-    {
-        Ok(())
+        .as_secs();
+    let current = time / totp.step;
+    let skew = totp.skew as u64;
+    // Every step is checked on its own to know which one the token belongs to
+    totp.skew = 0;
+    (current.saturating_sub(skew)..=current + skew)
+        .find(|step| totp.check(token, step * totp.step))
+        .ok_or(AuthError::InvalidTOTP)
+}
+
+fn hash_recovery(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
+/// Generates new recovery codes, returns them formatted as "xxxxx-xxxxx" along with their hashes
+pub fn generate_recovery() -> (Vec<String>, Vec<String>) {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let code: String = rand::rng()
+                .sample_iter(&Alphanumeric)
+                .take(RECOVERY_CODE_SIZE)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect();
+            let hash = hash_recovery(&code);
+            let (first, second) = code.split_at(RECOVERY_CODE_SIZE / 2);
+            (format!("{first}-{second}"), hash)
+        })
+        .unzip()
+}
+
+/// Returns the hash of the token if it is shaped like a recovery code
+pub fn recovery_hash(token: &str) -> Option<String> {
+    let code: String = token
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    if code.len() == RECOVERY_CODE_SIZE && code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some(hash_recovery(&code))
     } else {
-        Err(AuthError::InvalidTOTP)
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_codes_match_their_hashes() {
+        let (codes, hashes) = generate_recovery();
+        assert_eq!(codes.len(), RECOVERY_CODES);
+        for (code, hash) in codes.iter().zip(&hashes) {
+            assert_eq!(code.len(), RECOVERY_CODE_SIZE + 1);
+            assert_eq!(code.chars().nth(RECOVERY_CODE_SIZE / 2), Some('-'));
+            assert_eq!(recovery_hash(code).as_ref(), Some(hash));
+        }
+    }
+
+    #[test]
+    fn recovery_codes_are_parsed_loosely() {
+        let hash = recovery_hash("abcde-12345").unwrap();
+        assert_eq!(recovery_hash("ABCDE12345"), Some(hash.clone()));
+        assert_eq!(recovery_hash(" abcde 12345 "), Some(hash));
+        assert_eq!(recovery_hash("123456"), None);
+        assert_eq!(recovery_hash("abcde-1234"), None);
+        assert_eq!(recovery_hash("abcde-123456"), None);
+        assert_eq!(recovery_hash("abcde_12345"), None);
+    }
+
+    #[test]
+    fn tokens_belong_to_their_step() {
+        config::use_default();
+        let totp = generate("user".into()).unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let step = check(totp.get_url(), &totp.generate(now)).unwrap();
+        assert!(step.abs_diff(now / totp.step) <= 1);
+        let old = totp.generate(now - 10 * totp.step);
+        assert!(matches!(check(totp.get_url(), &old), Err(AuthError::InvalidTOTP)));
     }
 }
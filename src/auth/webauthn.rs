@@ -0,0 +1,498 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+pub mod error;
+use super::{check_password, check_second_factor, error::AuthError, hash, id_err_into, throttle, validate_user};
+use crate::config;
+use crate::config::PasskeyMode;
+use crate::database::{auth, webauthn as db};
+use actix_identity::Identity;
+use async_sqlite::Pool;
+use common_library::error::ErrToResponse;
+use common_library::serde_json::{self, Value, json};
+use error::WebAuthnError;
+use rand::{Rng, distr::Alphanumeric};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use webauthn_rs::prelude::*;
+
+/// Time given to the browser to complete a ceremony
+const CEREMONY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const ID_SIZE: usize = 32;
+const MAX_NAME_SIZE: usize = 64;
+
+static WEBAUTHN: OnceLock<Webauthn> = OnceLock::new();
+/// Passkey registrations waiting for the browser, along with the name of the new passkey
+static REGISTRATIONS: LazyLock<Ceremonies<(PasskeyRegistration, String)>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+/// Passkey logins waiting for the browser
+static AUTHENTICATIONS: LazyLock<Ceremonies<PasskeyAuthentication>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+type Ceremonies<T> = Mutex<HashMap<String, Ceremony<T>>>;
+
+/// State kept between the start and the end of a ceremony
+struct Ceremony<T> {
+    username: String,
+    state: T,
+    started: Instant,
+}
+
+fn boxed<E: ErrToResponse + 'static>(e: E) -> Box<dyn ErrToResponse> {
+    Box::new(e)
+}
+
+fn db_err(e: crate::database::error::DBError) -> Box<dyn ErrToResponse> {
+    boxed(Into::<WebAuthnError>::into(e))
+}
+
+fn store<T>(ceremonies: &Ceremonies<T>, username: String, state: T) -> Result<String, WebAuthnError> {
+    let mut ceremonies = ceremonies
+        .lock()
+        .map_err(|_| WebAuthnError::InternalError("Passkey ceremonies lock was poisoned".into()))?;
+    ceremonies.retain(|_, c| c.started.elapsed() < CEREMONY_TIMEOUT);
+    let id: String = rand::rng().sample_iter(&Alphanumeric).take(ID_SIZE).map(char::from).collect();
+    ceremonies.insert(
+        id.clone(),
+        Ceremony {
+            username,
+            state,
+            started: Instant::now(),
+        },
+    );
+    Ok(id)
+}
+
+fn take<T>(ceremonies: &Ceremonies<T>, id: &str) -> Result<Ceremony<T>, WebAuthnError> {
+    ceremonies
+        .lock()
+        .map_err(|_| WebAuthnError::InternalError("Passkey ceremonies lock was poisoned".into()))?
+        .remove(id)
+        .filter(|c| c.started.elapsed() < CEREMONY_TIMEOUT)
+        .ok_or(WebAuthnError::CeremonyNotFound)
+}
+
+fn webauthn() -> Result<&'static Webauthn, WebAuthnError> {
+    WEBAUTHN.get().ok_or(WebAuthnError::Disabled)
+}
+
+/// Stable WebAuthn handle of a user, derived from its username
+fn user_handle(username: &str) -> Uuid {
+    let hash = Sha256::digest(username.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&hash[..16]);
+    Uuid::from_bytes(bytes)
+}
+
+/// Hex encoded credential id, used to find a passkey in the database
+fn cred_key(cred_id: &CredentialID) -> String {
+    let bytes: &[u8] = cred_id.as_ref();
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn parse_passkey(credential: &db::Credential) -> Result<Passkey, WebAuthnError> {
+    serde_json::from_str(&credential.passkey).map_err(|e| WebAuthnError::InternalError(format!("Invalid saved passkey: {e}")))
+}
+
+/// Sets up the relying party from `webauthn` in the config, if present
+pub fn init() -> Result<(), String> {
+    let Some(conf) = config!(webauthn) else {
+        return Ok(());
+    };
+    let origin = Url::parse(&conf.origin).map_err(|e| format!("Invalid WebAuthn origin `{}`: {e}", conf.origin))?;
+    let webauthn = WebauthnBuilder::new(&conf.rp_id, &origin)
+        .and_then(|builder| builder.rp_name(config!(server_name)).build())
+        .map_err(|e| format!("Failed to set up WebAuthn: {e}"))?;
+    WEBAUTHN.set(webauthn).map_err(|_| "WebAuthn was already set up".to_string())
+}
+
+/// Starts adding a passkey to the account. The password is asked again since a passkey can replace it.
+/// Returns the ceremony id and the options for the browser.
+pub async fn start_registration(
+    pool: &Pool,
+    user: Identity,
+    password: &[u8],
+    name: String,
+) -> Result<(String, CreationChallengeResponse), Box<dyn ErrToResponse>> {
+    webauthn().map_err(boxed)?;
+    let name: String = name.trim().chars().filter(|c| !c.is_control()).collect();
+    if name.is_empty() || name.chars().count() > MAX_NAME_SIZE {
+        return Err(boxed(WebAuthnError::BadName(MAX_NAME_SIZE)));
+    }
+    let (username, sessionid) =
+        auth::unpack(user.id().map_err(|e| boxed(id_err_into(e)))?).map_err(|e| boxed(Into::<AuthError>::into(e)))?;
+    match auth::get_passhash(pool, username.clone(), sessionid)
+        .await
+        .map_err(|e| boxed(Into::<AuthError>::into(e)))?
+    {
+        Some(pass_hash) => hash::verify(password, pass_hash).await.map_err(boxed)?,
+        None => {
+            user.logout();
+            return Err(boxed(AuthError::InvalidSession));
+        }
+    }
+    begin_registration(pool, username, name).await
+}
+
+/// Starts the registration of a passkey named `name` for a user already checked
+async fn begin_registration(
+    pool: &Pool,
+    username: String,
+    name: String,
+) -> Result<(String, CreationChallengeResponse), Box<dyn ErrToResponse>> {
+    let webauthn = webauthn().map_err(boxed)?;
+    let exclude = db::get_credentials(pool, username.clone())
+        .await
+        .map_err(db_err)?
+        .iter()
+        .map(|c| parse_passkey(c).map(|p| p.cred_id().clone()))
+        .collect::<Result<Vec<CredentialID>, WebAuthnError>>()
+        .map_err(boxed)?;
+    let (options, state) = webauthn
+        .start_passkey_registration(user_handle(&username), &username, &username, Some(exclude))
+        .map_err(|e| boxed(WebAuthnError::InternalError(format!("Failed to start passkey registration: {e}"))))?;
+    let id = store(&REGISTRATIONS, username, (state, name)).map_err(boxed)?;
+    Ok((id, options))
+}
+
+/// Verifies the new passkey created by the browser and saves it
+pub async fn finish_registration(
+    pool: &Pool,
+    user: Identity,
+    id: &str,
+    credential: RegisterPublicKeyCredential,
+) -> Result<(), Box<dyn ErrToResponse>> {
+    webauthn().map_err(boxed)?;
+    let (username, _) = validate_user(pool, user).await.map_err(boxed)?;
+    save_registration(pool, username, id, credential).await
+}
+
+/// Verifies the new passkey of a user already checked and saves it
+async fn save_registration(
+    pool: &Pool,
+    username: String,
+    id: &str,
+    credential: RegisterPublicKeyCredential,
+) -> Result<(), Box<dyn ErrToResponse>> {
+    let webauthn = webauthn().map_err(boxed)?;
+    let ceremony = take(&REGISTRATIONS, id).map_err(boxed)?;
+    if ceremony.username != username {
+        return Err(boxed(WebAuthnError::CeremonyNotFound));
+    }
+    let (state, name) = ceremony.state;
+    let passkey = webauthn.finish_passkey_registration(&credential, &state).map_err(|e| {
+        log::debug!("Passkey registration of `{username}` failed: {e}");
+        boxed(WebAuthnError::Rejected)
+    })?;
+    let serialized = serde_json::to_string(&passkey)
+        .map_err(|e| boxed(WebAuthnError::InternalError(format!("Failed to serialize passkey: {e}"))))?;
+    db::add_credential(pool, username.clone(), cred_key(passkey.cred_id()), name.clone(), serialized)
+        .await
+        .map_err(db_err)?;
+    log::warn!("user `{username}` added passkey `{name}`");
+    Ok(())
+}
+
+/// Checks what the passkey does not replace, depending on the config, and starts a login.
+/// Returns the ceremony id and the options for the browser.
+pub async fn start_login(
+    pool: &Pool,
+    attempt: throttle::Attempt,
+    username: String,
+    password: &[u8],
+    totp: &str,
+) -> Result<(String, RequestChallengeResponse), Box<dyn ErrToResponse>> {
+    webauthn().map_err(boxed)?;
+    let mode = config!(webauthn)
+        .as_ref()
+        .map(|w| w.replaces)
+        .ok_or_else(|| boxed(WebAuthnError::Disabled))?;
+    let checked = match mode {
+        PasskeyMode::Totp => check_password(pool, &username, password).await.map(|_| ()),
+        PasskeyMode::Password => match auth::get_auth(pool, username.clone()).await.map_err(|e| e.into()) {
+            Ok(Some(user)) => check_second_factor(pool, username.clone(), user.totp, totp).await,
+            Ok(None) => Err(AuthError::InvalidCredentials),
+            Err(e) => Err(e),
+        },
+    };
+    if let Err(e) = checked {
+        if matches!(e, AuthError::InvalidCredentials | AuthError::InvalidTOTP) {
+            attempt.failed();
+        }
+        return Err(boxed(e));
+    }
+    begin_login(pool, username).await
+}
+
+/// Starts a passkey login for a user whose other factor was already checked
+async fn begin_login(pool: &Pool, username: String) -> Result<(String, RequestChallengeResponse), Box<dyn ErrToResponse>> {
+    let webauthn = webauthn().map_err(boxed)?;
+    let passkeys = db::get_credentials(pool, username.clone())
+        .await
+        .map_err(db_err)?
+        .iter()
+        .map(parse_passkey)
+        .collect::<Result<Vec<Passkey>, WebAuthnError>>()
+        .map_err(boxed)?;
+    if passkeys.is_empty() {
+        return Err(boxed(WebAuthnError::NoPasskeys));
+    }
+    let (options, state) = webauthn
+        .start_passkey_authentication(&passkeys)
+        .map_err(|e| boxed(WebAuthnError::InternalError(format!("Failed to start passkey login: {e}"))))?;
+    let id = store(&AUTHENTICATIONS, username, state).map_err(boxed)?;
+    Ok((id, options))
+}
+
+/// Verifies the assertion of the browser and returns the username of the session to start
+pub async fn finish_login(pool: &Pool, ip: &str, id: &str, credential: PublicKeyCredential) -> Result<String, Box<dyn ErrToResponse>> {
+    let webauthn = webauthn().map_err(boxed)?;
+    let ceremony = take(&AUTHENTICATIONS, id).map_err(boxed)?;
+    let username = ceremony.username;
+    let attempt = throttle::check(ip, &username).map_err(boxed)?;
+    let result = match webauthn.finish_passkey_authentication(&credential, &ceremony.state) {
+        Ok(result) => result,
+        Err(e) => {
+            log::debug!("Passkey login of `{username}` failed: {e}");
+            attempt.failed();
+            return Err(boxed(WebAuthnError::Rejected));
+        }
+    };
+    if result.needs_update() {
+        let key = cred_key(result.cred_id());
+        let credentials = db::get_credentials(pool, username.clone()).await.map_err(db_err)?;
+        if let Some(credential) = credentials.iter().find(|c| c.cred_id == key) {
+            let mut passkey = parse_passkey(credential).map_err(boxed)?;
+            passkey.update_credential(&result);
+            let serialized = serde_json::to_string(&passkey)
+                .map_err(|e| boxed(WebAuthnError::InternalError(format!("Failed to serialize passkey: {e}"))))?;
+            db::update_credential(pool, key, serialized).await.map_err(db_err)?;
+        }
+    }
+    let user = auth::get_auth(pool, username.clone())
+        .await
+        .map_err(|e| boxed(Into::<AuthError>::into(e)))?
+        .ok_or_else(|| boxed(AuthError::InvalidCredentials))?;
+    attempt.succeeded();
+    Ok(user.username)
+}
+
+/// Returns the passkeys of the user, without their keys
+pub async fn list(pool: &Pool, user: Identity) -> Result<Vec<Value>, Box<dyn ErrToResponse>> {
+    webauthn().map_err(boxed)?;
+    let (username, _) = validate_user(pool, user).await.map_err(boxed)?;
+    Ok(db::get_credentials(pool, username)
+        .await
+        .map_err(db_err)?
+        .into_iter()
+        .map(|c| json!({ "id": c.cred_id, "name": c.name, "created": c.created }))
+        .collect())
+}
+
+/// Deletes a passkey of the user
+pub async fn delete(pool: &Pool, user: Identity, cred_id: String) -> Result<(), Box<dyn ErrToResponse>> {
+    webauthn().map_err(boxed)?;
+    let (username, _) = validate_user(pool, user).await.map_err(boxed)?;
+    if !db::delete_credential(pool, username.clone(), cred_id).await.map_err(db_err)? {
+        return Err(boxed(WebAuthnError::CredentialNotFound));
+    }
+    log::warn!("user `{username}` deleted a passkey");
+    Ok(())
+}
+
+#[cfg(all(test, feature = "openssl"))]
+mod tests {
+    use super::*;
+    use crate::database::memory;
+    use openssl::bn::BigNumContext;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::sign::Signer;
+
+    const RP_ID: &str = "localhost";
+    const ORIGIN: &str = "https://localhost:8443";
+
+    fn ok<T>(result: Result<T, Box<dyn ErrToResponse>>) -> T {
+        result.unwrap_or_else(|e| panic!("{}: {}", e.error(), e.msg()))
+    }
+
+    fn base64url(data: &[u8]) -> String {
+        const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+        let mut out = String::new();
+        for chunk in data.chunks(3) {
+            let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+            for i in 0..=chunk.len() {
+                out.push(CHARS[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            }
+        }
+        out
+    }
+
+    /// Head of a CBOR item of the given major type
+    fn cbor(major: u8, len: usize) -> Vec<u8> {
+        match len {
+            0..24 => vec![(major << 5) | len as u8],
+            24..256 => vec![(major << 5) | 24, len as u8],
+            _ => [vec![(major << 5) | 25], (len as u16).to_be_bytes().to_vec()].concat(),
+        }
+    }
+
+    fn cbor_bytes(data: &[u8]) -> Vec<u8> {
+        [cbor(2, data.len()), data.to_vec()].concat()
+    }
+
+    fn cbor_text(text: &str) -> Vec<u8> {
+        [cbor(3, text.len()), text.as_bytes().to_vec()].concat()
+    }
+
+    /// Authenticator holding a single ES256 passkey, as a browser would drive it
+    struct Authenticator {
+        key: PKey<Private>,
+        cred_id: [u8; 16],
+        counter: u32,
+    }
+
+    impl Authenticator {
+        fn new() -> Self {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            Authenticator {
+                key: PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap(),
+                cred_id: rand::random(),
+                counter: 0,
+            }
+        }
+
+        /// Public key in COSE format
+        fn cose_key(&self) -> Vec<u8> {
+            let ec = self.key.ec_key().unwrap();
+            let (mut x, mut y) = (openssl::bn::BigNum::new().unwrap(), openssl::bn::BigNum::new().unwrap());
+            ec.public_key()
+                .affine_coordinates(ec.group(), &mut x, &mut y, &mut BigNumContext::new().unwrap())
+                .unwrap();
+            // kty: EC2, alg: ES256, crv: P-256, x, y
+            let mut key = vec![0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21];
+            key.extend(cbor_bytes(&x.to_vec_padded(32).unwrap()));
+            key.push(0x22);
+            key.extend(cbor_bytes(&y.to_vec_padded(32).unwrap()));
+            key
+        }
+
+        /// Authenticator data with user presence and verification, and the new credential if any
+        fn auth_data(&self, attested: bool) -> Vec<u8> {
+            let mut data = Sha256::digest(RP_ID).to_vec();
+            data.push(if attested { 0x45 } else { 0x05 });
+            data.extend(self.counter.to_be_bytes());
+            if attested {
+                data.extend([0u8; 16]);
+                data.extend((self.cred_id.len() as u16).to_be_bytes());
+                data.extend(self.cred_id);
+                data.extend(self.cose_key());
+            }
+            data
+        }
+
+        fn client_data(kind: &str, options: impl serde::Serialize) -> Vec<u8> {
+            let options = serde_json::to_value(options).unwrap();
+            let challenge = options["publicKey"]["challenge"].as_str().expect("Options without a challenge");
+            json!({ "type": kind, "challenge": challenge, "origin": ORIGIN, "crossOrigin": false })
+                .to_string()
+                .into_bytes()
+        }
+
+        fn register(&self, options: &CreationChallengeResponse) -> RegisterPublicKeyCredential {
+            let mut attestation = vec![0xa3];
+            attestation.extend(cbor_text("fmt"));
+            attestation.extend(cbor_text("none"));
+            attestation.extend(cbor_text("attStmt"));
+            attestation.push(0xa0);
+            attestation.extend(cbor_text("authData"));
+            attestation.extend(cbor_bytes(&self.auth_data(true)));
+            serde_json::from_value(json!({
+                "id": base64url(&self.cred_id),
+                "rawId": base64url(&self.cred_id),
+                "type": "public-key",
+                "response": {
+                    "attestationObject": base64url(&attestation),
+                    "clientDataJSON": base64url(&Self::client_data("webauthn.create", options)),
+                },
+            }))
+            .expect("Invalid registration credential")
+        }
+
+        fn authenticate(&mut self, options: &RequestChallengeResponse) -> PublicKeyCredential {
+            self.counter += 1;
+            let auth_data = self.auth_data(false);
+            let client_data = Self::client_data("webauthn.get", options);
+            let mut signer = Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+            signer.update(&auth_data).unwrap();
+            signer.update(&Sha256::digest(&client_data)).unwrap();
+            serde_json::from_value(json!({
+                "id": base64url(&self.cred_id),
+                "rawId": base64url(&self.cred_id),
+                "type": "public-key",
+                "response": {
+                    "authenticatorData": base64url(&auth_data),
+                    "clientDataJSON": base64url(&client_data),
+                    "signature": base64url(&signer.sign_to_vec().unwrap()),
+                    "userHandle": base64url(user_handle("alice").as_bytes()),
+                },
+            }))
+            .expect("Invalid login credential")
+        }
+    }
+
+    async fn setup() -> Pool {
+        WEBAUTHN.get_or_init(|| {
+            WebauthnBuilder::new(RP_ID, &Url::parse(ORIGIN).unwrap())
+                .and_then(|builder| builder.rp_name("tiny-cloud").build())
+                .expect("Failed to set up WebAuthn")
+        });
+        let pool = memory().await;
+        auth::add_user(&pool, "alice".into(), String::new(), String::new(), false)
+            .await
+            .unwrap();
+        pool
+    }
+
+    #[actix_web::test]
+    async fn passkey_round_trip() {
+        let pool = setup().await;
+        let mut authenticator = Authenticator::new();
+
+        let (id, options) = ok(begin_registration(&pool, "alice".into(), "laptop".into()).await);
+        ok(save_registration(&pool, "alice".into(), &id, authenticator.register(&options)).await);
+        let saved = db::get_credentials(&pool, "alice".into()).await.unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].cred_id, cred_key(&CredentialID::from(authenticator.cred_id.to_vec())));
+        assert_eq!(saved[0].name, "laptop");
+
+        for _ in 0..2 {
+            let (id, options) = ok(begin_login(&pool, "alice".into()).await);
+            let credential = authenticator.authenticate(&options);
+            assert_eq!(ok(finish_login(&pool, "127.0.0.1", &id, credential).await), "alice");
+        }
+    }
+
+    #[actix_web::test]
+    async fn assertions_are_checked() {
+        let pool = setup().await;
+        let mut authenticator = Authenticator::new();
+        let (id, options) = ok(begin_registration(&pool, "alice".into(), "laptop".into()).await);
+        ok(save_registration(&pool, "alice".into(), &id, authenticator.register(&options)).await);
+
+        // The ceremony is gone once used
+        let (id, options) = ok(begin_login(&pool, "alice".into()).await);
+        let credential = authenticator.authenticate(&options);
+        ok(finish_login(&pool, "127.0.0.2", &id, credential).await);
+        let credential = authenticator.authenticate(&options);
+        let replayed = finish_login(&pool, "127.0.0.2", &id, credential).await;
+        assert_eq!(replayed.err().map(|e| e.error()), Some(WebAuthnError::CeremonyNotFound.error()));
+
+        // Signed by another key
+        let (id, options) = ok(begin_login(&pool, "alice".into()).await);
+        authenticator.key = Authenticator::new().key;
+        let forged = finish_login(&pool, "127.0.0.2", &id, authenticator.authenticate(&options)).await;
+        assert_eq!(forged.err().map(|e| e.error()), Some(WebAuthnError::Rejected.error()));
+    }
+}
@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use common_library::error::ErrToResponse;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum WebAuthnError {
+    #[error("An internal server error occurred")]
+    InternalError(String),
+    #[error("Passkeys are not enabled")]
+    Disabled,
+    #[error("Passkey request expired or was not found")]
+    CeremonyNotFound,
+    #[error("Passkey was rejected")]
+    Rejected,
+    #[error("Passkey was not found")]
+    CredentialNotFound,
+    #[error("No passkeys were added to this account")]
+    NoPasskeys,
+    #[error("Passkey name must be between 1 and {0} characters")]
+    BadName(usize),
+}
+
+impl ErrToResponse for WebAuthnError {
+    fn error(&self) -> &'static str {
+        "WebAuthnError"
+    }
+
+    fn err_type(&self) -> &'static str {
+        match self {
+            Self::InternalError(_) => stringify!(InternalError),
+            Self::Disabled => stringify!(Disabled),
+            Self::CeremonyNotFound => stringify!(CeremonyNotFound),
+            Self::Rejected => stringify!(Rejected),
+            Self::CredentialNotFound => stringify!(CredentialNotFound),
+            Self::NoPasskeys => stringify!(NoPasskeys),
+            Self::BadName(_) => stringify!(BadName),
+        }
+    }
+
+    fn msg(&self) -> String {
+        self.to_string()
+    }
+
+    fn http_code(&self) -> HttpResponseBuilder {
+        match self {
+            Self::InternalError(_) => HttpResponse::InternalServerError(),
+            Self::Disabled => HttpResponse::NotFound(),
+            Self::CeremonyNotFound => HttpResponse::BadRequest(),
+            Self::Rejected => HttpResponse::Unauthorized(),
+            Self::CredentialNotFound => HttpResponse::NotFound(),
+            Self::NoPasskeys => HttpResponse::BadRequest(),
+            Self::BadName(_) => HttpResponse::BadRequest(),
+        }
+    }
+
+    fn handle(&self) {
+        if let Self::InternalError(err) = self {
+            log::error!("An internal server error occurred during passkey authentication: {err}");
+        }
+    }
+}
@@ -1,37 +1,252 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+mod default;
+mod env;
+mod validate;
+
 use common_library::toml;
 use serde::{Deserialize, Serialize};
 use std::env::current_exe;
-use std::path::PathBuf;
-use tokio::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::OnceCell;
 
-pub static CONFIG: OnceCell<Config> = OnceCell::const_new();
+/// Current config. Whoever still holds the previous one after a reload keeps it until dropped.
+static CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+/// Path the config was opened from, to read it again on reloads
+static PATH: OnceLock<PathBuf> = OnceLock::new();
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Server {
     pub host: String,
     pub port: u16,
     pub workers: usize,
     pub is_behind_proxy: bool,
+    pub unix_socket: Option<UnixSocket>,
+    /// Addresses listened on besides `host`:`port`
+    pub listeners: Option<Vec<Address>>,
+    pub http_redirect: Option<HttpRedirect>,
+}
+
+/// Address the server listens on besides `host`:`port`
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Address {
+    pub host: String,
+    pub port: u16,
+    /// Whether TLS is used, defaults to whether `tls` is set
+    pub tls: Option<bool>,
 }
 
+/// Plain HTTP listener answering every request with a redirect to the same URL over HTTPS
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpRedirect {
+    pub host: String,
+    pub port: u16,
+    /// Port of the HTTPS URLs redirected to, defaults to 443
+    pub https_port: Option<u16>,
+}
+
+/// Unix domain socket the server listens on, available only on Unix. TLS is never used on it.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnixSocket {
+    pub path: String,
+    /// Permissions of the socket file, e.g. 0o660
+    pub mode: Option<u32>,
+    /// Whether to listen only on the socket and not on `host`:`port`
+    pub only: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 #[cfg(not(feature = "no-tls"))]
 pub struct Tls {
     pub privkey_path: String,
     pub cert_path: String,
 }
 
+/// Protocol versions, cipher suites and extensions of TLS handshakes, applied the same way by both TLS backends
+/// except for `http2`
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsPolicy {
+    pub profile: TlsProfile,
+    /// Lowest protocol version accepted, defaults to the one of the profile
+    pub min_version: Option<TlsVersion>,
+    /// Cipher suites accepted, by their IANA names. Listing some of a protocol version replaces the profile's ones for it.
+    pub ciphers: Option<Vec<String>>,
+    /// Whether HTTP/2 is offered to clients, defaults to true. Openssl only: actix-web always puts `h2` first in the ALPN
+    /// protocols of rustls, so rustls builds refuse the config if it is false.
+    pub http2: Option<bool>,
+    /// OCSP response in DER format stapled to handshakes, reloaded along with the certificate
+    pub ocsp_path: Option<String>,
+}
+
+/// Server side TLS recommendations of Mozilla
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsProfile {
+    /// TLS 1.3 only
+    Modern,
+    /// TLS 1.2 and 1.3 with forward secret AEAD cipher suites
+    Intermediate,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Registration {
     pub token_duration_seconds: u64,
     pub token_size: u8,
 }
 
+/// Throttling of failed logins per IP address and per username
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoginThrottle {
+    /// Seconds during which a failed attempt is counted
+    pub window_seconds: u64,
+    /// Failed attempts allowed from an IP address within the window
+    pub max_ip_attempts: u32,
+    /// Failed attempts allowed for a username within the window
+    pub max_user_attempts: u32,
+    /// Lockout after reaching the limit, doubled for every further failure
+    pub lockout_seconds: u64,
+    /// Longest possible lockout
+    pub max_lockout_seconds: u64,
+}
+
+/// Personal API tokens, sent by scripts as `Authorization: Bearer`
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiTokens {
+    /// Longest lifetime a user can give to a token
+    pub max_days: u32,
+}
+
+/// What a passkey replaces when logging in
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PasskeyMode {
+    /// Users login with password and passkey
+    Totp,
+    /// Users login with passkey and TOTP token
+    Password,
+}
+
+impl PasskeyMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Totp => "totp",
+            Self::Password => "password",
+        }
+    }
+}
+
+/// Passkey logins, available only with feature "webauthn"
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebAuthn {
+    /// Domain passkeys are bound to, e.g. "cloud.example.com"
+    pub rp_id: String,
+    /// Origin of the server as seen by browsers, e.g. "https://cloud.example.com"
+    pub origin: String,
+    pub replaces: PasskeyMode,
+}
+
+/// Certificates obtained and renewed from an ACME server like Let's Encrypt, available only with feature "acme".
+/// They replace the `tls` table and are stored under `data_directory`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Acme {
+    /// Domains the certificate is for, e.g. ["cloud.example.com"]
+    pub domains: Vec<String>,
+    /// Contacts of the account, e.g. ["mailto:admin@example.com"]
+    pub contact: Option<Vec<String>>,
+    /// Directory of the ACME server, e.g. "https://acme-v02.api.letsencrypt.org/directory"
+    pub directory_url: String,
+    pub challenge: AcmeChallenge,
+    /// Root certificate the ACME server is trusted with instead of the usual ones, for test servers like pebble
+    pub ca_cert_path: Option<String>,
+    /// Days before expiration at which the certificate is renewed
+    pub renew_days: u64,
+}
+
+/// How the ACME server checks that the domains point to this server
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum AcmeChallenge {
+    /// A file served over plain HTTP on port 80
+    #[serde(rename = "http-01")]
+    Http01,
+    /// A special certificate served over TLS on port 443
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01,
+}
+
+#[cfg(feature = "acme")]
+impl AcmeChallenge {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Http01 => "http-01",
+            Self::TlsAlpn01 => "tls-alpn-01",
+        }
+    }
+}
+
+/// Client certificates requested during TLS handshakes, checked against a CA bundle
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientAuth {
+    /// CA certificates in PEM format, client certificates must be signed by one of them
+    pub ca_path: String,
+    /// Whether connections without a valid client certificate are refused
+    pub required: bool,
+    /// Name of the certificate holding the username, so that its owner can login with it. If none, no one can.
+    pub login: Option<CertificateName>,
+}
+
+/// Name of a client certificate mapped to a username
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum CertificateName {
+    /// Common name of the subject
+    #[serde(rename = "cn")]
+    CommonName,
+    /// First DNS name of the subject alternative names
+    #[serde(rename = "san")]
+    SubjectAltName,
+}
+
+/// Secret key replaced by a rotation, cookies encrypted with it are still accepted until it expires
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PreviousSecretKey {
+    pub path: String,
+    /// Unix time after which the key is not accepted anymore
+    pub expires: u64,
+}
+
+/// Sessions stored in the database, the cookie then carries only an opaque id
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionStore {
+    /// Minutes between deletions of expired sessions
+    pub sweep_minutes: u64,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Logging {
     pub stdout_level: String,
     pub file: Option<String>,
@@ -41,6 +256,7 @@ pub struct Logging {
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CredentialSize {
     pub max_username: u8,
     pub min_username: u8,
@@ -49,6 +265,7 @@ pub struct CredentialSize {
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Durations {
     pub cookie_minutes: u32,
     pub login_minutes: Option<u64>,
@@ -56,24 +273,44 @@ pub struct Durations {
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Limits {
     pub file_upload_size: usize,
     pub payload_size: usize,
+    /// Minutes after which an unfinished resumable upload is deleted, if none it is never deleted
+    pub partial_upload_minutes: Option<u64>,
+    /// Most unfinished resumable uploads anonymous clients can have at once, if none only users can start them
+    pub anonymous_uploads: Option<usize>,
+    /// Largest total size in bytes of the unfinished resumable uploads of anonymous clients, if none it has no limit
+    pub anonymous_uploads_size: Option<u64>,
+    /// Most unfinished resumable uploads each user can have at once, if none they have no limit
+    pub user_uploads: Option<usize>,
+    /// Storage quota in bytes of users without their own, if none they have no limit
+    pub default_quota: Option<u64>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub server_name: String,
     pub description: String,
     pub url_prefix: String,
     pub data_directory: String,
     pub session_secret_key_path: String,
+    pub previous_secret_keys: Option<Vec<PreviousSecretKey>>,
+    pub session_store: Option<SessionStore>,
     pub homepage_script: Option<String>,
     pub server: Server,
     pub logging: Logging,
     #[cfg(not(feature = "no-tls"))]
     pub tls: Option<Tls>,
+    pub tls_policy: Option<TlsPolicy>,
     pub registration: Option<Registration>,
+    pub login_throttle: Option<LoginThrottle>,
+    pub api_tokens: Option<ApiTokens>,
+    pub webauthn: Option<WebAuthn>,
+    pub acme: Option<Acme>,
+    pub client_auth: Option<ClientAuth>,
     pub limits: Limits,
     pub duration: Durations,
     pub cred_size: CredentialSize,
@@ -98,6 +335,9 @@ impl Config {
                 port: 80,
                 workers: num_cpus::get(),
                 is_behind_proxy: false,
+                unix_socket: None,
+                listeners: None,
+                http_redirect: None,
             },
             logging: Logging {
                 stdout_level: "info".into(),
@@ -111,14 +351,31 @@ impl Config {
                 privkey_path: format!("{}/privkey.pem", get_exec_dir()?),
                 cert_path: format!("{}/cert.pem", get_exec_dir()?),
             }),
+            tls_policy: None,
             registration: Some(Registration {
                 token_size: 16,
                 token_duration_seconds: 24 * 60 * 60,
             }),
+            login_throttle: Some(LoginThrottle {
+                window_seconds: 15 * 60,
+                max_ip_attempts: 20,
+                max_user_attempts: 5,
+                lockout_seconds: 30,
+                max_lockout_seconds: 60 * 60,
+            }),
+            api_tokens: Some(ApiTokens { max_days: 365 }),
+            webauthn: None,
+            acme: None,
+            client_auth: None,
             data_directory: format!("{}/data", get_exec_dir()?),
             limits: Limits {
                 file_upload_size: 5_000_000_000,
                 payload_size: 4096,
+                partial_upload_minutes: Some(24 * 60),
+                anonymous_uploads: Some(100),
+                anonymous_uploads_size: Some(10_000_000_000),
+                user_uploads: Some(20),
+                default_quota: None,
             },
             duration: Durations {
                 cookie_minutes: 43200,
@@ -126,6 +383,8 @@ impl Config {
                 visit_minutes: Some(21600),
             },
             session_secret_key_path: format!("{}/secret.key", get_exec_dir()?),
+            previous_secret_keys: None,
+            session_store: None,
             cred_size: CredentialSize {
                 max_username: 10,
                 min_username: 3,
@@ -135,9 +394,21 @@ impl Config {
             plugins,
         })
     }
+
+    /// Whether TLS is configured, with `tls` or `acme`, never in builds without TLS
+    pub fn has_tls(&self) -> bool {
+        #[cfg(feature = "no-tls")]
+        {
+            false
+        }
+        #[cfg(not(feature = "no-tls"))]
+        {
+            self.tls.is_some() || self.acme.is_some()
+        }
+    }
 }
 
-pub async fn open(path: &PathBuf) -> Result<(), String> {
+async fn read(path: &PathBuf) -> Result<Config, String> {
     let mut file = File::open(&path)
         .await
         .map_err(|e| format!("Failed to open config file `{}`: {e}", path.display()))?;
@@ -145,27 +416,98 @@ pub async fn open(path: &PathBuf) -> Result<(), String> {
     file.read_to_string(&mut config)
         .await
         .map_err(|e| format!("Failed to read config file `{}`: {e}", path.display()))?;
-    CONFIG
-        .set(toml::from_str(&config).map_err(|e| format!("Failed to read config file `{}`: {e}", path.display()))?)
-        .expect("Config has already been opened. This is a bug");
+    let overrides = env::overrides()?;
+    // Deserializing from a table loses the position of errors, so it is only done when something is overridden
+    let config: Config = if overrides.is_empty() {
+        toml::from_str(&config).map_err(|e| format!("Failed to read config file `{}`: {e}", path.display()))?
+    } else {
+        let mut table: toml::Table =
+            toml::from_str(&config).map_err(|e| format!("Failed to read config file `{}`: {e}", path.display()))?;
+        env::apply(&mut table, overrides)?;
+        table
+            .try_into()
+            .map_err(|e| format!("Failed to read config file `{}` and its environment overrides: {e}", path.display()))?
+    };
+    let problems = validate::validate(&config);
+    if !problems.is_empty() {
+        return Err(format!(
+            "Invalid config file `{}`:\n{}",
+            path.display(),
+            problems.iter().map(|p| format!("  - {p}")).collect::<Vec<_>>().join("\n")
+        ));
+    }
+    // The certificate obtained by ACME is used like any other
+    #[cfg(feature = "acme")]
+    let config = match config.acme {
+        Some(_) => Config {
+            tls: Some(crate::acme::tls_files(&config.data_directory)),
+            ..config
+        },
+        None => config,
+    };
+    Ok(config)
+}
+
+pub async fn open(path: &PathBuf) -> Result<(), String> {
+    let config = read(path).await?;
+    PATH.set(path.clone()).expect("Config has already been opened. This is a bug");
+    replace(config);
     Ok(())
 }
 
-pub async fn write_default(plugins: toml::Table) -> Result<(), String> {
-    let mut path = current_exe().map_err(|e| format!("Failed to get executable's path: {e}"))?;
-    path.pop();
-    path.push("default.toml");
-    let mut file = File::create(path).await.map_err(|e| format!("Failed to create config file: {e}"))?;
-    let default = Config::default(plugins)?;
-    let default = toml::to_string(&default).map_err(|e| format!("Failed to serialize config: {e}"))?;
+/// Reads the config file again, without applying it.
+pub async fn read_again() -> Result<Config, String> {
+    let path = PATH
+        .get()
+        .expect("Tried to reload config while it wasn't opened yet. This is a bug");
+    read(path).await
+}
+
+/// Makes `config` the current one, the previous config is freed once nothing holds it anymore.
+pub fn replace(config: Config) {
+    *CONFIG.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(config));
+}
+
+/// Writes the default config to `path`, with comments documenting every key. Fails if the file exists, unless `force` is set.
+pub async fn write_default(plugins: toml::Table, path: &Path, force: bool) -> Result<(), String> {
+    let default = default::documented(&Config::default(plugins)?)?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .create_new(!force)
+        .open(path)
+        .await
+        .map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists => format!("Config file `{}` already exists, use `--force` to overwrite it", path.display()),
+            _ => format!("Failed to create config file `{}`: {e}", path.display()),
+        })?;
     file.write_all(default.as_bytes())
         .await
         .map_err(|e| format!("Failed to write config: {e}"))?;
+    println!("Default config written to `{}`", path.display());
     Ok(())
 }
 
-pub fn get() -> &'static Config {
+/// Makes the default config the current one, with data in a temporary directory, unless a config was already opened.
+/// Used by tests.
+#[cfg(test)]
+pub fn use_default() {
+    let mut config = CONFIG.write().unwrap_or_else(PoisonError::into_inner);
+    if config.is_none() {
+        let default = Config::default(toml::Table::new()).expect("Failed to build default config");
+        let data_directory = std::env::temp_dir().join(format!("tiny-cloud-test-{}", std::process::id()));
+        *config = Some(Arc::new(Config {
+            data_directory: data_directory.display().to_string(),
+            ..default
+        }));
+    }
+}
+
+pub fn get() -> Arc<Config> {
     CONFIG
-        .get()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
         .expect("Tried to access config while it wasn't opened yet. This is a bug")
 }
@@ -0,0 +1,324 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::Config;
+use crate::plugins;
+use common_library::toml;
+
+/// Comments written above each key and table of the default config
+const DOCS: &[(&str, &str)] = &[
+    ("server_name", "Name shown in the web UI"),
+    ("description", "Description shown on the login and registration pages"),
+    (
+        "url_prefix",
+        "Path every page and API is served under, e.g. \"tcloud\" serves the UI at /tcloud/ui",
+    ),
+    (
+        "data_directory",
+        "Directory holding the database and the files of every user, created if missing.\nIts parent directory must exist.",
+    ),
+    (
+        "session_secret_key_path",
+        "File with the 64 bytes key encrypting session cookies, create it with `--gen-secret`.\n\
+         Keep it private: anyone reading it can forge sessions.",
+    ),
+    (
+        "server",
+        "Address the server listens on.\nWhen started by systemd socket activation, the sockets it passes replace `host` and `port`.",
+    ),
+    ("server.host", "IP address or hostname, \"0.0.0.0\" listens on every interface"),
+    ("server.port", "TCP port, 1-65535"),
+    (
+        "server.workers",
+        "Worker threads handling requests, at least 1, defaults to the number of CPUs",
+    ),
+    (
+        "server.is_behind_proxy",
+        "Whether the server is behind a reverse proxy, client IPs are then taken from the `Forwarded` and\n\
+         `X-Forwarded-For` headers. Enable it only if the proxy sets them, otherwise clients can spoof their IP.",
+    ),
+    (
+        "logging",
+        "Levels are one of \"off\", \"error\", \"warn\", \"info\", \"debug\" or \"trace\".\n\
+         \"debug\" and \"trace\" are only available in debug builds.\n\
+         Levels can be changed without a restart by sending SIGHUP to the server.",
+    ),
+    ("logging.stdout_level", "Level of the logs printed to stdout"),
+    (
+        "tls",
+        "Certificate and private key in PEM format, remove this table to serve plain HTTP or to use `acme`.\n\
+         Both files are checked every minute and reloaded when they change, or on SIGHUP, without a restart.",
+    ),
+    ("tls.privkey_path", "Private key file"),
+    ("tls.cert_path", "Certificate chain file"),
+    (
+        "registration",
+        "Registration of new users with tokens created by admins, remove this table to disable it",
+    ),
+    (
+        "registration.token_duration_seconds",
+        "Seconds a registration token stays valid, at least 1",
+    ),
+    ("registration.token_size", "Length of registration tokens in characters, 1-255"),
+    ("login_throttle", "Throttling of failed logins, remove this table to disable it"),
+    (
+        "login_throttle.window_seconds",
+        "Seconds during which a failed attempt is counted, at least 1",
+    ),
+    (
+        "login_throttle.max_ip_attempts",
+        "Failed attempts allowed from an IP address within the window, at least 1",
+    ),
+    (
+        "login_throttle.max_user_attempts",
+        "Failed attempts allowed for a username within the window, at least 1",
+    ),
+    (
+        "login_throttle.lockout_seconds",
+        "Seconds of lockout after reaching a limit, doubled for every further failure.\n\
+         Must not be greater than `max_lockout_seconds`.",
+    ),
+    ("login_throttle.max_lockout_seconds", "Longest possible lockout in seconds"),
+    (
+        "api_tokens",
+        "Personal API tokens created by users in their settings, sent by scripts as `Authorization: Bearer`.\n\
+         They only give access to plugins, quota and admin APIs. Remove this table to disable them.",
+    ),
+    ("api_tokens.max_days", "Longest lifetime of a token in days, at least 1"),
+    ("limits", "Size limits, in bytes"),
+    ("limits.file_upload_size", "Largest file that can be uploaded, at least 1"),
+    (
+        "limits.payload_size",
+        "Largest JSON request body, at least 1. 4096 is enough for every built-in API.",
+    ),
+    (
+        "limits.partial_upload_minutes",
+        "Minutes after which an unfinished resumable upload is deleted, at least 1.\nRemove it to keep them forever.",
+    ),
+    (
+        "limits.anonymous_uploads",
+        "Most unfinished resumable uploads clients without an account can have at once.\n\
+         Remove it to let only users start them.",
+    ),
+    (
+        "limits.anonymous_uploads_size",
+        "Largest total size of the unfinished resumable uploads of clients without an account.\nRemove it for no limit.",
+    ),
+    (
+        "limits.user_uploads",
+        "Most unfinished resumable uploads each user can have at once.\n\
+         Their sizes count against the user's quota until they are finished.\nRemove it for no limit.",
+    ),
+    ("duration", "Lifetime of sessions, in minutes"),
+    (
+        "duration.cookie_minutes",
+        "Minutes a session cookie lasts without being used, at least 1",
+    ),
+    (
+        "duration.login_minutes",
+        "Minutes after login at which a session ends regardless of activity, at least 1.\nRemove it to disable this deadline.",
+    ),
+    (
+        "duration.visit_minutes",
+        "Minutes of inactivity after which a session ends, at least 1.\nRemove it to disable this deadline.",
+    ),
+    (
+        "cred_size",
+        "Length limits of credentials, in characters. Minimums must not be greater than maximums.",
+    ),
+    ("cred_size.max_username", "Longest username, 1-255"),
+    ("cred_size.min_username", "Shortest username, at least 1"),
+    ("cred_size.max_passwd", "Longest password, 1-65535"),
+    ("cred_size.min_passwd", "Shortest password, at least 1"),
+    ("plugins", "Configs of the plugins, each one in a table named after its plugin"),
+];
+
+/// Optional keys that are not set by default, written commented out at the end of their table
+const EXAMPLES: &[(&str, &str)] = &[
+    (
+        "",
+        "# Executable run to create the home page, with the username and whether the user is an admin as arguments.\n\
+         # It must print the HTML of the page.\n\
+         #homepage_script = \"/usr/local/bin/tiny-cloud-home\"",
+    ),
+    (
+        "server",
+        "# More addresses to listen on, each one using TLS if `tls` is set unless overridden.\n\
+         # \"::\" alone accepts both IPv6 and IPv4 connections, listing \"::\" and \"0.0.0.0\" on the same port\n\
+         # binds each family with its own socket.\n\
+         #listeners = [{ host = \"::\", port = 8443 }, { host = \"127.0.0.1\", port = 8080, tls = false }]\n\
+         # Plain HTTP listener redirecting every request to the same URL over HTTPS on `https_port`, requires `tls`\n\
+         #http_redirect = { host = \"0.0.0.0\", port = 80, https_port = 443 }",
+    ),
+    (
+        "server",
+        "# Unix domain socket to listen on, e.g. for a local reverse proxy, only on Unix. TLS is never used on it.\n\
+         # `mode` sets the permissions of the socket file, `only` disables listening on `host` and `port`.\n\
+         #unix_socket = { path = \"/run/tiny-cloud/tiny-cloud.sock\", mode = 0o660, only = false }",
+    ),
+    (
+        "logging",
+        "# File logs are written to, its parent directory must exist\n\
+         #file = \"/var/log/tiny-cloud.log\"\n\
+         # Level of the logs written to the file, defaults to `stdout_level`\n\
+         #file_level = \"warn\"",
+    ),
+    #[cfg(feature = "syslog")]
+    (
+        "logging",
+        "# Level of the logs sent to syslog, defaults to `stdout_level`\n\
+         #syslog_level = \"warn\"",
+    ),
+    (
+        "limits",
+        "# Storage quota in bytes of users without their own, remove it for no limit\n\
+         #default_quota = 10000000000",
+    ),
+];
+
+/// Optional tables that are not set by default, written commented out at the end of the config
+const OPTIONAL_TABLES: &str = "\
+# Keeps sessions in the database instead of cookies, so that they can be killed immediately.
+#[session_store]
+# Minutes between deletions of expired sessions, at least 1
+#sweep_minutes = 60
+
+# Passkey logins, available only with feature \"webauthn\"
+#[webauthn]
+# Domain passkeys are bound to
+#rp_id = \"cloud.example.com\"
+# Origin of the server as seen by browsers, starting with https:// or http://
+#origin = \"https://cloud.example.com\"
+# What a passkey replaces when logging in: \"totp\" or \"password\"
+#replaces = \"totp\"
+
+# Certificates obtained and renewed automatically, available only with feature \"acme\".
+# It replaces the `tls` table, the certificate and the keys are stored under `data_directory`.
+# Using it means agreeing to the terms of service of the ACME server.
+#[acme]
+# Domains the certificate is for, all pointing to this server. Wildcards are not supported.
+#domains = [\"cloud.example.com\"]
+# Contacts of the account, optional
+#contact = [\"mailto:admin@example.com\"]
+# Directory of the ACME server, e.g. the staging one of Let's Encrypt for testing:
+# \"https://acme-staging-v02.api.letsencrypt.org/directory\"
+#directory_url = \"https://acme-v02.api.letsencrypt.org/directory\"
+# \"http-01\" needs plain HTTP on port 80, e.g. with `server.http_redirect`.
+# \"tls-alpn-01\" needs TLS on port 443.
+#challenge = \"http-01\"
+# Root certificate trusted for the ACME server instead of the usual ones, e.g. the one of a local pebble server
+#ca_cert_path = \"/path/to/pebble.minica.pem\"
+# Days before expiration at which the certificate is renewed, at least 1
+#renew_days = 30
+
+# Protocol versions, cipher suites and extensions of TLS handshakes, for both `tls` and `acme`.
+# Without it, openssl follows an older Mozilla profile without TLS 1.3 and rustls accepts TLS 1.2 and 1.3.
+#[tls_policy]
+# Server side TLS recommendations of Mozilla: \"modern\" (TLS 1.3 only) or \"intermediate\" (TLS 1.2 and 1.3)
+#profile = \"intermediate\"
+# Lowest protocol version accepted, \"1.2\" or \"1.3\", defaults to the one of the profile
+#min_version = \"1.3\"
+# Cipher suites accepted, by their IANA names, whatever the backend.
+# Listing some of a protocol version replaces the profile's ones for it.
+#ciphers = [\"TLS_AES_256_GCM_SHA384\", \"TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384\"]
+# openssl builds only: whether HTTP/2 is offered to clients, defaults to true.
+# rustls builds always offer HTTP/2, as actix-web puts `h2` first in their ALPN protocols,
+# so they refuse to start if this is false.
+#http2 = true
+# OCSP response in DER format stapled to handshakes, e.g. fetched periodically with `openssl ocsp`.
+# It is reloaded along with the certificate.
+#ocsp_path = \"/path/to/ocsp.der\"
+
+# Client certificates requested during TLS handshakes, e.g. for devices like backup scripts
+#[client_auth]
+# CA certificates in PEM format, client certificates must be signed by one of them
+#ca_path = \"/path/to/clients-ca.pem\"
+# Whether connections without a valid client certificate are refused
+#required = false
+# Name of the certificate holding the username, \"cn\" or \"san\" (its first DNS name).
+# Its owner can then login with POST /api/auth/certificate, without password or TOTP token.
+#login = \"cn\"
+
+# Secret keys replaced by a rotation with `--gen-secret`, which prints this table.
+# Cookies encrypted with them are accepted until `expires`, in seconds since the Unix epoch.
+#[[previous_secret_keys]]
+#path = \"/path/to/secret.key.1700000000.old\"
+#expires = 1702592000
+";
+
+fn comment(out: &mut String, text: &str) {
+    for line in text.lines() {
+        out.push_str("# ");
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+fn examples(out: &mut String, table: &str) {
+    for (_, example) in EXAMPLES.iter().filter(|(t, _)| *t == table) {
+        out.push_str(example);
+        out.push('\n');
+    }
+}
+
+/// Removes the blank lines at the end, so that examples are written right after the keys of their table
+fn trim_end(out: &mut String) {
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+}
+
+/// Serializes the config with a comment describing each key, its unit and the values it accepts.
+/// Optional keys and tables that are not set are written commented out.
+pub fn documented(config: &Config) -> Result<String, String> {
+    let serialized = toml::to_string(config).map_err(|e| format!("Failed to serialize config: {e}"))?;
+    let mut out = String::new();
+    comment(
+        &mut out,
+        "Configuration of Tiny Cloud.\n\
+         Every key can be overridden by an environment variable, e.g. `TINYCLOUD_SERVER__PORT=8080` sets `server.port`.\n\
+         Check it with `--check-config`.",
+    );
+    out.push('\n');
+
+    let mut table = String::new();
+    for line in serialized.lines() {
+        let header = line
+            .strip_prefix('[')
+            .and_then(|l| l.strip_suffix(']'))
+            .map(|h| h.trim_matches(['[', ']']));
+        if let Some(header) = header {
+            trim_end(&mut out);
+            examples(&mut out, &table);
+            out.push('\n');
+            table = header.to_string();
+            match header.strip_prefix("plugins.") {
+                Some(name) => {
+                    let description = plugins::list().iter().find(|p| p.name == name).map_or("", |p| p.description);
+                    comment(&mut out, &format!("Defaults of plugin `{name}`: {description}"));
+                }
+                None => {
+                    if let Some((_, doc)) = DOCS.iter().find(|(key, _)| *key == header) {
+                        comment(&mut out, doc);
+                    }
+                }
+            }
+        } else if let Some((key, _)) = line.split_once(" = ") {
+            let path = if table.is_empty() {
+                key.to_string()
+            } else {
+                format!("{table}.{key}")
+            };
+            if let Some((_, doc)) = DOCS.iter().find(|(k, _)| *k == path) {
+                comment(&mut out, doc);
+            }
+        }
+        if !line.is_empty() {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    trim_end(&mut out);
+    examples(&mut out, &table);
+    out.push('\n');
+    out.push_str(OPTIONAL_TABLES);
+    Ok(out)
+}
@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use common_library::{Toml, toml::Table};
+use std::env;
+use std::ffi::OsString;
+
+/// Prefix of the variables overriding a config key, e.g. `TINYCLOUD_SERVER__PORT=8080`
+const PREFIX: &str = "TINYCLOUD_";
+/// Prefix of the variables giving a file to read the value of a key from, e.g.
+/// `TINYCLOUD_FILE_PLUGINS__NAME__PASSWORD=/run/secrets/password`. No top-level key starts with `file_`.
+const FILE_PREFIX: &str = "TINYCLOUD_FILE_";
+/// Separates the levels of nested keys, since keys themselves contain single underscores
+const SEPARATOR: &str = "__";
+
+/// A value taken from the environment, along with the variable it comes from
+pub struct Override {
+    var: String,
+    path: Vec<String>,
+    value: Toml,
+}
+
+/// Path of the key a variable refers to, e.g. `LIMITS__FILE_UPLOAD_SIZE` is `["limits", "file_upload_size"]`
+fn key_path(var: &str, key: &str) -> Result<Vec<String>, String> {
+    let path: Vec<String> = key.split(SEPARATOR).map(str::to_lowercase).collect();
+    if path.iter().any(String::is_empty) {
+        return Err(format!("Environment variable `{var}` does not name a valid config key"));
+    }
+    Ok(path)
+}
+
+/// Collects the overrides of the environment, sorted so that a table is set before the keys it contains.
+/// Values are parsed as TOML, e.g. `8080`, `true` or `["a", "b"]`, and taken as strings when they are not valid TOML.
+/// A string that looks like another type must be quoted, e.g. `'"8080"'`.
+/// Values read from files are always strings, without their trailing newline. Secrets that are already files,
+/// like the session secret key, are overridden by giving their path, e.g. `TINYCLOUD_SESSION_SECRET_KEY_PATH`.
+pub fn overrides() -> Result<Vec<Override>, String> {
+    overrides_of(env::vars_os())
+}
+
+/// Collects the overrides among the given variables
+fn overrides_of(vars: impl Iterator<Item = (OsString, OsString)>) -> Result<Vec<Override>, String> {
+    let mut overrides = Vec::new();
+    for (var, value) in vars {
+        let Some(var) = var.to_str().filter(|v| v.starts_with(PREFIX)).map(str::to_string) else {
+            continue;
+        };
+        let value = value
+            .into_string()
+            .map_err(|_| format!("Environment variable `{var}` is not valid unicode"))?;
+        let (path, value) = match var.strip_prefix(FILE_PREFIX) {
+            Some(key) => {
+                let content = std::fs::read_to_string(&value)
+                    .map_err(|e| format!("Failed to read file `{value}` given by environment variable `{var}`: {e}"))?;
+                let content = content.strip_suffix('\n').unwrap_or(&content);
+                let content = content.strip_suffix('\r').unwrap_or(content);
+                (key_path(&var, key)?, Toml::String(content.to_string()))
+            }
+            None => (
+                key_path(&var, &var[PREFIX.len()..])?,
+                value.parse::<Toml>().unwrap_or(Toml::String(value)),
+            ),
+        };
+        overrides.push(Override { var, path, value });
+    }
+    overrides.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(overrides)
+}
+
+/// Sets the overridden keys in the config, creating the tables that are missing.
+pub fn apply(config: &mut Table, overrides: Vec<Override>) -> Result<(), String> {
+    for Override { var, mut path, value } in overrides {
+        let Some(key) = path.pop() else {
+            continue;
+        };
+        let mut table = &mut *config;
+        for (i, name) in path.iter().enumerate() {
+            table = match table.entry(name.clone()).or_insert_with(|| Toml::Table(Table::new())) {
+                Toml::Table(table) => table,
+                _ => {
+                    return Err(format!(
+                        "Environment variable `{var}` can't override a key of `{}`, it is not a table",
+                        path[..=i].join(".")
+                    ));
+                }
+            };
+        }
+        table.insert(key, value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> impl Iterator<Item = (OsString, OsString)> {
+        vars.iter()
+            .map(|(var, value)| (OsString::from(var), OsString::from(value)))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn applied(config: &str, env: &[(&str, &str)]) -> Result<Table, String> {
+        let mut config: Table = config.parse().unwrap();
+        apply(&mut config, overrides_of(vars(env))?)?;
+        Ok(config)
+    }
+
+    #[test]
+    fn keys_are_split_on_double_underscores() {
+        assert_eq!(key_path("", "LIMITS__FILE_UPLOAD_SIZE").unwrap(), ["limits", "file_upload_size"]);
+        assert_eq!(key_path("", "SERVER_NAME").unwrap(), ["server_name"]);
+        assert!(key_path("", "").is_err());
+        assert!(key_path("", "LIMITS____FILE_UPLOAD_SIZE").is_err());
+        assert!(key_path("", "LIMITS__").is_err());
+    }
+
+    #[test]
+    fn values_are_parsed_as_toml() {
+        let config = applied(
+            "server_name = \"cloud\"\n[limits]\nfile_upload_size = 1\nfile_upload_timeout = 2\n",
+            &[
+                ("TINYCLOUD_LIMITS__FILE_UPLOAD_SIZE", "1000"),
+                ("TINYCLOUD_SERVER_NAME", "my cloud"),
+                ("TINYCLOUD_URL_PREFIX", "'\"8080\"'"),
+                ("TINYCLOUD_SERVER__ADDRESSES", "[\"::1\", \"127.0.0.1\"]"),
+                ("TINY_CLOUD_SERVER_NAME", "ignored"),
+                ("SERVER_NAME", "ignored"),
+            ],
+        )
+        .unwrap();
+        let expected: Table = "server_name = \"my cloud\"\nurl_prefix = \"\\\"8080\\\"\"\n\
+            [limits]\nfile_upload_size = 1000\nfile_upload_timeout = 2\n\
+            [server]\naddresses = [\"::1\", \"127.0.0.1\"]\n"
+            .parse()
+            .unwrap();
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn tables_are_set_before_their_keys() {
+        let config = applied(
+            "",
+            &[
+                ("TINYCLOUD_TLS__KEY_PATH", "key.pem"),
+                ("TINYCLOUD_TLS", "{ cert_path = \"cert.pem\" }"),
+            ],
+        )
+        .unwrap();
+        let expected: Table = "[tls]\ncert_path = \"cert.pem\"\nkey_path = \"key.pem\"\n".parse().unwrap();
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn keys_of_values_are_refused() {
+        assert!(applied("server_name = \"cloud\"", &[("TINYCLOUD_SERVER_NAME__FIRST", "my")]).is_err());
+        assert!(applied("", &[("TINYCLOUD_", "1")]).is_err());
+    }
+
+    #[test]
+    fn files_are_read_as_strings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("password");
+        std::fs::write(&path, "1234\r\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let config = applied("", &[("TINYCLOUD_FILE_PLUGINS__NAME__PASSWORD", path)]).unwrap();
+        let expected: Table = "[plugins.name]\npassword = \"1234\"\n".parse().unwrap();
+        assert_eq!(config, expected);
+
+        let missing = dir.path().join("missing");
+        assert!(applied("", &[("TINYCLOUD_FILE_PLUGINS__NAME__PASSWORD", missing.to_str().unwrap())]).is_err());
+    }
+}
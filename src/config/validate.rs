@@ -0,0 +1,301 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::{AcmeChallenge, Config, TlsProfile, TlsVersion};
+use crate::plugins;
+use std::path::Path;
+
+/// Whether the directory a path would be created in exists
+fn parent_exists(path: &str) -> bool {
+    Path::new(path)
+        .parent()
+        .is_none_or(|parent| parent.as_os_str().is_empty() || parent.is_dir())
+}
+
+/// Checks the values that deserializing can't, so that they don't fail later at runtime.
+/// Returns every problem found, each one prefixed with the path of its key.
+pub fn validate(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut check = |ok: bool, key: &str, msg: String| {
+        if !ok {
+            problems.push(format!("`{key}`: {msg}"));
+        }
+    };
+
+    check(!config.server.host.is_empty(), "server.host", "must not be empty".into());
+    check(config.server.workers > 0, "server.workers", "must be at least 1".into());
+    let tls_msg = if cfg!(feature = "no-tls") {
+        "TLS is not available in this build"
+    } else {
+        "requires the `tls` or `acme` table"
+    };
+    for (i, address) in config.server.listeners.iter().flatten().enumerate() {
+        check(
+            !address.host.is_empty(),
+            &format!("server.listeners[{i}].host"),
+            "must not be empty".into(),
+        );
+        check(
+            address.tls != Some(true) || config.has_tls(),
+            &format!("server.listeners[{i}].tls"),
+            tls_msg.into(),
+        );
+    }
+    if let Some(redirect) = &config.server.http_redirect {
+        check(config.has_tls(), "server.http_redirect", tls_msg.into());
+        check(!redirect.host.is_empty(), "server.http_redirect.host", "must not be empty".into());
+        check(
+            redirect.https_port != Some(0),
+            "server.http_redirect.https_port",
+            "must be 1-65535".into(),
+        );
+    }
+    if let Some(socket) = &config.server.unix_socket {
+        check(
+            cfg!(unix),
+            "server.unix_socket",
+            "Unix domain sockets are only available on Unix".into(),
+        );
+        check(!socket.path.is_empty(), "server.unix_socket.path", "must not be empty".into());
+        check(
+            parent_exists(&socket.path),
+            "server.unix_socket.path",
+            format!("parent directory of `{}` does not exist", socket.path),
+        );
+        if let Some(mode) = socket.mode {
+            check(
+                mode <= 0o777,
+                "server.unix_socket.mode",
+                format!("{mode:#o} is not a valid mode, it must be at most 0o777"),
+            );
+        }
+    }
+
+    check(!config.data_directory.is_empty(), "data_directory", "must not be empty".into());
+    check(
+        parent_exists(&config.data_directory),
+        "data_directory",
+        format!("parent directory of `{}` does not exist", config.data_directory),
+    );
+    check(
+        parent_exists(&config.session_secret_key_path),
+        "session_secret_key_path",
+        format!("parent directory of `{}` does not exist", config.session_secret_key_path),
+    );
+    for (i, key) in config.previous_secret_keys.iter().flatten().enumerate() {
+        check(
+            !key.path.is_empty(),
+            &format!("previous_secret_keys[{i}].path"),
+            "must not be empty".into(),
+        );
+    }
+    if let Some(store) = &config.session_store {
+        check(store.sweep_minutes > 0, "session_store.sweep_minutes", "must be at least 1".into());
+    }
+
+    let levels = [
+        ("logging.stdout_level", Some(config.logging.stdout_level.as_str())),
+        ("logging.file_level", config.logging.file_level.as_deref()),
+        #[cfg(feature = "syslog")]
+        ("logging.syslog_level", config.logging.syslog_level.as_deref()),
+    ];
+    for (key, level) in levels {
+        if let Some(Err(e)) = level.map(crate::log_filter) {
+            check(false, key, e);
+        }
+    }
+    if let Some(file) = &config.logging.file {
+        check(
+            parent_exists(file),
+            "logging.file",
+            format!("parent directory of `{file}` does not exist"),
+        );
+    }
+
+    if let Some(registration) = &config.registration {
+        check(registration.token_size > 0, "registration.token_size", "must be at least 1".into());
+        check(
+            registration.token_duration_seconds > 0,
+            "registration.token_duration_seconds",
+            "must be at least 1".into(),
+        );
+    }
+
+    if let Some(throttle) = &config.login_throttle {
+        check(
+            throttle.window_seconds > 0,
+            "login_throttle.window_seconds",
+            "must be at least 1".into(),
+        );
+        check(
+            throttle.max_ip_attempts > 0,
+            "login_throttle.max_ip_attempts",
+            "must be at least 1".into(),
+        );
+        check(
+            throttle.max_user_attempts > 0,
+            "login_throttle.max_user_attempts",
+            "must be at least 1".into(),
+        );
+        check(
+            throttle.lockout_seconds <= throttle.max_lockout_seconds,
+            "login_throttle.lockout_seconds",
+            format!(
+                "must not be greater than `login_throttle.max_lockout_seconds` ({} > {})",
+                throttle.lockout_seconds, throttle.max_lockout_seconds
+            ),
+        );
+    }
+
+    if let Some(api_tokens) = &config.api_tokens {
+        check(api_tokens.max_days > 0, "api_tokens.max_days", "must be at least 1".into());
+    }
+
+    if let Some(webauthn) = &config.webauthn {
+        check(
+            cfg!(feature = "webauthn"),
+            "webauthn",
+            "WebAuthn is only available with feature \"webauthn\"".into(),
+        );
+        check(!webauthn.rp_id.is_empty(), "webauthn.rp_id", "must not be empty".into());
+        check(
+            webauthn.origin.starts_with("https://") || webauthn.origin.starts_with("http://"),
+            "webauthn.origin",
+            format!("`{}` must start with `https://` or `http://`", webauthn.origin),
+        );
+    }
+
+    if let Some(acme) = &config.acme {
+        check(
+            cfg!(feature = "acme"),
+            "acme",
+            "ACME is only available with feature \"acme\"".into(),
+        );
+        #[cfg(not(feature = "no-tls"))]
+        check(
+            config.tls.is_none(),
+            "acme",
+            "can't be set along with `tls`, remove one of them".into(),
+        );
+        check(!acme.domains.is_empty(), "acme.domains", "must not be empty".into());
+        for (i, domain) in acme.domains.iter().enumerate() {
+            check(!domain.is_empty(), &format!("acme.domains[{i}]"), "must not be empty".into());
+            check(
+                !domain.starts_with("*."),
+                &format!("acme.domains[{i}]"),
+                format!("`{domain}` is a wildcard, which needs the unsupported DNS-01 challenge"),
+            );
+        }
+        check(
+            acme.directory_url.starts_with("https://"),
+            "acme.directory_url",
+            format!("`{}` must start with `https://`", acme.directory_url),
+        );
+        if let Some(path) = &acme.ca_cert_path {
+            check(Path::new(path).is_file(), "acme.ca_cert_path", format!("`{path}` is not a file"));
+        }
+        check(acme.renew_days > 0, "acme.renew_days", "must be at least 1".into());
+    }
+
+    if let Some(policy) = &config.tls_policy {
+        check(config.has_tls(), "tls_policy", tls_msg.into());
+        check(
+            policy.profile != TlsProfile::Modern || policy.min_version != Some(TlsVersion::Tls12),
+            "tls_policy.min_version",
+            "the \"modern\" profile only accepts TLS 1.3".into(),
+        );
+        if let Some(ciphers) = &policy.ciphers {
+            check(
+                !ciphers.is_empty(),
+                "tls_policy.ciphers",
+                "must not be empty, remove it to use the profile's ones".into(),
+            );
+            for (i, name) in ciphers.iter().enumerate() {
+                check(
+                    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                    &format!("tls_policy.ciphers[{i}]"),
+                    format!("`{name}` is not an IANA cipher suite name, e.g. \"TLS_AES_128_GCM_SHA256\""),
+                );
+            }
+        }
+        check(
+            policy.http2 != Some(false) || !cfg!(feature = "rustls"),
+            "tls_policy.http2",
+            "HTTP/2 can only be disabled in openssl builds, rustls ones always offer it".into(),
+        );
+        if let Some(path) = &policy.ocsp_path {
+            check(Path::new(path).is_file(), "tls_policy.ocsp_path", format!("`{path}` is not a file"));
+        }
+    }
+
+    if let Some(client_auth) = &config.client_auth {
+        check(config.has_tls(), "client_auth", tls_msg.into());
+        check(
+            Path::new(&client_auth.ca_path).is_file(),
+            "client_auth.ca_path",
+            format!("`{}` is not a file", client_auth.ca_path),
+        );
+        check(
+            !client_auth.required || config.acme.as_ref().is_none_or(|a| a.challenge != AcmeChallenge::TlsAlpn01),
+            "client_auth.required",
+            "can't be set with the \"tls-alpn-01\" ACME challenge, the ACME server has no client certificate".into(),
+        );
+    }
+
+    check(
+        config.limits.file_upload_size > 0,
+        "limits.file_upload_size",
+        "must be at least 1".into(),
+    );
+    check(config.limits.payload_size > 0, "limits.payload_size", "must be at least 1".into());
+    check(
+        config.limits.partial_upload_minutes != Some(0),
+        "limits.partial_upload_minutes",
+        "must be at least 1, remove it to keep partial uploads forever".into(),
+    );
+
+    check(
+        config.duration.cookie_minutes > 0,
+        "duration.cookie_minutes",
+        "must be at least 1".into(),
+    );
+    check(
+        config.duration.login_minutes != Some(0),
+        "duration.login_minutes",
+        "must be at least 1, remove it to disable the deadline".into(),
+    );
+    check(
+        config.duration.visit_minutes != Some(0),
+        "duration.visit_minutes",
+        "must be at least 1, remove it to disable the deadline".into(),
+    );
+
+    let cred_size = &config.cred_size;
+    check(cred_size.min_username > 0, "cred_size.min_username", "must be at least 1".into());
+    check(
+        cred_size.min_username <= cred_size.max_username,
+        "cred_size.min_username",
+        format!(
+            "must not be greater than `cred_size.max_username` ({} > {})",
+            cred_size.min_username, cred_size.max_username
+        ),
+    );
+    check(cred_size.min_passwd > 0, "cred_size.min_passwd", "must be at least 1".into());
+    check(
+        cred_size.min_passwd <= cred_size.max_passwd,
+        "cred_size.min_passwd",
+        format!(
+            "must not be greater than `cred_size.max_passwd` ({} > {})",
+            cred_size.min_passwd, cred_size.max_passwd
+        ),
+    );
+
+    for name in config.plugins.keys() {
+        check(
+            plugins::list().iter().any(|p| p.name == name),
+            &format!("plugins.{name}"),
+            "no plugin with this name is included in this build".into(),
+        );
+    }
+
+    problems
+}
@@ -1,25 +1,21 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+pub mod api_token;
 pub mod auth;
 pub mod error;
+mod migrations;
+pub mod session;
 pub mod token;
 pub mod utils;
+#[cfg(feature = "webauthn")]
+pub mod webauthn;
 use crate::{config, plugins};
 use async_sqlite::{JournalMode, Pool, PoolBuilder};
-use auth::{USERS_TABLE, get_all_usernames};
+use auth::get_all_usernames;
 use error::DBError;
 use std::path::PathBuf;
-use token::TOKEN_TABLE;
 use tokio::fs;
 
-fn tables() -> String {
-    if config!(registration).is_some() {
-        format!("BEGIN;{USERS_TABLE};{TOKEN_TABLE};COMMIT;")
-    } else {
-        format!("BEGIN;{USERS_TABLE};COMMIT;")
-    }
-}
-
 async fn create_user_dir(user: &str) -> Result<(), DBError> {
     let mut path = PathBuf::from(config!(data_directory));
     path.push("users");
@@ -56,6 +52,7 @@ pub async fn init() -> Result<Pool, DBError> {
 
     // Open/Create database
     data_path.push("auth.db");
+    let existed = fs::try_exists(&data_path).await.unwrap_or(true);
     let pool = PoolBuilder::new()
         .journal_mode(JournalMode::Wal)
         .path(&data_path)
@@ -64,10 +61,8 @@ pub async fn init() -> Result<Pool, DBError> {
         .map_err(|e| DBError::IOError(e.to_string()))?;
     data_path.pop();
 
-    // Crates tables if they do not exist yet
-    pool.conn(|conn| conn.execute_batch(&tables()))
-        .await
-        .map_err(|e| DBError::ExecError(format!("Failed to initialize tables: {e}")))?;
+    // Creates or updates tables
+    migrations::migrate(&pool, &data_path, existed).await?;
 
     // Crates directories for all of the users if they do not exist yet
     for user in get_all_usernames(&pool).await? {
@@ -86,3 +81,21 @@ pub async fn init() -> Result<Pool, DBError> {
 
     Ok(pool)
 }
+
+/// Opens an empty database in memory with the latest schema, along with the default config and no plugins.
+/// Used by tests.
+#[cfg(test)]
+pub async fn memory() -> Pool {
+    config::use_default();
+    plugins::use_none();
+    let pool = PoolBuilder::new()
+        .path(":memory:")
+        .num_conns(1)
+        .open()
+        .await
+        .expect("Failed to open database in memory");
+    migrations::migrate(&pool, std::path::Path::new(""), false)
+        .await
+        .expect("Failed to migrate database in memory");
+    pool
+}
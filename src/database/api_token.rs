@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::{error::DBError, utils::now};
+use async_sqlite::{
+    Pool,
+    rusqlite::{OptionalExtension, params},
+};
+
+/// Seconds between updates of the last time a token was used, to avoid writing on every request
+const LAST_USED_INTERVAL: u64 = 60;
+
+/// An API token as listed to its user, without its hash.
+pub struct ApiToken {
+    pub id: i64,
+    /// Name given by the user
+    pub name: String,
+    /// Scopes separated by spaces
+    pub scopes: String,
+    /// Creation time in seconds since the Unix epoch
+    pub created: u64,
+    /// Expiration time in seconds since the Unix epoch
+    pub expires: u64,
+    /// Last request time in seconds since the Unix epoch, updated once a minute at most
+    pub last_used: Option<u64>,
+}
+
+/// User a valid token belongs to.
+pub struct Owner {
+    pub username: String,
+    pub is_admin: bool,
+    /// Scopes separated by spaces
+    pub scopes: String,
+}
+
+/// Saves a new token of the user. Expired tokens of all users are deleted in the meantime.
+pub async fn create(
+    pool: &Pool,
+    username: String,
+    name: String,
+    token_hash: String,
+    scopes: String,
+    expires: u64,
+) -> Result<(), DBError> {
+    let now = now()?;
+    pool.conn(move |conn| {
+        conn.execute("DELETE FROM api_tokens WHERE expires<=?1", [now])?;
+        conn.execute(
+            "INSERT INTO api_tokens (username, name, token_hash, scopes, created, expires) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![username, name, token_hash, scopes, now, expires],
+        )
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to create API token: {e}")))?;
+    Ok(())
+}
+
+/// Returns the user of a token that has not expired and records that it was used.
+/// If the token is not valid returns [`None`].
+pub async fn check(pool: &Pool, token_hash: String) -> Result<Option<Owner>, DBError> {
+    let now = now()?;
+    pool.conn(move |conn| {
+        let found = conn
+            .query_row(
+                "SELECT id, username, is_admin, scopes, last_used FROM api_tokens JOIN users USING (username)
+                 WHERE token_hash=?1 AND expires>?2",
+                params![token_hash, now],
+                |row| {
+                    Ok((
+                        row.get::<usize, i64>(0)?,
+                        Owner {
+                            username: row.get(1)?,
+                            is_admin: row.get(2)?,
+                            scopes: row.get(3)?,
+                        },
+                        row.get::<usize, Option<u64>>(4)?,
+                    ))
+                },
+            )
+            .optional()?;
+        match found {
+            Some((id, owner, last_used)) => {
+                if last_used.is_none_or(|last_used| last_used + LAST_USED_INTERVAL <= now) {
+                    conn.execute("UPDATE api_tokens SET last_used=?1 WHERE id=?2", params![now, id])?;
+                }
+                Ok(Some(owner))
+            }
+            None => Ok(None),
+        }
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to check API token: {e}")))
+}
+
+/// Returns the tokens of the user that have not expired, ordered from the most recent
+pub async fn list(pool: &Pool, username: String) -> Result<Vec<ApiToken>, DBError> {
+    let now = now()?;
+    pool.conn(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, name, scopes, created, expires, last_used FROM api_tokens WHERE username=?1 AND expires>?2
+             ORDER BY created DESC",
+        )?;
+        let rows = stmt.query_map(params![username, now], |row| {
+            Ok(ApiToken {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                scopes: row.get(2)?,
+                created: row.get(3)?,
+                expires: row.get(4)?,
+                last_used: row.get(5)?,
+            })
+        })?;
+        rows.collect()
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to list API tokens: {e}")))
+}
+
+/// Deletes a token of the user. Returns false if it does not exist.
+pub async fn delete(pool: &Pool, username: String, id: i64) -> Result<bool, DBError> {
+    pool.conn(move |conn| conn.execute("DELETE FROM api_tokens WHERE username=?1 AND id=?2", params![username, id]))
+        .await
+        .map(|changes| changes > 0)
+        .map_err(|e| DBError::ExecError(format!("Failed to delete API token: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{auth, memory};
+
+    async fn hashes(pool: &Pool) -> Vec<String> {
+        pool.conn(|conn| {
+            conn.prepare("SELECT token_hash FROM api_tokens ORDER BY id")?
+                .query_map([], |row| row.get(0))?
+                .collect()
+        })
+        .await
+        .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn create_prunes_expired_tokens() {
+        let pool = memory().await;
+        auth::add_user(&pool, "alice".into(), String::new(), String::new(), false)
+            .await
+            .unwrap();
+        let now = now().unwrap();
+        for (hash, expires) in [("expired", now - 1), ("expiring", now), ("valid", now + 60)] {
+            pool.conn(move |conn| {
+                conn.execute(
+                    "INSERT INTO api_tokens (username, name, token_hash, scopes, created, expires) VALUES ('alice', '', ?1, '', 0, ?2)",
+                    params![hash, expires],
+                )
+            })
+            .await
+            .unwrap();
+        }
+        assert!(check(&pool, "expiring".into()).await.unwrap().is_none());
+        assert_eq!(list(&pool, "alice".into()).await.unwrap().len(), 1);
+
+        create(&pool, "alice".into(), "new".into(), "new".into(), "quota".into(), now + 60)
+            .await
+            .unwrap();
+        assert_eq!(hashes(&pool).await, ["valid", "new"]);
+    }
+
+    #[actix_web::test]
+    async fn check_records_the_last_use() {
+        let pool = memory().await;
+        auth::add_user(&pool, "alice".into(), String::new(), String::new(), false)
+            .await
+            .unwrap();
+        create(
+            &pool,
+            "alice".into(),
+            "script".into(),
+            "hash".into(),
+            "quota".into(),
+            now().unwrap() + 60,
+        )
+        .await
+        .unwrap();
+        assert_eq!(list(&pool, "alice".into()).await.unwrap()[0].last_used, None);
+
+        let owner = check(&pool, "hash".into()).await.unwrap().unwrap();
+        assert_eq!((owner.username.as_str(), owner.scopes.as_str()), ("alice", "quota"));
+        assert!(list(&pool, "alice".into()).await.unwrap()[0].last_used.is_some());
+    }
+}
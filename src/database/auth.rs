@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use super::error::DBError;
+use super::{error::DBError, session, utils::now};
 use async_sqlite::{
     Error, Pool,
     rusqlite::{self, ErrorCode, OptionalExtension, named_params, params},
@@ -9,27 +9,25 @@ use async_sqlite::{
 /// Authentication data of a user.
 #[non_exhaustive]
 pub struct UserAuth {
-    /// Username and id used as session identity. Formatted as "USERNAME:ID"
-    pub userid: String,
+    pub username: String,
     /// Password's hash of the user.
     pub pass_hash: String,
     /// TOTP secret of the user. Enabled only with feature "totp-auth".
     pub totp: String,
 }
 
-pub const USERS_TABLE: &str = "CREATE TABLE IF NOT EXISTS users (
-    username    TEXT    UNIQUE NOT NULL,
-    sessionid   BIGINT  UNIQUE NOT NULL,
-    pass_hash   TEXT    NOT NULL,
-    totp        TEXT    NOT NULL,
-    is_admin    INTEGER DEFAULT 0
-)";
+/// A user as listed to admins.
+pub struct UserEntry {
+    pub username: String,
+    pub is_admin: bool,
+    /// Storage quota in bytes, None if the default one applies.
+    pub quota: Option<u64>,
+}
 
 /// Adds a new user to the database, fails if it already exists.
-/// Returns a string containing the username and the session id of the user (called userid),
-/// formatted as "USERNAME:SESSION_ID".
-pub async fn add_user(pool: &Pool, username: String, pass_hash: String, totp: String, is_admin: bool) -> Result<String, DBError> {
+pub async fn add_user(pool: &Pool, username: String, pass_hash: String, totp: String, is_admin: bool) -> Result<(), DBError> {
     let username_clone = username.clone();
+    // Sessions are stored in their own table, the column is kept only for older databases
     let sessionid: i64 = rand::random();
     pool.conn(move |conn| {
         conn.execute(
@@ -57,23 +55,19 @@ pub async fn add_user(pool: &Pool, username: String, pass_hash: String, totp: St
     log::info!("Added a new user: '{username}'");
     super::create_user_dir(&username).await?;
     log::info!("Created new user dir for '{username}'");
-    Ok(format!("{username}:{sessionid}"))
+    Ok(())
 }
 
 /// Returns a user's authentication data as a [`UserAuth`].
 pub async fn get_auth(pool: &Pool, username: String) -> Result<Option<UserAuth>, DBError> {
     pool.conn(|conn| {
-        conn.query_row(
-            "SELECT username, sessionid, pass_hash, totp FROM users WHERE username=?1",
-            [username],
-            |row| {
-                Ok(UserAuth {
-                    userid: format!("{}:{}", row.get::<usize, String>(0)?, row.get::<usize, i64>(1)?),
-                    pass_hash: row.get(2)?,
-                    totp: row.get(3)?,
-                })
-            },
-        )
+        conn.query_row("SELECT username, pass_hash, totp FROM users WHERE username=?1", [username], |row| {
+            Ok(UserAuth {
+                username: row.get(0)?,
+                pass_hash: row.get(1)?,
+                totp: row.get(2)?,
+            })
+        })
         .optional()
     })
     .await
@@ -91,10 +85,12 @@ pub fn unpack(userid: String) -> Result<(String, i64), DBError> {
 
 /// Returns password hash from userid, if userid is not valid returns [`None`].
 pub async fn get_passhash(pool: &Pool, username: String, sessionid: i64) -> Result<Option<String>, DBError> {
+    let (idle_limit, login_limit) = session::limits(now()?);
     pool.conn(move |conn| {
         conn.query_row(
-            "SELECT pass_hash FROM users WHERE username=?1 AND sessionid=?2",
-            params![username, sessionid],
+            "SELECT pass_hash FROM users JOIN sessions USING (username)
+             WHERE username=?1 AND sessions.sessionid=?2 AND last_seen>=?3 AND created>=?4",
+            params![username, sessionid, idle_limit, login_limit],
             |row| row.get(0),
         )
         .optional()
@@ -103,38 +99,23 @@ pub async fn get_passhash(pool: &Pool, username: String, sessionid: i64) -> Resu
     .map_err(|e| DBError::ExecError(format!("Failed to get passhash from userid: {e}")))
 }
 
-/// Returns username and user admin status from userid.
+/// Returns username and user admin status from userid and records that its session was used.
 /// If the userid is not valid returns [`None`].
 pub async fn userinfo(pool: &Pool, userid: String) -> Result<Option<(String, bool)>, DBError> {
     let (username, sessionid) = unpack(userid)?;
+    if !session::touch(pool, username.clone(), sessionid).await? {
+        return Ok(None);
+    }
     pool.conn(move |conn| {
-        conn.query_row(
-            "SELECT username, is_admin FROM users WHERE username=?1 AND sessionid=?2",
-            params![username, sessionid],
-            |row| Ok((row.get(0)?, row.get(1)?)),
-        )
+        conn.query_row("SELECT username, is_admin FROM users WHERE username=?1", [username], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
         .optional()
     })
     .await
     .map_err(|e| DBError::ExecError(format!("Failed to get user: {e}")))
 }
 
-/// Generates a new session id. Since the user must login again, the function doesn't return
-/// the new userid. Returns error if the userid is not valid.
-pub async fn change_sessionid(pool: &Pool, userid: String) -> Result<(), DBError> {
-    let (username, old_sessionid) = unpack(userid)?;
-    let new_sessionid: i64 = rand::random();
-    pool.conn(move |conn| {
-        conn.execute(
-            "UPDATE users SET sessionid=?1 WHERE username=?2 AND sessionid=?3",
-            params![new_sessionid, username, old_sessionid],
-        )
-    })
-    .await
-    .map_err(|e| DBError::ExecError(format!("Failed to change session id: {e}")))
-    .and_then(|changes| if changes == 0 { Err(DBError::InvalidUserID) } else { Ok(()) })
-}
-
 /// Changes password's hash of the selected user
 pub async fn change_passhash(pool: &Pool, username: String, new_pwdhash: String) -> Result<(), DBError> {
     pool.conn(|conn| conn.execute("UPDATE users SET pass_hash=?1 WHERE username=?2", [new_pwdhash, username]))
@@ -144,12 +125,75 @@ pub async fn change_passhash(pool: &Pool, username: String, new_pwdhash: String)
 }
 
 pub async fn change_totp(pool: &Pool, username: String, new_totp: String) -> Result<(), DBError> {
-    pool.conn(|conn| conn.execute("UPDATE users SET totp=?1 WHERE username=?2", [new_totp, username]))
+    pool.conn(|conn| conn.execute("UPDATE users SET totp=?1, totp_step=0 WHERE username=?2", [new_totp, username]))
         .await
         .map_err(|e| DBError::ExecError(format!("Failed to change user's TOTP: {e}")))
         .and_then(|changes| if changes > 0 { Ok(()) } else { Err(DBError::UserNotFound) })
 }
 
+/// Marks a TOTP time step as used by the user.
+/// Returns false if a step at least as recent was already used, which means the token was replayed.
+pub async fn use_totp_step(pool: &Pool, username: String, step: u64) -> Result<bool, DBError> {
+    pool.conn(move |conn| {
+        conn.execute(
+            "UPDATE users SET totp_step=?1 WHERE username=?2 AND totp_step<?1",
+            params![step, username],
+        )
+    })
+    .await
+    .map(|changes| changes > 0)
+    .map_err(|e| DBError::ExecError(format!("Failed to update TOTP step: {e}")))
+}
+
+/// Replaces all the recovery codes of the user with the given hashes
+pub async fn set_recovery_codes(pool: &Pool, username: String, hashes: Vec<String>) -> Result<(), DBError> {
+    pool.conn_mut(move |conn| {
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM recovery_codes WHERE username=?1", [&username])?;
+        for hash in hashes {
+            tx.execute(
+                "INSERT INTO recovery_codes (username, code_hash) VALUES (?1, ?2)",
+                [&username, &hash],
+            )?;
+        }
+        tx.commit()
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to set recovery codes: {e}")))
+}
+
+/// Replaces the TOTP secret and recovery codes of the user and ends all of its sessions, all or nothing
+pub async fn reset_totp(pool: &Pool, username: String, new_totp: String, hashes: Vec<String>) -> Result<(), DBError> {
+    pool.conn_mut(move |conn| {
+        let tx = conn.transaction()?;
+        if tx.execute("UPDATE users SET totp=?1, totp_step=0 WHERE username=?2", [&new_totp, &username])? == 0 {
+            return Ok(false);
+        }
+        tx.execute("DELETE FROM recovery_codes WHERE username=?1", [&username])?;
+        for hash in hashes {
+            tx.execute(
+                "INSERT INTO recovery_codes (username, code_hash) VALUES (?1, ?2)",
+                [&username, &hash],
+            )?;
+        }
+        tx.execute("DELETE FROM sessions WHERE username=?1", [&username])?;
+        tx.execute("DELETE FROM session_store WHERE username=?1", [&username])?;
+        tx.commit()?;
+        Ok(true)
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to reset user's TOTP: {e}")))
+    .and_then(|exists| if exists { Ok(()) } else { Err(DBError::UserNotFound) })
+}
+
+/// Consumes a recovery code of the user. Returns false if it does not exist.
+pub async fn use_recovery_code(pool: &Pool, username: String, hash: String) -> Result<bool, DBError> {
+    pool.conn(move |conn| conn.execute("DELETE FROM recovery_codes WHERE username=?1 AND code_hash=?2", [username, hash]))
+        .await
+        .map(|changes| changes > 0)
+        .map_err(|e| DBError::ExecError(format!("Failed to use recovery code: {e}")))
+}
+
 /// Gets a list of all the usernames in the database
 pub async fn get_all_usernames(pool: &Pool) -> Result<Vec<String>, DBError> {
     pool.conn(|conn| {
@@ -165,20 +209,232 @@ pub async fn get_all_usernames(pool: &Pool) -> Result<Vec<String>, DBError> {
     .map_err(|e| DBError::ExecError(format!("Failed to get user: {e}")))
 }
 
-/// Deletes user from database and all of its
-pub async fn delete_user(pool: &Pool, userid: String) -> Result<(), DBError> {
-    let (username, sessionid) = unpack(userid)?;
-    let user = username.clone();
-    pool.conn(move |conn| conn.execute("DELETE FROM users WHERE username=?1 AND sessionid=?2", params![username, sessionid]))
+/// Gets a list of all the users in the database as [`UserEntry`]
+pub async fn get_all_users(pool: &Pool) -> Result<Vec<UserEntry>, DBError> {
+    pool.conn(|conn| {
+        let mut stmt = conn.prepare("SELECT username, is_admin, quota FROM users ORDER BY username")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(UserEntry {
+                username: row.get(0)?,
+                is_admin: row.get(1)?,
+                quota: row.get(2)?,
+            })
+        })?;
+        rows.collect()
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to get users: {e}")))
+}
+
+/// Changes admin status of the selected user
+pub async fn set_admin(pool: &Pool, username: String, is_admin: bool) -> Result<(), DBError> {
+    pool.conn(move |conn| conn.execute("UPDATE users SET is_admin=?1 WHERE username=?2", params![is_admin, username]))
         .await
-        .map_err(|e| DBError::ExecError(format!("Failed to delete user '{user}': {e}")))
-        .and_then(|changes| if changes == 0 { Err(DBError::InvalidUserID) } else { Ok(()) })?;
-    log::info!("Deleted user '{user}'");
+        .map_err(|e| DBError::ExecError(format!("Failed to change user's admin status: {e}")))
+        .and_then(|changes| if changes > 0 { Ok(()) } else { Err(DBError::UserNotFound) })
+}
+
+/// Returns the storage quota of the selected user, None if the default one applies
+pub async fn get_quota(pool: &Pool, username: String) -> Result<Option<u64>, DBError> {
+    pool.conn(|conn| {
+        conn.query_row("SELECT quota FROM users WHERE username=?1", [username], |row| row.get(0))
+            .optional()
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to get quota: {e}")))?
+    .ok_or(DBError::UserNotFound)
+}
+
+/// Changes the storage quota of the selected user, None restores the default one
+pub async fn set_quota(pool: &Pool, username: String, quota: Option<u64>) -> Result<(), DBError> {
+    pool.conn(move |conn| conn.execute("UPDATE users SET quota=?1 WHERE username=?2", params![quota, username]))
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to change quota: {e}")))
+        .and_then(|changes| if changes > 0 { Ok(()) } else { Err(DBError::UserNotFound) })
+}
+
+fn spawn_delete_user_dir(user: String) {
     tokio::spawn(async move {
         if let Err(e) = super::delete_user_dir(&user).await {
             log::error!("Failed to delete user directory: {e}");
         }
         log::info!("Deleted user directory of '{user}'");
     });
+}
+
+/// Deletes what belongs to a user along with it, within the transaction that deleted the user
+fn delete_user_data(tx: &rusqlite::Transaction, username: &str) -> rusqlite::Result<()> {
+    tx.execute("DELETE FROM sessions WHERE username=?1", [username])?;
+    tx.execute("DELETE FROM session_store WHERE username=?1", [username])?;
+    tx.execute("DELETE FROM recovery_codes WHERE username=?1", [username])?;
+    tx.execute("DELETE FROM api_tokens WHERE username=?1", [username])?;
+    // The table exists even without the feature, and passkeys must not outlive their user
+    tx.execute("DELETE FROM webauthn_credentials WHERE username=?1", [username])?;
+    Ok(())
+}
+
+/// Deletes user from database and all of its
+pub async fn delete_user(pool: &Pool, userid: String) -> Result<(), DBError> {
+    let (username, sessionid) = unpack(userid)?;
+    let (idle_limit, login_limit) = session::limits(now()?);
+    let user = username.clone();
+    pool.conn_mut(move |conn| {
+        let tx = conn.transaction()?;
+        let changes = tx.execute(
+            "DELETE FROM users WHERE username=?1 AND EXISTS (
+                SELECT 1 FROM sessions WHERE username=?1 AND sessionid=?2 AND last_seen>=?3 AND created>=?4
+             )",
+            params![username, sessionid, idle_limit, login_limit],
+        )?;
+        if changes > 0 {
+            delete_user_data(&tx, &username)?;
+        }
+        tx.commit()?;
+        Ok(changes)
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to delete user '{user}': {e}")))
+    .and_then(|changes| if changes == 0 { Err(DBError::InvalidUserID) } else { Ok(()) })?;
+    log::info!("Deleted user '{user}'");
+    spawn_delete_user_dir(user);
+    Ok(())
+}
+
+/// Deletes the selected user from database and all of its files
+pub async fn delete_user_by_name(pool: &Pool, username: String) -> Result<(), DBError> {
+    let user = username.clone();
+    pool.conn_mut(move |conn| {
+        let tx = conn.transaction()?;
+        let changes = tx.execute("DELETE FROM users WHERE username=?1", [&username])?;
+        if changes > 0 {
+            delete_user_data(&tx, &username)?;
+        }
+        tx.commit()?;
+        Ok(changes)
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to delete user '{user}': {e}")))
+    .and_then(|changes| if changes == 0 { Err(DBError::UserNotFound) } else { Ok(()) })?;
+    log::info!("Deleted user '{user}'");
+    spawn_delete_user_dir(user);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::memory;
+
+    async fn count(pool: &Pool, table: &'static str, username: &str) -> i64 {
+        let username = username.to_string();
+        pool.conn(move |conn| {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {table} WHERE username=?1"), [username], |row| {
+                row.get(0)
+            })
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn user_with_data(pool: &Pool, username: &str) -> String {
+        add_user(pool, username.into(), String::new(), String::new(), false).await.unwrap();
+        set_recovery_codes(pool, username.into(), vec!["hash".into()]).await.unwrap();
+        let user = username.to_string();
+        pool.conn(move |conn| {
+            conn.execute(
+                "INSERT INTO webauthn_credentials (username, cred_id, name, passkey, created) VALUES (?1, ?1, '', '', 0)",
+                [user],
+            )
+        })
+        .await
+        .unwrap();
+        session::create(pool, username.into(), String::new(), String::new()).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn delete_user_needs_a_valid_session() {
+        let pool = memory().await;
+        let userid = user_with_data(&pool, "deleted").await;
+        user_with_data(&pool, "kept").await;
+
+        let wrong = delete_user(&pool, "deleted:1".into()).await;
+        assert!(matches!(wrong, Err(DBError::InvalidUserID)));
+        for table in ["users", "sessions", "recovery_codes", "webauthn_credentials"] {
+            assert_eq!(count(&pool, table, "deleted").await, 1, "{table} was emptied by a failed deletion");
+        }
+
+        delete_user(&pool, userid).await.unwrap();
+        for table in ["users", "sessions", "recovery_codes", "webauthn_credentials"] {
+            assert_eq!(
+                count(&pool, table, "deleted").await,
+                0,
+                "{table} still has rows of the deleted user"
+            );
+            assert_eq!(count(&pool, table, "kept").await, 1, "{table} lost rows of another user");
+        }
+    }
+
+    #[actix_web::test]
+    async fn delete_user_by_name_removes_its_data() {
+        let pool = memory().await;
+        user_with_data(&pool, "byname").await;
+        assert!(matches!(
+            delete_user_by_name(&pool, "missing".into()).await,
+            Err(DBError::UserNotFound)
+        ));
+        delete_user_by_name(&pool, "byname".into()).await.unwrap();
+        for table in ["users", "sessions", "recovery_codes", "webauthn_credentials"] {
+            assert_eq!(count(&pool, table, "byname").await, 0, "{table} still has rows of the deleted user");
+        }
+    }
+
+    #[actix_web::test]
+    async fn reset_totp_replaces_codes_and_ends_sessions() {
+        let pool = memory().await;
+        user_with_data(&pool, "reset").await;
+        user_with_data(&pool, "kept").await;
+        assert!(matches!(
+            reset_totp(&pool, "missing".into(), "totp".into(), vec!["new".into()]).await,
+            Err(DBError::UserNotFound)
+        ));
+        assert_eq!(count(&pool, "recovery_codes", "missing").await, 0);
+
+        reset_totp(&pool, "reset".into(), "totp".into(), vec!["new1".into(), "new2".into()])
+            .await
+            .unwrap();
+        assert_eq!(count(&pool, "recovery_codes", "reset").await, 2);
+        assert_eq!(count(&pool, "sessions", "reset").await, 0);
+        assert!(!use_recovery_code(&pool, "reset".into(), "hash".into()).await.unwrap());
+        let totp: String = pool
+            .conn(|conn| conn.query_row("SELECT totp FROM users WHERE username='reset'", [], |row| row.get(0)))
+            .await
+            .unwrap();
+        assert_eq!(totp, "totp");
+        assert_eq!(count(&pool, "sessions", "kept").await, 1);
+        assert_eq!(count(&pool, "recovery_codes", "kept").await, 1);
+    }
+
+    #[actix_web::test]
+    async fn expired_sessions_are_refused_before_they_are_pruned() {
+        let pool = memory().await;
+        let userid = user_with_data(&pool, "expired").await;
+        let (_, sessionid) = unpack(userid.clone()).unwrap();
+        assert!(get_passhash(&pool, "expired".into(), sessionid).await.unwrap().is_some());
+
+        let (idle_limit, login_limit) = session::limits(now().unwrap());
+        for (created, last_seen) in [(now().unwrap(), idle_limit - 1), (login_limit - 1, now().unwrap())] {
+            pool.conn(move |conn| {
+                conn.execute(
+                    "UPDATE sessions SET created=?1, last_seen=?2 WHERE sessionid=?3",
+                    params![created, last_seen, sessionid],
+                )
+            })
+            .await
+            .unwrap();
+            assert!(get_passhash(&pool, "expired".into(), sessionid).await.unwrap().is_none());
+            assert!(matches!(delete_user(&pool, userid.clone()).await, Err(DBError::InvalidUserID)));
+            assert_eq!(count(&pool, "users", "expired").await, 1);
+            assert_eq!(count(&pool, "sessions", "expired").await, 1, "the session was pruned");
+        }
+    }
+}
@@ -1,6 +1,8 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use crate::{auth::error::AuthError, plugins::error::PluginError, token::error::TokenError};
+use crate::{
+    admin::error::AdminError, auth::error::AuthError, plugins::error::PluginError, quota::error::QuotaError, token::error::TokenError,
+};
 use std::convert::Into;
 use thiserror::Error;
 
@@ -18,6 +20,8 @@ pub enum DBError {
     InvalidUserID,
     #[error("Time failure: {0}")]
     TimeFailure(String),
+    #[error("Database schema version {0} is newer than the latest supported ({1}), update tiny-cloud or restore a backup")]
+    NewerSchema(usize, usize),
 }
 
 impl Into<AuthError> for DBError {
@@ -41,3 +45,28 @@ impl Into<PluginError> for DBError {
         PluginError::InternalError(self.to_string())
     }
 }
+
+impl Into<AdminError> for DBError {
+    fn into(self) -> AdminError {
+        match self {
+            Self::UserNotFound => AdminError::UserNotFound,
+            _ => AdminError::InternalError(self.to_string()),
+        }
+    }
+}
+
+impl Into<QuotaError> for DBError {
+    fn into(self) -> QuotaError {
+        match self {
+            Self::UserNotFound => QuotaError::UserNotFound,
+            _ => QuotaError::InternalError(self.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "webauthn")]
+impl Into<crate::auth::webauthn::error::WebAuthnError> for DBError {
+    fn into(self) -> crate::auth::webauthn::error::WebAuthnError {
+        crate::auth::webauthn::error::WebAuthnError::InternalError(self.to_string())
+    }
+}
@@ -0,0 +1,308 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::error::DBError;
+use async_sqlite::{
+    Pool,
+    rusqlite::{self, Transaction},
+};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A step bringing the schema from the previous version to the next one.
+/// Migrations are never edited once released, changes to the schema get a new migration.
+struct Migration {
+    description: &'static str,
+    apply: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+/// Ordered migrations, the schema version of a database is the number of migrations applied to it.
+/// Databases created before versioning are at version 0 but may already contain some of these tables and columns,
+/// so migrations must not fail when that happens.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "create users and tokens tables",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS users (
+                    username    TEXT    UNIQUE NOT NULL,
+                    sessionid   BIGINT  UNIQUE NOT NULL,
+                    pass_hash   TEXT    NOT NULL,
+                    totp        TEXT    NOT NULL,
+                    is_admin    INTEGER DEFAULT 0
+                );
+                CREATE TABLE IF NOT EXISTS tokens (
+                    id          INTEGER PRIMARY KEY,
+                    token       TEXT    NOT NULL,
+                    expire_date INT     NOT NULL,
+                    for_user    TEXT,
+                    UNIQUE(token)
+                );",
+            )
+        },
+    },
+    Migration {
+        description: "add storage quotas",
+        apply: |tx| add_column(tx, "users", "quota", "BIGINT"),
+    },
+    Migration {
+        description: "add TOTP replay protection and recovery codes",
+        apply: |tx| {
+            add_column(tx, "users", "totp_step", "BIGINT NOT NULL DEFAULT 0")?;
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS recovery_codes (
+                    username    TEXT    NOT NULL,
+                    code_hash   TEXT    NOT NULL,
+                    UNIQUE(username, code_hash)
+                );",
+            )
+        },
+    },
+    Migration {
+        description: "add passkeys",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS webauthn_credentials (
+                    username    TEXT    NOT NULL,
+                    cred_id     TEXT    UNIQUE NOT NULL,
+                    name        TEXT    NOT NULL,
+                    passkey     TEXT    NOT NULL,
+                    created     BIGINT  NOT NULL
+                );",
+            )
+        },
+    },
+    Migration {
+        description: "track sessions individually, users.sessionid is no longer used",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS sessions (
+                    id          INTEGER PRIMARY KEY,
+                    username    TEXT    NOT NULL,
+                    sessionid   BIGINT  UNIQUE NOT NULL,
+                    created     BIGINT  NOT NULL,
+                    last_seen   BIGINT  NOT NULL,
+                    ip          TEXT    NOT NULL,
+                    user_agent  TEXT    NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS sessions_username ON sessions (username);",
+            )
+        },
+    },
+    Migration {
+        description: "add server-side session store",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS session_store (
+                    key         TEXT    PRIMARY KEY,
+                    state       TEXT    NOT NULL,
+                    username    TEXT,
+                    sessionid   BIGINT,
+                    expires     BIGINT  NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS session_store_username ON session_store (username);",
+            )
+        },
+    },
+    Migration {
+        description: "add personal API tokens",
+        apply: |tx| {
+            tx.execute_batch(
+                "CREATE TABLE IF NOT EXISTS api_tokens (
+                    id          INTEGER PRIMARY KEY,
+                    username    TEXT    NOT NULL,
+                    name        TEXT    NOT NULL,
+                    token_hash  TEXT    UNIQUE NOT NULL,
+                    scopes      TEXT    NOT NULL,
+                    created     BIGINT  NOT NULL,
+                    expires     BIGINT  NOT NULL,
+                    last_used   BIGINT
+                );
+                CREATE INDEX IF NOT EXISTS api_tokens_username ON api_tokens (username);",
+            )
+        },
+    },
+];
+
+/// Adds a column to a table, unless it already exists.
+fn add_column(tx: &Transaction, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+    let exists: bool = tx.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name=?2",
+        [table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        tx.execute(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"), [])?;
+    }
+    Ok(())
+}
+
+/// Returns the schema version of the database.
+async fn version(pool: &Pool) -> Result<usize, DBError> {
+    pool.conn(|conn| conn.query_row("PRAGMA user_version", [], |row| row.get(0)))
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to read schema version: {e}")))
+}
+
+/// Copies the database next to it before it gets migrated, named after its schema version.
+async fn backup(pool: &Pool, data_dir: &Path, version: usize) -> Result<(), DBError> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| DBError::TimeFailure(e.to_string()))?
+        .as_secs();
+    let path = data_dir.join(format!("auth.db.v{version}-{time}.bak"));
+    let dest = path
+        .to_str()
+        .ok_or_else(|| DBError::IOError(format!("Invalid backup path: {}", path.display())))?
+        .to_string();
+    pool.conn(move |conn| conn.execute("VACUUM INTO ?1", [dest]))
+        .await
+        .map_err(|e| DBError::IOError(format!("Failed to back up database before migrating it: {e}")))?;
+    log::warn!("Database backed up to {} before migrating it", path.display());
+    Ok(())
+}
+
+/// Brings the database to the latest schema version.
+/// Every migration runs in its own transaction along with the update of the version, so a failure leaves the database
+/// at the last successful version. Existing databases are backed up in `data_dir` first.
+/// Fails if the database was created by a newer version of tiny-cloud.
+pub async fn migrate(pool: &Pool, data_dir: &Path, existed: bool) -> Result<(), DBError> {
+    let current = version(pool).await?;
+    let latest = MIGRATIONS.len();
+    if current > latest {
+        return Err(DBError::NewerSchema(current, latest));
+    }
+    if current == latest {
+        return Ok(());
+    }
+    if existed {
+        backup(pool, data_dir, current).await?;
+    }
+    pool.conn_mut(move |conn| {
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+            let tx = conn.transaction()?;
+            (migration.apply)(&tx)?;
+            tx.pragma_update(None, "user_version", version + 1)?;
+            tx.commit()?;
+            log::info!("Migrated database to version {}: {}", version + 1, migration.description);
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to migrate database: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_sqlite::PoolBuilder;
+
+    async fn open() -> Pool {
+        PoolBuilder::new()
+            .path(":memory:")
+            .num_conns(1)
+            .open()
+            .await
+            .expect("Failed to open database in memory")
+    }
+
+    async fn columns(pool: &Pool, table: &'static str) -> Vec<String> {
+        pool.conn(move |conn| {
+            conn.prepare("SELECT name FROM pragma_table_info(?1)")?
+                .query_map([table], |row| row.get(0))?
+                .collect()
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn check_latest(pool: &Pool) {
+        assert_eq!(version(pool).await.unwrap(), MIGRATIONS.len());
+        assert!(columns(pool, "users").await.contains(&"totp_step".to_string()));
+        assert!(columns(pool, "users").await.contains(&"quota".to_string()));
+        for table in [
+            "tokens",
+            "recovery_codes",
+            "webauthn_credentials",
+            "sessions",
+            "session_store",
+            "api_tokens",
+        ] {
+            assert!(!columns(pool, table).await.is_empty(), "{table} is missing");
+        }
+    }
+
+    #[actix_web::test]
+    async fn migrates_from_version_0() {
+        let pool = open().await;
+        migrate(&pool, Path::new(""), false).await.unwrap();
+        check_latest(&pool).await;
+        migrate(&pool, Path::new(""), false).await.unwrap();
+        check_latest(&pool).await;
+    }
+
+    #[actix_web::test]
+    async fn migrates_from_before_versioning() {
+        let pool = open().await;
+        pool.conn(|conn| {
+            conn.execute_batch(
+                "CREATE TABLE users (
+                    username    TEXT    UNIQUE NOT NULL,
+                    sessionid   BIGINT  UNIQUE NOT NULL,
+                    pass_hash   TEXT    NOT NULL,
+                    totp        TEXT    NOT NULL,
+                    is_admin    INTEGER DEFAULT 0,
+                    quota       BIGINT
+                );
+                CREATE TABLE tokens (
+                    id          INTEGER PRIMARY KEY,
+                    token       TEXT    NOT NULL,
+                    expire_date INT     NOT NULL,
+                    for_user    TEXT,
+                    UNIQUE(token)
+                );
+                INSERT INTO users (username, sessionid, pass_hash, totp, is_admin, quota) VALUES ('alice', 1, 'hash', '', 1, 42);",
+            )
+        })
+        .await
+        .unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+
+        migrate(&pool, data_dir.path(), true).await.unwrap();
+        check_latest(&pool).await;
+        let user = pool
+            .conn(|conn| {
+                conn.query_row(
+                    "SELECT pass_hash, is_admin, quota, totp_step FROM users WHERE username='alice'",
+                    [],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, bool>(1)?,
+                            row.get::<_, u64>(2)?,
+                            row.get::<_, u64>(3)?,
+                        ))
+                    },
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(user, ("hash".to_string(), true, 42, 0));
+        let backups: Vec<_> = std::fs::read_dir(data_dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].to_string_lossy().starts_with("auth.db.v0-"));
+    }
+
+    #[actix_web::test]
+    async fn refuses_newer_schemas() {
+        let pool = open().await;
+        let newer = MIGRATIONS.len() + 1;
+        pool.conn(move |conn| conn.pragma_update(None, "user_version", newer))
+            .await
+            .unwrap();
+        let result = migrate(&pool, Path::new(""), true).await;
+        assert!(matches!(result, Err(DBError::NewerSchema(v, l)) if v == newer && l == MIGRATIONS.len()));
+    }
+}
@@ -0,0 +1,321 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::{error::DBError, utils::now};
+use crate::config;
+use async_sqlite::{
+    Pool,
+    rusqlite::{OptionalExtension, params},
+};
+
+/// Seconds between updates of the last time a session was seen, to avoid writing on every request
+const LAST_SEEN_INTERVAL: u64 = 60;
+
+/// A session as listed to its user.
+pub struct Session {
+    /// Id used to refer to the session, it is not the one stored in its identity
+    pub id: i64,
+    /// Login time in seconds since the Unix epoch
+    pub created: u64,
+    /// Last request time in seconds since the Unix epoch, updated once a minute at most
+    pub last_seen: u64,
+    pub ip: String,
+    pub user_agent: String,
+}
+
+/// Returns the oldest last request and login times of a session that has not expired
+pub fn limits(now: u64) -> (u64, u64) {
+    let idle_limit = now.saturating_sub(u64::from(*config!(duration.cookie_minutes)) * 60);
+    let login_limit = config!(duration.login_minutes).map_or(0, |m| now.saturating_sub(m * 60));
+    (idle_limit, login_limit)
+}
+
+/// Deletes the sessions whose cookies have expired.
+fn prune(conn: &async_sqlite::rusqlite::Connection, now: u64) -> async_sqlite::rusqlite::Result<usize> {
+    let (idle_limit, login_limit) = limits(now);
+    conn.execute(
+        "DELETE FROM sessions WHERE last_seen<?1 OR created<?2",
+        params![idle_limit, login_limit],
+    )
+}
+
+/// Starts a new session for the user and returns its userid, formatted as "USERNAME:SESSION_ID".
+/// Expired sessions of all users are deleted in the meantime.
+pub async fn create(pool: &Pool, username: String, ip: String, user_agent: String) -> Result<String, DBError> {
+    let now = now()?;
+    let sessionid: i64 = rand::random();
+    let user = username.clone();
+    pool.conn(move |conn| {
+        prune(conn, now)?;
+        conn.execute(
+            "INSERT INTO sessions (username, sessionid, created, last_seen, ip, user_agent) VALUES (?1, ?2, ?3, ?3, ?4, ?5)",
+            params![user, sessionid, now, ip, user_agent],
+        )
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to create session: {e}")))?;
+    Ok(format!("{username}:{sessionid}"))
+}
+
+/// Records that the session was used, returns false if it does not exist or has expired.
+pub async fn touch(pool: &Pool, username: String, sessionid: i64) -> Result<bool, DBError> {
+    let now = now()?;
+    let (idle_limit, login_limit) = limits(now);
+    pool.conn(move |conn| {
+        let exists = conn
+            .query_row(
+                "SELECT last_seen FROM sessions WHERE username=?1 AND sessionid=?2 AND last_seen>=?3 AND created>=?4",
+                params![username, sessionid, idle_limit, login_limit],
+                |row| row.get::<usize, u64>(0),
+            )
+            .optional()?;
+        match exists {
+            Some(last_seen) if last_seen + LAST_SEEN_INTERVAL <= now => {
+                conn.execute("UPDATE sessions SET last_seen=?1 WHERE sessionid=?2", params![now, sessionid])?;
+                Ok(true)
+            }
+            Some(_) => Ok(true),
+            None => Ok(false),
+        }
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to update session: {e}")))
+}
+
+/// Returns the sessions of the user, ordered from the most recent, along with the listed id of the current one
+/// if its session id is given.
+pub async fn list(pool: &Pool, username: String, sessionid: Option<i64>) -> Result<(Vec<Session>, Option<i64>), DBError> {
+    pool.conn(move |conn| {
+        let mut stmt = conn.prepare(
+            "SELECT id, created, last_seen, ip, user_agent, sessionid FROM sessions WHERE username=?1 ORDER BY last_seen DESC",
+        )?;
+        let mut current = None;
+        let sessions = stmt
+            .query_map([username], |row| {
+                Ok((
+                    Session {
+                        id: row.get(0)?,
+                        created: row.get(1)?,
+                        last_seen: row.get(2)?,
+                        ip: row.get(3)?,
+                        user_agent: row.get(4)?,
+                    },
+                    row.get::<usize, i64>(5)?,
+                ))
+            })?
+            .map(|row| {
+                row.map(|(session, id)| {
+                    if Some(id) == sessionid {
+                        current = Some(session.id);
+                    }
+                    session
+                })
+            })
+            .collect::<Result<Vec<Session>, _>>()?;
+        Ok((sessions, current))
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to list sessions: {e}")))
+}
+
+/// Ends the session the identity refers to.
+pub async fn delete_current(pool: &Pool, username: String, sessionid: i64) -> Result<(), DBError> {
+    pool.conn(move |conn| {
+        conn.execute(
+            "DELETE FROM sessions WHERE username=?1 AND sessionid=?2",
+            params![username, sessionid],
+        )?;
+        conn.execute(
+            "DELETE FROM session_store WHERE username=?1 AND sessionid=?2",
+            params![username, sessionid],
+        )
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to delete session: {e}")))?;
+    Ok(())
+}
+
+/// Ends a session of the user by its listed id. Returns false if it does not exist.
+pub async fn delete(pool: &Pool, username: String, id: i64) -> Result<bool, DBError> {
+    pool.conn(move |conn| {
+        let sessionid: Option<i64> = conn
+            .query_row(
+                "DELETE FROM sessions WHERE username=?1 AND id=?2 RETURNING sessionid",
+                params![username, id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(sessionid) = sessionid {
+            conn.execute(
+                "DELETE FROM session_store WHERE username=?1 AND sessionid=?2",
+                params![username, sessionid],
+            )?;
+        }
+        Ok(sessionid.is_some())
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to delete session: {e}")))
+}
+
+/// Ends all the sessions of the user, fails if the user does not exist.
+pub async fn delete_all(pool: &Pool, username: String) -> Result<(), DBError> {
+    pool.conn(move |conn| {
+        let exists: bool = conn.query_row("SELECT COUNT(*) > 0 FROM users WHERE username=?1", [&username], |row| row.get(0))?;
+        conn.execute("DELETE FROM sessions WHERE username=?1", [&username])?;
+        conn.execute("DELETE FROM session_store WHERE username=?1", [&username])?;
+        Ok(exists)
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to delete sessions: {e}")))
+    .and_then(|exists| if exists { Ok(()) } else { Err(DBError::UserNotFound) })
+}
+
+/// Returns the serialized state of a stored session, if it has not expired.
+pub async fn load_state(pool: &Pool, key: String) -> Result<Option<String>, DBError> {
+    let now = now()?;
+    pool.conn(move |conn| {
+        conn.query_row(
+            "SELECT state FROM session_store WHERE key=?1 AND expires>?2",
+            params![key, now],
+            |row| row.get(0),
+        )
+        .optional()
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to load session state: {e}")))
+}
+
+/// Stores the serialized state of a session along with the identity it holds, if any.
+/// Returns false if `update` is set and the session does not exist anymore.
+pub async fn save_state(
+    pool: &Pool,
+    key: String,
+    state: String,
+    owner: Option<(String, i64)>,
+    expires: u64,
+    update: bool,
+) -> Result<bool, DBError> {
+    let (username, sessionid) = owner.unzip();
+    pool.conn(move |conn| {
+        if update {
+            conn.execute(
+                "UPDATE session_store SET state=?2, username=?3, sessionid=?4, expires=?5 WHERE key=?1",
+                params![key, state, username, sessionid, expires],
+            )
+        } else {
+            conn.execute(
+                "INSERT INTO session_store (key, state, username, sessionid, expires) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![key, state, username, sessionid, expires],
+            )
+        }
+    })
+    .await
+    .map(|changes| changes > 0)
+    .map_err(|e| DBError::ExecError(format!("Failed to save session state: {e}")))
+}
+
+/// Changes when a stored session expires.
+pub async fn set_state_expiry(pool: &Pool, key: String, expires: u64) -> Result<(), DBError> {
+    pool.conn(move |conn| conn.execute("UPDATE session_store SET expires=?1 WHERE key=?2", params![expires, key]))
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to update session expiry: {e}")))?;
+    Ok(())
+}
+
+/// Deletes a stored session.
+pub async fn delete_state(pool: &Pool, key: String) -> Result<(), DBError> {
+    pool.conn(move |conn| conn.execute("DELETE FROM session_store WHERE key=?1", [key]))
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to delete session state: {e}")))?;
+    Ok(())
+}
+
+/// Deletes the expired stored sessions and returns how many there were.
+pub async fn sweep_states(pool: &Pool) -> Result<usize, DBError> {
+    let now = now()?;
+    pool.conn(move |conn| conn.execute("DELETE FROM session_store WHERE expires<=?1", [now]))
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to delete expired sessions: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::memory;
+
+    /// Moves the login and last request times of a session back by the given seconds
+    async fn age(pool: &Pool, userid: &str, login: u64, idle: u64) -> i64 {
+        let sessionid: i64 = userid.rsplit_once(':').unwrap().1.parse().unwrap();
+        pool.conn(move |conn| {
+            conn.execute(
+                "UPDATE sessions SET created=created-?1, last_seen=last_seen-?2 WHERE sessionid=?3",
+                params![login, idle, sessionid],
+            )
+        })
+        .await
+        .unwrap();
+        sessionid
+    }
+
+    async fn last_seen(pool: &Pool, sessionid: i64) -> Option<u64> {
+        pool.conn(move |conn| {
+            conn.query_row("SELECT last_seen FROM sessions WHERE sessionid=?1", [sessionid], |row| row.get(0))
+                .optional()
+        })
+        .await
+        .unwrap()
+    }
+
+    fn idle_time() -> u64 {
+        u64::from(*config!(duration.cookie_minutes)) * 60
+    }
+
+    fn login_time() -> u64 {
+        config!(duration.login_minutes).expect("Default config without a login duration") * 60
+    }
+
+    #[actix_web::test]
+    async fn touch_updates_last_seen_once_a_minute() {
+        let pool = memory().await;
+        let userid = create(&pool, "alice".into(), String::new(), String::new()).await.unwrap();
+
+        let sessionid = age(&pool, &userid, 0, LAST_SEEN_INTERVAL / 2).await;
+        let seen = last_seen(&pool, sessionid).await;
+        assert!(touch(&pool, "alice".into(), sessionid).await.unwrap());
+        assert_eq!(last_seen(&pool, sessionid).await, seen);
+
+        age(&pool, &userid, 0, LAST_SEEN_INTERVAL).await;
+        assert!(touch(&pool, "alice".into(), sessionid).await.unwrap());
+        assert!(last_seen(&pool, sessionid).await.unwrap() >= now().unwrap() - 1);
+
+        assert!(!touch(&pool, "bob".into(), sessionid).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn touch_rejects_expired_sessions() {
+        let pool = memory().await;
+        let idle = create(&pool, "alice".into(), String::new(), String::new()).await.unwrap();
+        let idle = age(&pool, &idle, idle_time() + 10, idle_time() + 10).await;
+        let old = create(&pool, "alice".into(), String::new(), String::new()).await.unwrap();
+        let old = age(&pool, &old, login_time() + 10, 0).await;
+
+        assert!(!touch(&pool, "alice".into(), idle).await.unwrap());
+        assert!(!touch(&pool, "alice".into(), old).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn create_prunes_expired_sessions() {
+        let pool = memory().await;
+        let idle = create(&pool, "alice".into(), String::new(), String::new()).await.unwrap();
+        let old = create(&pool, "bob".into(), String::new(), String::new()).await.unwrap();
+        let kept = create(&pool, "carol".into(), String::new(), String::new()).await.unwrap();
+        let idle = age(&pool, &idle, idle_time() + 10, idle_time() + 10).await;
+        let old = age(&pool, &old, login_time() + 10, 0).await;
+        let kept = age(&pool, &kept, idle_time() - 10, idle_time() - 10).await;
+        assert!(last_seen(&pool, idle).await.is_some());
+
+        create(&pool, "dave".into(), String::new(), String::new()).await.unwrap();
+        assert_eq!(last_seen(&pool, idle).await, None);
+        assert_eq!(last_seen(&pool, old).await, None);
+        assert!(last_seen(&pool, kept).await.is_some());
+    }
+}
@@ -20,14 +20,6 @@ pub struct Token {
     pub for_user: Option<String>,
 }
 
-pub const TOKEN_TABLE: &str = "CREATE TABLE IF NOT EXISTS tokens (
-    id          INTEGER PRIMARY KEY,
-    token       TEXT    NOT NULL,
-    expire_date INT     NOT NULL,
-    for_user    TEXT,
-    UNIQUE(token)
-)";
-
 fn gen_token(registration: &Registration) -> String {
     rand::rng()
         .sample_iter(&Alphanumeric)
@@ -0,0 +1,71 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::{error::DBError, utils::now};
+use async_sqlite::{Pool, rusqlite::params};
+
+/// A passkey of a user.
+#[non_exhaustive]
+pub struct Credential {
+    /// Credential id, hex encoded
+    pub cred_id: String,
+    /// Name given by the user
+    pub name: String,
+    /// Serialized passkey
+    pub passkey: String,
+    /// Creation time in seconds since the Unix epoch
+    pub created: u64,
+}
+
+/// Saves a new passkey of the user
+pub async fn add_credential(pool: &Pool, username: String, cred_id: String, name: String, passkey: String) -> Result<(), DBError> {
+    let created = now()?;
+    pool.conn(move |conn| {
+        conn.execute(
+            "INSERT INTO webauthn_credentials (username, cred_id, name, passkey, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![username, cred_id, name, passkey, created],
+        )
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to add passkey: {e}")))?;
+    Ok(())
+}
+
+/// Returns all the passkeys of the user
+pub async fn get_credentials(pool: &Pool, username: String) -> Result<Vec<Credential>, DBError> {
+    pool.conn(move |conn| {
+        let mut stmt =
+            conn.prepare("SELECT cred_id, name, passkey, created FROM webauthn_credentials WHERE username=?1 ORDER BY created")?;
+        let rows = stmt.query_map([username], |row| {
+            Ok(Credential {
+                cred_id: row.get(0)?,
+                name: row.get(1)?,
+                passkey: row.get(2)?,
+                created: row.get(3)?,
+            })
+        })?;
+        rows.collect()
+    })
+    .await
+    .map_err(|e| DBError::ExecError(format!("Failed to get passkeys: {e}")))
+}
+
+/// Replaces a saved passkey, used when its counter changes
+pub async fn update_credential(pool: &Pool, cred_id: String, passkey: String) -> Result<(), DBError> {
+    pool.conn(move |conn| conn.execute("UPDATE webauthn_credentials SET passkey=?1 WHERE cred_id=?2", [passkey, cred_id]))
+        .await
+        .map_err(|e| DBError::ExecError(format!("Failed to update passkey: {e}")))?;
+    Ok(())
+}
+
+/// Deletes a passkey of the user. Returns false if it does not exist.
+pub async fn delete_credential(pool: &Pool, username: String, cred_id: String) -> Result<bool, DBError> {
+    pool.conn(move |conn| {
+        conn.execute(
+            "DELETE FROM webauthn_credentials WHERE username=?1 AND cred_id=?2",
+            [username, cred_id],
+        )
+    })
+    .await
+    .map(|changes| changes > 0)
+    .map_err(|e| DBError::ExecError(format!("Failed to delete passkey: {e}")))
+}
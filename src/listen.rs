@@ -0,0 +1,274 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+#[cfg(unix)]
+use crate::config::UnixSocket;
+use listenfd::ListenFd;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashSet;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::{
+    fs::{FileTypeExt, PermissionsExt},
+    net::UnixListener,
+};
+#[cfg(unix)]
+use std::{
+    fs::{self, Permissions},
+    path::Path,
+};
+
+/// Connections waiting to be accepted, same as `HttpServer`'s default
+const BACKLOG: i32 = 1024;
+/// First file descriptor passed by socket activation
+const LISTEN_FDS_START: usize = 3;
+
+/// A socket the server accepts connections on
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+/// TCP address to listen on
+pub struct Bind<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub tls: bool,
+}
+
+fn bind_addr(addr: SocketAddr, only_v6: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+/// Binds every address each host resolves to, and returns the listeners along with whether they use TLS.
+/// An IPv6 socket also accepts IPv4 connections, unless an IPv4 address is bound on the same port, so that `::` alone
+/// is dual-stack while `::` and `0.0.0.0` can be listed together.
+/// Like `HttpServer::bind`, a host fails only if none of its addresses could be bound.
+pub fn bind_tcp(binds: &[Bind]) -> Result<Vec<(TcpListener, bool)>, String> {
+    let mut resolved = Vec::new();
+    for bind in binds {
+        let addrs: Vec<SocketAddr> = (bind.host, bind.port)
+            .to_socket_addrs()
+            .map_err(|e| format!("Failed to resolve `{}:{}`: {e}", bind.host, bind.port))?
+            .collect();
+        if addrs.is_empty() {
+            return Err(format!("`{}` does not resolve to any address", bind.host));
+        }
+        resolved.push((bind, addrs));
+    }
+    let ipv4_ports: HashSet<u16> = resolved
+        .iter()
+        .flat_map(|(_, addrs)| addrs)
+        .filter(|addr| addr.is_ipv4())
+        .map(SocketAddr::port)
+        .collect();
+
+    let mut listeners = Vec::new();
+    for (bind, addrs) in resolved {
+        let mut error = None;
+        let bound = listeners.len();
+        for addr in addrs {
+            match bind_addr(addr, ipv4_ports.contains(&addr.port())) {
+                Ok(listener) => listeners.push((listener, bind.tls)),
+                Err(e) => error = Some(format!("Failed to bind {addr}: {e}")),
+            }
+        }
+        if let Some(error) = error.filter(|_| listeners.len() == bound) {
+            return Err(error);
+        }
+    }
+    Ok(listeners)
+}
+
+/// Binds the Unix domain socket and sets its permissions.
+/// A socket left by a previous run at the same path is replaced, any other file is left untouched.
+#[cfg(unix)]
+pub fn bind_unix(socket: &UnixSocket) -> Result<UnixListener, String> {
+    let path = Path::new(&socket.path);
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        fs::remove_file(path).map_err(|e| format!("Failed to remove old socket `{}`: {e}", socket.path))?;
+    }
+    let listener = UnixListener::bind(path).map_err(|e| format!("Failed to bind socket `{}`: {e}", socket.path))?;
+    if let Some(mode) = socket.mode {
+        fs::set_permissions(path, Permissions::from_mode(mode))
+            .map_err(|e| format!("Failed to set permissions of socket `{}`: {e}", socket.path))?;
+    }
+    Ok(listener)
+}
+
+/// Takes the sockets passed by systemd socket activation, as described by `LISTEN_PID` and `LISTEN_FDS`.
+/// Returns none when the server was not socket activated, or when the variables were meant for another process.
+pub fn activated() -> Result<Vec<Listener>, String> {
+    let mut fds = ListenFd::from_env();
+    let mut listeners = Vec::new();
+    for i in 0..fds.len() {
+        let fd = LISTEN_FDS_START + i;
+        if let Ok(Some(listener)) = fds.take_tcp_listener(i) {
+            listeners.push(Listener::Tcp(listener));
+            continue;
+        }
+        #[cfg(unix)]
+        if let Ok(Some(listener)) = fds.take_unix_listener(i) {
+            listeners.push(Listener::Unix(listener));
+            continue;
+        }
+        return Err(format!(
+            "File descriptor {fd} passed by socket activation is not a TCP or Unix socket"
+        ));
+    }
+    Ok(listeners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpStream};
+    #[cfg(unix)]
+    use std::{
+        os::{fd::OwnedFd, unix::net::UnixStream},
+        process::{Command, Output, Stdio},
+    };
+
+    fn bind(host: &str, port: u16) -> Bind<'_> {
+        Bind { host, port, tls: false }
+    }
+
+    fn free_port() -> u16 {
+        TcpListener::bind((Ipv6Addr::UNSPECIFIED, 0)).unwrap().local_addr().unwrap().port()
+    }
+
+    #[test]
+    fn binds_every_host() {
+        let listeners = bind_tcp(&[
+            bind("127.0.0.1", 0),
+            Bind {
+                host: "::1",
+                port: 0,
+                tls: true,
+            },
+        ])
+        .unwrap();
+        let bound: Vec<(bool, bool)> = listeners.iter().map(|(l, tls)| (l.local_addr().unwrap().is_ipv4(), *tls)).collect();
+        assert_eq!(bound, [(true, false), (false, true)]);
+        assert!(bind_tcp(&[bind("no-such-host.invalid", 0)]).is_err());
+    }
+
+    #[test]
+    fn fails_when_nothing_could_be_bound() {
+        let taken = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = taken.local_addr().unwrap().port();
+        assert!(bind_tcp(&[bind("127.0.0.1", port)]).is_err());
+    }
+
+    #[test]
+    fn ipv6_alone_is_dual_stack() {
+        let listeners = bind_tcp(&[bind("::", 0)]).unwrap();
+        let port = listeners[0].0.local_addr().unwrap().port();
+        TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        TcpStream::connect((Ipv6Addr::LOCALHOST, port)).unwrap();
+    }
+
+    #[test]
+    fn ipv6_leaves_ipv4_to_its_own_socket() {
+        let port = free_port();
+        let listeners = bind_tcp(&[bind("::", port), bind("0.0.0.0", port)]).unwrap();
+        let ipv4 = listeners.iter().position(|(l, _)| l.local_addr().unwrap().is_ipv4()).unwrap();
+        TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        let (_, peer) = listeners[ipv4].0.accept().unwrap();
+        assert!(peer.is_ipv4());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn binds_unix_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tiny-cloud.sock");
+        let socket = UnixSocket {
+            path: path.to_str().unwrap().to_string(),
+            mode: Some(0o660),
+            only: None,
+        };
+
+        let listener = bind_unix(&socket).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+        drop(listener);
+        // The socket left behind is replaced
+        let listener = bind_unix(&socket).unwrap();
+        UnixStream::connect(&path).unwrap();
+        drop(listener);
+
+        // Other files are not
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, "data").unwrap();
+        assert!(bind_unix(&socket).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+    }
+
+    #[test]
+    fn not_activated() {
+        assert!(activated().unwrap().is_empty());
+    }
+
+    /// Runs the ignored test `test` in a new process, socket activated with `first` and `second` as descriptors 3 and 4
+    #[cfg(unix)]
+    fn run_activated(test: &str, first: Stdio, second: Stdio) -> Output {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg("export LISTEN_PID=$$ LISTEN_FDS=2; exec \"$0\" \"$@\" 3<&0 4<&1 0</dev/null 1>&2")
+            .arg(std::env::current_exe().unwrap())
+            .args([test, "--exact", "--ignored", "--test-threads=1"])
+            .stdin(first)
+            .stdout(second)
+            .stderr(Stdio::piped())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        output
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn takes_activated_sockets() {
+        let tcp = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let unix = UnixListener::bind(dir.path().join("tiny-cloud.sock")).unwrap();
+        run_activated(
+            "listen::tests::activated_sockets",
+            Stdio::from(OwnedFd::from(tcp)),
+            Stdio::from(OwnedFd::from(unix)),
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "socket activated by takes_activated_sockets"]
+    fn activated_sockets() {
+        let listeners = activated().unwrap();
+        assert!(matches!(listeners.as_slice(), [Listener::Tcp(_), Listener::Unix(_)]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_activated_files() {
+        let file = tempfile::tempfile().unwrap();
+        let tcp = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        run_activated("listen::tests::activated_file", Stdio::from(file), Stdio::from(OwnedFd::from(tcp)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "socket activated by refuses_activated_files"]
+    fn activated_file() {
+        let error = activated().err().unwrap();
+        assert_eq!(error, "File descriptor 3 passed by socket activation is not a TCP or Unix socket");
+    }
+}
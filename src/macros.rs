@@ -3,7 +3,7 @@
 /// Gets a config's value
 #[macro_export]
 macro_rules! config {
-    ($( $config:ident ).* ) => {{
+    ($( $config:ident ).* ) => {
         &crate::config::get()$(.$config)*
-    }};
+    };
 }
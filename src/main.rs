@@ -1,27 +1,32 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+#[cfg(feature = "acme")]
+mod acme;
+mod admin;
 mod api;
 mod auth;
 mod config;
 mod database;
 mod error;
+mod listen;
 mod plugins;
+mod quota;
+mod reload;
+mod secret;
 mod server;
 #[cfg(not(feature = "no-tls"))]
 mod tls;
 mod token;
+mod upload;
 mod utils;
 mod webui;
 #[macro_use]
 mod macros;
 use std::process::ExitCode;
 
-use actix_web::cookie::Key;
 use common_library::tiny_args::*;
 use log::LevelFilter;
 use plugins::Plugins;
-use tokio::fs;
-use zeroize::Zeroizing;
 
 #[actix_web::main]
 async fn main() -> ExitCode {
@@ -55,8 +60,26 @@ pub fn log_filter(level: &str) -> Result<LevelFilter, String> {
     }
 }
 
+/// Level filters of the logger, the file and syslog ones default to the stdout one
+pub struct LogLevels {
+    pub stdout: LevelFilter,
+    pub file: LevelFilter,
+    #[cfg(feature = "syslog")]
+    pub syslog: LevelFilter,
+}
+
+pub fn log_levels(logging: &config::Logging) -> Result<LogLevels, String> {
+    let stdout = log_filter(&logging.stdout_level)?;
+    Ok(LogLevels {
+        stdout,
+        file: logging.file_level.as_deref().map(log_filter).transpose()?.unwrap_or(stdout),
+        #[cfg(feature = "syslog")]
+        syslog: logging.syslog_level.as_deref().map(log_filter).transpose()?.unwrap_or(stdout),
+    })
+}
+
 async fn run() -> Result<(), String> {
-    let mut plugins = Plugins::new();
+    let mut plugins = Plugins::new().map_err(|e| format!("Failed to load plugins: {e}"))?;
 
     let mut cmd = Command::create("tiny-cloud", env!("CARGO_PKG_DESCRIPTION"))
         .author(env!("CARGO_PKG_AUTHORS"))
@@ -68,7 +91,22 @@ async fn run() -> Result<(), String> {
             "Path to the configuration file",
         )
         .arg(arg! { --create-user }, value!(), "Creates a new user and exits")
-        .arg(arg! { --write-default }, value!(), "Writes the default configuration and exits")
+        .arg(
+            arg! { --write-default },
+            value!(),
+            "Writes the documented default configuration to the --config path and exits",
+        )
+        .arg(arg! { --force }, value!(), "Lets --write-default overwrite an existing file")
+        .arg(
+            arg! { --gen-secret },
+            value!(),
+            "Creates a new session secret key, keeping the old one aside, and exits",
+        )
+        .arg(
+            arg! { --check-config },
+            value!(),
+            "Checks the configuration file, reports every problem found and exits",
+        )
         .arg(arg!(-'h', --help), value!(), "Shows this help and exits");
     cmd = plugins.add_subcmds(cmd);
     let parsed = cmd.parse()?;
@@ -82,14 +120,26 @@ async fn run() -> Result<(), String> {
         return Ok(());
     }
 
+    let config_path = parsed.args.get(arg!(--config)).path().unwrap();
+
     if parsed.args.count(arg! { --write-default }) > 0 {
-        config::write_default(plugins.default_configs())
+        let force = parsed.args.count(arg! { --force }) > 0;
+        config::write_default(plugins.default_configs(), config_path, force)
             .await
             .map_err(|e| format!("Failed to write default config: {e}"))?;
         return Ok(());
     }
 
-    config::open(parsed.args.get(arg!(--config)).path().unwrap()).await?;
+    config::open(config_path).await?;
+
+    if parsed.args.count(arg! { --check-config }) > 0 {
+        println!("Config file `{}` is valid", config_path.display());
+        return Ok(());
+    }
+
+    if parsed.args.count(arg! { --gen-secret }) > 0 {
+        return secret::generate().await.map_err(|e| format!("Failed to create secret key: {e}"));
+    }
 
     let database = database::init().await.map_err(|e| format!("Failed to open database: {e}"))?;
 
@@ -100,41 +150,31 @@ async fn run() -> Result<(), String> {
         return Ok(());
     }
 
-    let default_level = log_filter(config!(logging.stdout_level))?;
+    let levels = log_levels(config!(logging))?;
     tiny_logs::init(
-        default_level,
+        levels.stdout,
         config!(logging.file).clone(),
-        config!(logging.file_level)
-            .clone()
-            .map(|f| log_filter(&f))
-            .transpose()?
-            .unwrap_or(default_level),
+        levels.file,
         #[cfg(feature = "syslog")]
-        config!(logging.syslog_level)
-            .clone()
-            .map(|f| log_filter(&f))
-            .transpose()?
-            .unwrap_or(default_level),
+        levels.syslog,
     )
     .await
     .map_err(|e| format!("Failed to initialize logging: {e}"))?;
 
-    let secret_key = {
-        let path = config!(session_secret_key_path);
-        fs::read(path)
-            .await
-            .map(Zeroizing::new)
-            .map_err(|e| format!("Failed to read secret key file `{path}`: {e}"))?
-    };
-    if secret_key.len() < 64 {
-        return Err("Session secret key must be 64 bytes long".into());
-    }
-    let secret_key = Key::from(&secret_key[..64]);
+    let secret_key = secret::load().await?;
 
     plugins
         .init(config!(plugins))
         .map_err(|e| format!("Failed to initialize plugins: {e}"))?;
 
+    upload::init().await?;
+
+    #[cfg(feature = "webauthn")]
+    auth::webauthn::init()?;
+
+    #[cfg(feature = "acme")]
+    acme::init().await?;
+
     server::start(secret_key, database, plugins)
         .await
         .map_err(|e| format!("Server crashed: {e}"))
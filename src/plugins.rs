@@ -3,9 +3,9 @@
 pub mod error;
 mod macros;
 use crate::*;
-use actix_web::HttpResponse;
-use api::plugins::FileForm;
-use common_library::plugin::{PluginInfo, User};
+use actix_multipart::form::tempfile::TempFile;
+use actix_web::{HttpRequest, HttpResponse};
+use common_library::plugin::{PluginInfo, User, WebUI};
 use common_library::{Json, Toml, plugin::Plugin, toml::Table};
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -17,13 +17,35 @@ pub struct Plugins {
     plugins: HashMap<String, Box<dyn Plugin>>,
 }
 
+/// Every plugin bundled in this build.
+/// Each plugin is enabled by its own `plugin-*` feature.
+fn bundled() -> Vec<(String, Box<dyn Plugin>)> {
+    vec![
+        #[cfg(feature = "plugin-archive")]
+        crate::plugin!(archive::ArchivePlugin),
+    ]
+}
+
 impl Plugins {
-    pub fn new() -> Self {
-        let plugins = HashMap::<String, Box<dyn Plugin>>::from([]);
+    /// Loads all the bundled plugins.
+    /// Fails if two plugins have the same name or if a plugin's sub command is not named after it.
+    pub fn new() -> Result<Self, String> {
+        let mut plugins = HashMap::<String, Box<dyn Plugin>>::new();
+        for (name, plugin) in bundled() {
+            if let Some(subcmd) = plugin.subcmd().filter(|s| s.name() != name) {
+                return Err(format!(
+                    "Plugin '{name}' has a sub command named '{}', it must be named after the plugin",
+                    subcmd.name()
+                ));
+            }
+            if plugins.insert(name.clone(), plugin).is_some() {
+                return Err(format!("Plugin '{name}' was registered more than once"));
+            }
+        }
         PLUGIN_NAMES
             .set(plugins.values().map(|p| p.info()).collect())
             .expect("Tried to initialize PLUGIN_NAMES while already initialized. This is a bug");
-        Self { plugins }
+        Ok(Self { plugins })
     }
 
     pub fn add_subcmds(&self, mut cmd: Command) -> Command {
@@ -65,6 +87,16 @@ impl Plugins {
         Ok(())
     }
 
+    /// Returns the web UI of a plugin.
+    /// Returns [`None`] if the plugin does not exist or if the user is not allowed to use it.
+    pub async fn webui(&self, name: &str, user: &User) -> Option<WebUI> {
+        let plugin = self.plugins.get(name)?;
+        if plugin.info().admin_only && !user.is_admin {
+            return None;
+        }
+        Some(plugin.webui().await)
+    }
+
     pub async fn request(&self, name: String, user: Option<User>, body: Json) -> HttpResponse {
         if let Some(plugin) = self.plugins.get(&name) {
             if plugin.info().admin_only && !user.as_ref().map(|u| u.is_admin).unwrap_or(false) {
@@ -77,13 +109,36 @@ impl Plugins {
         }
     }
 
-    pub async fn file(&self, name: String, user: Option<User>, file: FileForm) -> HttpResponse {
+    /// Checks whether a plugin exists and the user is allowed to use it
+    pub fn allows(&self, name: &str, user: &Option<User>) -> bool {
+        self.plugins
+            .get(name)
+            .map(|p| !p.info().admin_only || user.as_ref().map(|u| u.is_admin).unwrap_or(false))
+            .unwrap_or(false)
+    }
+
+    pub async fn file(&self, name: String, user: Option<User>, file: TempFile, info: Json) -> HttpResponse {
         if let Some(plugin) = self.plugins.get(&name) {
             if plugin.info().admin_only && !user.as_ref().map(|u| u.is_admin).unwrap_or(false) {
                 return HttpResponse::NotFound().body("");
             }
             let path = plugin_path(&user, name);
-            plugin.file(user, file.file, file.info.into_inner(), path).await
+            plugin.file(user, file, info, path).await
+        } else {
+            HttpResponse::NotFound().body("")
+        }
+    }
+
+    pub async fn download(&self, req: &HttpRequest, name: String, user: Option<User>, file: String) -> HttpResponse {
+        if let Some(plugin) = self.plugins.get(&name) {
+            if plugin.info().admin_only && !user.as_ref().map(|u| u.is_admin).unwrap_or(false) {
+                return HttpResponse::NotFound().body("");
+            }
+            let path = plugin_path(&user, name);
+            match plugin.download(user, file, path).await {
+                Ok(file) => file.into_response(req),
+                Err(resp) => resp,
+            }
         } else {
             HttpResponse::NotFound().body("")
         }
@@ -106,6 +161,12 @@ fn plugin_path(user: &Option<User>, plugin: String) -> PathBuf {
     path
 }
 
+/// Registers no plugin, unless they were already loaded. Used by tests.
+#[cfg(test)]
+pub fn use_none() {
+    let _ = PLUGIN_NAMES.set(Vec::new());
+}
+
 pub fn list() -> &'static Vec<&'static PluginInfo> {
     PLUGIN_NAMES
         .get()
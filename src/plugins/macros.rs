@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-/// Returns a new plugin instance.
-/// Requires the feature's name and the plugin's specific type.
+/// Returns a new plugin instance along with its name.
+/// Requires the plugin's specific type.
 /// The plugin must implement a `new() -> Self` function.
 #[macro_export]
 macro_rules! plugin {
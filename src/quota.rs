@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+pub mod error;
+use crate::config;
+use crate::database::auth;
+use async_sqlite::Pool;
+use common_library::plugin::User;
+use error::QuotaError;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long the usage of a user is reused by quota checks before walking its files again
+const USAGE_CACHE_TIME: Duration = Duration::from_secs(60);
+
+/// Usage of users when it was last calculated, plus the bytes accepted since then
+static USAGE: LazyLock<Mutex<HashMap<String, (Instant, u64)>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+/// Sizes of the unfinished uploads of users by upload id, counted as used until they are finished or removed
+static RESERVED: LazyLock<Mutex<HashMap<String, (String, u64)>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Sums the size of every file inside a directory. Symlinks are not followed.
+fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += meta.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Returns the bytes used by a user across all plugins
+pub async fn usage(username: &str) -> Result<u64, QuotaError> {
+    let mut path = PathBuf::from(config!(data_directory));
+    path.push("users");
+    path.push(username);
+    let used = tokio::task::spawn_blocking(move || match dir_size(&path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        res => res,
+    })
+    .await
+    .map_err(|e| QuotaError::InternalError(format!("Failed to calculate usage: {e}")))?
+    .map_err(|e| QuotaError::InternalError(format!("Failed to calculate usage: {e}")))?;
+    USAGE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(username.into(), (Instant::now(), used));
+    Ok(used)
+}
+
+/// Returns the usage of a user calculated in the last [`USAGE_CACHE_TIME`], or calculates it again
+async fn cached_usage(username: &str) -> Result<u64, QuotaError> {
+    let cached = USAGE.lock().unwrap_or_else(PoisonError::into_inner).get(username).copied();
+    match cached {
+        Some((time, used)) if time.elapsed() < USAGE_CACHE_TIME => Ok(used),
+        _ => usage(username).await,
+    }
+}
+
+/// Returns the quota of a user in bytes, falling back to `limits.default_quota`.
+/// None means the user has no limit.
+pub async fn limit(pool: &Pool, username: &str) -> Result<Option<u64>, QuotaError> {
+    let quota = auth::get_quota(pool, username.into()).await.map_err(|e| e.into())?;
+    Ok(quota.or(*config!(limits.default_quota)))
+}
+
+/// Bytes reserved by the unfinished uploads of a user
+fn reserved_by(reserved: &HashMap<String, (String, u64)>, username: &str) -> u64 {
+    reserved.values().filter(|(user, _)| user == username).map(|(_, size)| size).sum()
+}
+
+/// Checks whether `size` more bytes fit in the quota of a user, and counts them as used until its usage is calculated again.
+/// Requests without a user are not subject to quotas.
+pub async fn check(pool: &Pool, user: &Option<User>, size: u64) -> Result<(), QuotaError> {
+    check_with(pool, user, size, Reservation::None).await
+}
+
+/// Checks whether an upload of `size` bytes fits in the quota of a user along with its other unfinished uploads,
+/// and counts it as used until it is finished with [`finish`] or removed with [`release`].
+pub async fn reserve(pool: &Pool, user: &Option<User>, id: &str, size: u64) -> Result<(), QuotaError> {
+    check_with(pool, user, size, Reservation::Take(id)).await
+}
+
+/// Replaces the reservation of a finished upload by its actual size, if it still fits in the quota of its user
+pub async fn finish(pool: &Pool, user: &Option<User>, id: &str, size: u64) -> Result<(), QuotaError> {
+    check_with(pool, user, size, Reservation::Finish(id)).await
+}
+
+/// Stops counting an upload that was removed before it was finished
+pub fn release(id: &str) {
+    RESERVED.lock().unwrap_or_else(PoisonError::into_inner).remove(id);
+}
+
+/// Counts an unfinished upload found on startup, without checking it
+pub fn restore(id: &str, username: &str, size: u64) {
+    RESERVED
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(id.into(), (username.into(), size));
+}
+
+/// What a quota check does with the reservation of an upload
+enum Reservation<'a> {
+    None,
+    Take(&'a str),
+    Finish(&'a str),
+}
+
+async fn check_with(pool: &Pool, user: &Option<User>, size: u64, reservation: Reservation<'_>) -> Result<(), QuotaError> {
+    let Some(user) = user else {
+        return Ok(());
+    };
+    let quota = limit(pool, &user.name).await?;
+    let used = match quota {
+        Some(_) => cached_usage(&user.name).await?,
+        None => 0,
+    };
+    // Held from the check to the reservation, so that parallel uploads can't all fit in the same space
+    let mut reserved = RESERVED.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Reservation::Finish(id) = reservation {
+        reserved.remove(id);
+    }
+    let total = used.saturating_add(reserved_by(&reserved, &user.name)).saturating_add(size);
+    if quota.is_some_and(|quota| total > quota) {
+        return Err(QuotaError::Exceeded);
+    }
+    match reservation {
+        Reservation::Take(id) => {
+            reserved.insert(id.into(), (user.name.clone(), size));
+        }
+        Reservation::None | Reservation::Finish(_) => {
+            if let Some((_, used)) = USAGE.lock().unwrap_or_else(PoisonError::into_inner).get_mut(&user.name) {
+                *used = used.saturating_add(size);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::memory;
+
+    async fn user(pool: &Pool, name: &str, quota: u64) -> Option<User> {
+        auth::add_user(pool, name.into(), String::new(), String::new(), false)
+            .await
+            .unwrap();
+        auth::set_quota(pool, name.into(), Some(quota)).await.unwrap();
+        Some(User {
+            name: name.into(),
+            is_admin: false,
+        })
+    }
+
+    #[actix_web::test]
+    async fn unfinished_uploads_count_against_the_quota() {
+        let pool = memory().await;
+        let alice = user(&pool, "reserving", 100).await;
+
+        reserve(&pool, &alice, "first", 60).await.unwrap();
+        assert!(matches!(reserve(&pool, &alice, "second", 60).await, Err(QuotaError::Exceeded)));
+        assert!(matches!(check(&pool, &alice, 60).await, Err(QuotaError::Exceeded)));
+        release("first");
+        reserve(&pool, &alice, "second", 60).await.unwrap();
+        release("second");
+    }
+
+    #[actix_web::test]
+    async fn finished_uploads_replace_their_reservation() {
+        let pool = memory().await;
+        let alice = user(&pool, "finishing", 100).await;
+
+        reserve(&pool, &alice, "whole", 100).await.unwrap();
+        finish(&pool, &alice, "whole", 100).await.unwrap();
+        // The file is counted until the usage is calculated again, even though it is not stored yet
+        assert!(matches!(check(&pool, &alice, 1).await, Err(QuotaError::Exceeded)));
+        assert!(matches!(reserve(&pool, &alice, "more", 1).await, Err(QuotaError::Exceeded)));
+    }
+
+    #[actix_web::test]
+    async fn parallel_reservations_fit_together() {
+        let pool = memory().await;
+        let alice = user(&pool, "parallel", 100).await;
+
+        let ids: Vec<String> = (0..10).map(|i| format!("parallel-{i}")).collect();
+        let results = futures_util::future::join_all(ids.iter().map(|id| reserve(&pool, &alice, id, 30))).await;
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 3);
+        for id in &ids {
+            release(id);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use common_library::error::ErrToResponse;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum QuotaError {
+    #[error("An internal server error occurred")]
+    InternalError(String),
+    #[error("User was not found")]
+    UserNotFound,
+    #[error("Storage quota exceeded")]
+    Exceeded,
+}
+
+impl ErrToResponse for QuotaError {
+    fn error(&self) -> &'static str {
+        "QuotaError"
+    }
+
+    fn err_type(&self) -> &'static str {
+        match self {
+            Self::InternalError(_) => stringify!(InternalError),
+            Self::UserNotFound => stringify!(UserNotFound),
+            Self::Exceeded => stringify!(Exceeded),
+        }
+    }
+
+    fn msg(&self) -> String {
+        self.to_string()
+    }
+
+    fn http_code(&self) -> HttpResponseBuilder {
+        match self {
+            Self::InternalError(_) => HttpResponse::InternalServerError(),
+            Self::UserNotFound => HttpResponse::NotFound(),
+            Self::Exceeded => HttpResponse::InsufficientStorage(),
+        }
+    }
+
+    fn handle(&self) {
+        if let Self::InternalError(err) = self {
+            log::error!("An internal server error occurred while checking quotas: {err}");
+        }
+    }
+}
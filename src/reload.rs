@@ -0,0 +1,103 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::config;
+use crate::config::{Config, Limits, Logging};
+
+/// Adds the name of the field to `$fields` if it differs between the two configs
+macro_rules! changed {
+    ($fields:ident, $old:ident, $new:ident, $($field:ident).+) => {
+        if $old.$($field).+ != $new.$($field).+ {
+            $fields.push(stringify!($($field).+));
+        }
+    };
+}
+
+/// Reads the config file again and applies the sections that can change while running: `registration`, `api_tokens`,
+/// `cred_size`, `limits`, the logging levels and the TLS certificate.
+/// Changes to anything else are kept for the next restart and logged.
+pub async fn reload() -> Result<(), String> {
+    let new = config::read_again().await?;
+    let levels = crate::log_levels(&new.logging)?;
+    let old = config::get();
+
+    let mut restart = Vec::new();
+    changed!(restart, old, new, server_name);
+    changed!(restart, old, new, description);
+    changed!(restart, old, new, url_prefix);
+    changed!(restart, old, new, data_directory);
+    changed!(restart, old, new, session_secret_key_path);
+    changed!(restart, old, new, previous_secret_keys);
+    changed!(restart, old, new, session_store);
+    changed!(restart, old, new, homepage_script);
+    changed!(restart, old, new, server);
+    changed!(restart, old, new, logging.file);
+    // The certificate is reloaded, but TLS can't be turned on or off
+    #[cfg(not(feature = "no-tls"))]
+    if old.tls.is_some() != new.tls.is_some() {
+        restart.push("tls");
+    }
+    changed!(restart, old, new, tls_policy);
+    changed!(restart, old, new, login_throttle);
+    changed!(restart, old, new, webauthn);
+    changed!(restart, old, new, acme);
+    changed!(restart, old, new, client_auth);
+    // Both are given to the request extractors when the server starts
+    changed!(restart, old, new, limits.file_upload_size);
+    changed!(restart, old, new, limits.payload_size);
+    changed!(restart, old, new, duration);
+    changed!(restart, old, new, plugins);
+
+    let mut merged = Config::clone(&old);
+    merged.registration = new.registration;
+    merged.api_tokens = new.api_tokens;
+    merged.cred_size = new.cred_size;
+    merged.limits = Limits {
+        file_upload_size: old.limits.file_upload_size,
+        payload_size: old.limits.payload_size,
+        ..new.limits
+    };
+    merged.logging = Logging {
+        file: old.logging.file.clone(),
+        ..new.logging
+    };
+    #[cfg(not(feature = "no-tls"))]
+    if let (Some(_), Some(tls)) = (&old.tls, &new.tls) {
+        merged.tls = Some(tls.clone());
+        if let Err(e) = crate::tls::reload(tls) {
+            log::error!("Failed to reload TLS certificate, keeping the current one: {e}");
+        }
+    }
+    config::replace(merged);
+    tiny_logs::set_levels(
+        levels.stdout,
+        levels.file,
+        #[cfg(feature = "syslog")]
+        levels.syslog,
+    );
+
+    log::info!("Configuration reloaded");
+    if !restart.is_empty() {
+        log::warn!("Changes to {} will only apply after a restart", restart.join(", "));
+    }
+    Ok(())
+}
+
+/// Reloads the config every time the process receives SIGHUP
+#[cfg(unix)]
+pub async fn watch() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::error!("Failed to listen for SIGHUP, the config can't be reloaded: {e}");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        log::info!("Received SIGHUP, reloading configuration...");
+        if let Err(e) = reload().await {
+            log::error!("Failed to reload configuration, keeping the current one: {e}");
+        }
+    }
+}
@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::config;
+use actix_web::{
+    Error, HttpRequest,
+    body::MessageBody,
+    cookie::{Cookie, CookieJar, Key, SameSite, time::Duration},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderValue},
+    middleware::Next,
+};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::{fs, io::AsyncWriteExt};
+use zeroize::Zeroizing;
+
+/// Name of the session cookie
+pub const COOKIE_NAME: &str = "auth";
+const KEY_SIZE: usize = 64;
+
+/// Current key along with the previous ones that have not expired yet
+static KEYS: OnceLock<(Key, Vec<Key>)> = OnceLock::new();
+
+fn now() -> Result<u64, String> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .map_err(|e| format!("Time error: {e}"))
+}
+
+async fn read_key(path: &str) -> Result<Key, String> {
+    let key = fs::read(path)
+        .await
+        .map(Zeroizing::new)
+        .map_err(|e| format!("Failed to read secret key file `{path}`: {e}"))?;
+    if key.len() < KEY_SIZE {
+        return Err(format!(
+            "Secret key `{path}` must be {KEY_SIZE} bytes long, create one with `--gen-secret`"
+        ));
+    }
+    Ok(Key::from(&key[..KEY_SIZE]))
+}
+
+/// Reads the session secret key and the previous ones that have not expired yet.
+/// Returns the current key, the previous ones are kept to translate old cookies.
+pub async fn load() -> Result<Key, String> {
+    let key = read_key(config!(session_secret_key_path)).await?;
+    let now = now()?;
+    let mut previous = Vec::new();
+    for old in config!(previous_secret_keys).iter().flatten() {
+        if old.expires <= now {
+            log::info!("Previous secret key `{}` has expired and can be removed from the config", old.path);
+            continue;
+        }
+        previous.push(read_key(&old.path).await?);
+    }
+    KEYS.set((key.clone(), previous))
+        .map_err(|_| "Secret keys were already loaded".to_string())?;
+    Ok(key)
+}
+
+async fn write_key(path: &Path) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(path)
+        .await
+        .map_err(|e| format!("Failed to create secret key file `{}`: {e}", path.display()))?;
+    let key = Key::generate();
+    file.write_all(key.master())
+        .await
+        .map_err(|e| format!("Failed to write secret key file `{}`: {e}", path.display()))?;
+    file.sync_all()
+        .await
+        .map_err(|e| format!("Failed to write secret key file `{}`: {e}", path.display()))
+}
+
+/// Creates a new session secret key at `session_secret_key_path`, readable only by its owner.
+/// An existing key is moved aside and the config needed to keep accepting it is printed.
+pub async fn generate() -> Result<(), String> {
+    let path = PathBuf::from(config!(session_secret_key_path));
+    let mut new_path = path.clone().into_os_string();
+    new_path.push(".new");
+    let new_path = PathBuf::from(new_path);
+    write_key(&new_path).await?;
+
+    if !fs::try_exists(&path).await.unwrap_or(false) {
+        fs::rename(&new_path, &path)
+            .await
+            .map_err(|e| format!("Failed to move secret key to `{}`: {e}", path.display()))?;
+        println!("Created secret key `{}`", path.display());
+        return Ok(());
+    }
+
+    let now = now()?;
+    let mut old_path = path.clone().into_os_string();
+    old_path.push(format!(".{now}.old"));
+    let old_path = PathBuf::from(old_path);
+    fs::rename(&path, &old_path)
+        .await
+        .map_err(|e| format!("Failed to move old secret key to `{}`: {e}", old_path.display()))?;
+    fs::rename(&new_path, &path)
+        .await
+        .map_err(|e| format!("Failed to move secret key to `{}`: {e}", path.display()))?;
+    let expires = now + u64::from(*config!(duration.cookie_minutes)) * 60;
+    println!(
+        "Created secret key `{}`, the old one was moved to `{}`.",
+        path.display(),
+        old_path.display()
+    );
+    println!("To keep existing sessions valid until their cookies expire, add this to the config and restart the server:\n");
+    println!(
+        "[[previous_secret_keys]]\npath = {:?}\nexpires = {expires}",
+        old_path.display().to_string()
+    );
+    Ok(())
+}
+
+/// Whether cookies sent in response to the request must only be sent over HTTPS: when it came through a TLS
+/// listener, or through a proxy that received it over HTTPS
+fn secure_cookies(req: &HttpRequest) -> bool {
+    req.app_config().secure() || (*config!(server.is_behind_proxy) && req.connection_info().scheme() == "https")
+}
+
+/// Middleware that marks the session cookie as secure depending on the connection, since listeners with and without
+/// TLS share the same session middleware
+pub async fn secure(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let secure = secure_cookies(req.request());
+    let mut res = next.call(req).await?;
+    if !secure {
+        return Ok(res);
+    }
+    let cookies: Vec<Cookie<'static>> = res
+        .response()
+        .cookies()
+        .filter(|c| c.name() == COOKIE_NAME)
+        .map(|c| c.into_owned())
+        .collect();
+    if cookies.is_empty() {
+        return Ok(res);
+    }
+    res.response_mut().del_cookie(COOKIE_NAME);
+    for mut cookie in cookies {
+        cookie.set_secure(true);
+        res.response_mut()
+            .add_cookie(&cookie)
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+    Ok(res)
+}
+
+/// Returns the session cookie encrypted with the current key if it was encrypted with a previous one
+fn translate(cookie: Cookie<'static>) -> Option<Cookie<'static>> {
+    let (key, previous) = KEYS.get()?;
+    let mut jar = CookieJar::new();
+    if jar.private(key).decrypt(cookie.clone()).is_some() {
+        return None;
+    }
+    let plain = previous.iter().find_map(|old| jar.private(old).decrypt(cookie.clone()))?;
+    jar.private_mut(key).add(plain);
+    jar.get(COOKIE_NAME).cloned()
+}
+
+/// Middleware that lets sessions started before a key rotation continue, by encrypting their cookie with the current key
+pub async fn rotate(mut req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    if KEYS.get().is_none_or(|(_, previous)| previous.is_empty()) {
+        return next.call(req).await;
+    }
+    // The header is parsed by hand since parsed cookies would be cached in the request before they are translated
+    let mut translated = None;
+    let mut pairs = Vec::new();
+    for value in req.headers().get_all(header::COOKIE) {
+        for pair in value
+            .to_str()
+            .unwrap_or_default()
+            .split(';')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+        {
+            let new = Cookie::parse(pair.to_string())
+                .ok()
+                .filter(|c| c.name() == COOKIE_NAME)
+                .and_then(translate);
+            match new {
+                Some(cookie) => {
+                    pairs.push(format!("{COOKIE_NAME}={}", cookie.value()));
+                    translated = Some(cookie);
+                }
+                None => pairs.push(pair.to_string()),
+            }
+        }
+    }
+    let Some(mut cookie) = translated else {
+        return next.call(req).await;
+    };
+    let header = HeaderValue::from_str(&pairs.join("; ")).map_err(actix_web::error::ErrorBadRequest)?;
+    req.headers_mut().insert(header::COOKIE, header);
+    let secure = secure_cookies(req.request());
+
+    let mut res = next.call(req).await?;
+    // The session middleware sets the cookie only when the session changes
+    let already_set = res.response().cookies().any(|c| c.name() == COOKIE_NAME);
+    if !already_set {
+        cookie.set_path("/");
+        cookie.set_http_only(true);
+        cookie.set_same_site(SameSite::Strict);
+        cookie.set_secure(secure);
+        cookie.set_max_age(Duration::minutes((*config!(duration.cookie_minutes)).into()));
+        res.response_mut()
+            .add_cookie(&cookie)
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+    Ok(res)
+}
@@ -1,17 +1,30 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use crate::{api, config, error::RequestError, plugins::Plugins, utils, webui};
+#[cfg(feature = "acme")]
+use crate::acme;
+#[cfg(not(feature = "no-tls"))]
+use crate::tls;
+use crate::{
+    api, auth, config,
+    error::RequestError,
+    listen::{self, Bind, Listener},
+    plugins::Plugins,
+    reload, secret, upload, utils, webui,
+};
 use actix_identity::IdentityMiddleware;
 use actix_multipart::form::MultipartFormConfig;
-use actix_session::{SessionMiddleware, config::PersistentSession, storage::CookieSessionStore};
+use actix_session::{SessionMiddleware, config::PersistentSession};
 use actix_web::{
-    App, HttpServer,
+    App, HttpRequest, HttpResponse, HttpServer,
     cookie::{Key, SameSite, time::Duration},
-    error, middleware,
+    error,
+    http::header,
+    middleware,
     web::{self, Data},
 };
 use async_sqlite::Pool;
 use common_library::error::ErrToResponse;
+use futures_util::future::try_join;
 
 fn warn_msg(binding: &str) {
     log::info!("Binding to {binding}");
@@ -22,7 +35,56 @@ fn warn_msg(binding: &str) {
     log::warn!("Any other configuration is *UNSAFE* and may be subject to cyberattacks.");
 }
 
+/// Removes the port from the value of a Host header, keeping the brackets of IPv6 addresses
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    }
+}
+
+/// Answers with a permanent redirect to the same URL over HTTPS, on `http_redirect.https_port`
+async fn redirect_https(req: HttpRequest) -> HttpResponse {
+    let host = strip_port(req.connection_info().host()).to_string();
+    let port = config!(server.http_redirect).as_ref().and_then(|r| r.https_port).unwrap_or(443);
+    let authority = if port == 443 { host } else { format!("{host}:{port}") };
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    HttpResponse::MovedPermanently()
+        .insert_header((header::LOCATION, format!("https://{authority}{path}")))
+        .finish()
+}
+
+/// Adds the endpoint answering ACME HTTP-01 challenges, available only with feature "acme"
+#[cfg_attr(not(feature = "acme"), allow(unused_variables))]
+fn acme_routes(cfg: &mut web::ServiceConfig) {
+    #[cfg(feature = "acme")]
+    cfg.service(acme::http_challenge);
+}
+
+/// Adds the endpoint logging in with a client certificate, available only with TLS
+#[cfg_attr(feature = "no-tls", allow(unused_variables))]
+fn certificate_routes(cfg: &mut web::ServiceConfig) {
+    #[cfg(not(feature = "no-tls"))]
+    cfg.service(api::auth::certificate);
+}
+
+/// Adds the passkey endpoints, available only with feature "webauthn"
+#[cfg_attr(not(feature = "webauthn"), allow(unused_variables))]
+fn webauthn_routes(cfg: &mut web::ServiceConfig) {
+    #[cfg(feature = "webauthn")]
+    cfg.service(
+        web::scope("/webauthn")
+            .service(api::webauthn::register_start)
+            .service(api::webauthn::register_finish)
+            .service(api::webauthn::login_start)
+            .service(api::webauthn::login_finish)
+            .service(api::webauthn::list)
+            .service(api::webauthn::delete),
+    );
+}
+
 pub async fn start(secret_key: Key, database: Pool, plugins: Plugins) -> Result<(), String> {
+    let sweep_database = database.clone();
     let database = Data::new(database);
     let plugins = Data::new(plugins);
     let server = HttpServer::new(move || {
@@ -60,22 +122,20 @@ pub async fn start(secret_key: Key, database: Pool, plugins: Plugins) -> Result<
                     .build(),
             )
             .wrap({
-                let session_middleware = SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
-                    .cookie_name("auth".to_owned())
+                let store = auth::store::Store::new(database.get_ref().clone());
+                SessionMiddleware::builder(store, secret_key.clone())
+                    .cookie_name(secret::COOKIE_NAME.to_owned())
                     .cookie_http_only(true)
                     .cookie_same_site(SameSite::Strict)
                     .session_lifecycle(
                         PersistentSession::default().session_ttl(Duration::minutes((*config!(duration.cookie_minutes)).into())),
-                    );
-                #[cfg(feature = "no-tls")]
-                {
-                    session_middleware.cookie_secure(false).build()
-                }
-                #[cfg(not(feature = "no-tls"))]
-                {
-                    session_middleware.cookie_secure(config!(tls).is_some()).build()
-                }
+                    )
+                    .cookie_secure(false)
+                    .build()
             })
+            .wrap(middleware::from_fn(secret::secure))
+            .wrap(middleware::from_fn(secret::rotate))
+            .configure(acme_routes)
             .service(web::redirect(utils::make_url(""), utils::make_url("/ui")))
             .service(
                 web::scope(config!(url_prefix))
@@ -89,22 +149,55 @@ pub async fn start(secret_key: Key, database: Pool, plugins: Plugins) -> Result<
                             .service(webui::root)
                             .service(webui::register_page)
                             .service(webui::login_page)
-                            .service(webui::settings_page),
+                            .service(webui::settings_page)
+                            .service(webui::users_page)
+                            .service(webui::plugin_page),
                     )
                     .service(
                         web::scope("/api")
                             .service(api::info)
                             .service(api::plugins::handler)
                             .service(api::plugins::file)
+                            .service(api::plugins::download)
+                            .service(
+                                web::scope("/upload")
+                                    .service(api::upload::new)
+                                    .service(api::upload::status)
+                                    .service(api::upload::append)
+                                    .service(api::upload::finish)
+                                    .service(api::upload::cancel),
+                            )
                             .service(
                                 web::scope("/auth")
                                     .service(api::auth::login)
                                     .service(api::auth::register)
                                     .service(api::auth::logout)
                                     .service(api::auth::logoutall)
+                                    .service(api::auth::sessions)
+                                    .service(api::auth::revoke)
                                     .service(api::auth::delete)
                                     .service(api::auth::changepwd)
-                                    .service(api::auth::changetotp),
+                                    .service(api::auth::changetotp)
+                                    .service(
+                                        web::scope("/apitokens")
+                                            .service(api::api_token::new)
+                                            .service(api::api_token::list)
+                                            .service(api::api_token::revoke),
+                                    )
+                                    .configure(certificate_routes)
+                                    .configure(webauthn_routes),
+                            )
+                            .service(web::scope("/quota").service(api::quota::usage))
+                            .service(
+                                web::scope("/admin/users")
+                                    .service(api::admin::list)
+                                    .service(api::admin::setadmin)
+                                    .service(api::admin::setquota)
+                                    .service(api::admin::logout)
+                                    .service(api::admin::sessions)
+                                    .service(api::admin::killsession)
+                                    .service(api::admin::resettotp)
+                                    .service(api::admin::delete),
                             )
                             .service(
                                 web::scope("/token")
@@ -116,49 +209,131 @@ pub async fn start(secret_key: Key, database: Pool, plugins: Plugins) -> Result<
             )
     });
 
-    // Setting TLS
-    let server = {
-        let binding = format!("{}:{}", config!(server.host), config!(server.port));
-        #[cfg(feature = "openssl")]
-        {
-            use crate::tls;
-            if let Some(config) = config!(tls) {
-                log::info!("Binding to {binding} with TLS (openssl)");
-                server
-                    .bind_openssl(binding, tls::get_openssl_config(config)?)
-                    .map_err(|e| format!("Failed to bind server with TLS (openssl): {e}"))?
-            } else {
-                warn_msg(&binding);
-                server.bind(binding).map_err(|e| format!("Failed to bind server: {e}"))?
+    // Sockets passed by systemd replace `host`:`port`
+    let activated = listen::activated()?;
+    #[cfg(unix)]
+    let only_unix = config!(server.unix_socket).as_ref().is_some_and(|s| s.only == Some(true));
+    #[cfg(not(unix))]
+    let only_unix = false;
+    let config = config::get();
+    let has_tls = config.has_tls();
+    let mut binds = Vec::new();
+    if activated.is_empty() && !only_unix {
+        binds.push(Bind {
+            host: &config.server.host,
+            port: config.server.port,
+            tls: has_tls,
+        });
+    }
+    for address in config.server.listeners.iter().flatten() {
+        binds.push(Bind {
+            host: &address.host,
+            port: address.port,
+            tls: address.tls.unwrap_or(has_tls),
+        });
+    }
+    let mut tcp = listen::bind_tcp(&binds)?;
+    #[cfg(not(feature = "no-tls"))]
+    let server = server.on_connect(tls::keep_peer_certificate);
+    let mut server = server;
+    for listener in activated {
+        match listener {
+            Listener::Tcp(listener) => tcp.push((listener, has_tls)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let path = listener
+                    .local_addr()
+                    .ok()
+                    .and_then(|a| a.as_pathname().map(|p| p.display().to_string()))
+                    .unwrap_or_default();
+                log::info!("Binding to unix socket {path} passed by systemd");
+                server = server
+                    .listen_uds(listener)
+                    .map_err(|e| format!("Failed to listen on socket passed by systemd: {e}"))?;
             }
         }
+    }
+    #[cfg(unix)]
+    if let Some(socket) = config!(server.unix_socket) {
+        log::info!("Binding to unix socket {}", socket.path);
+        server = server
+            .listen_uds(listen::bind_unix(socket)?)
+            .map_err(|e| format!("Failed to listen on socket `{}`: {e}", socket.path))?;
+    }
 
+    // Setting TLS
+    #[cfg(feature = "rustls")]
+    let rustls_config = config!(tls).as_ref().map(tls::get_rustls_config).transpose()?;
+    for (listener, use_tls) in tcp {
+        let binding = listener.local_addr().map_or_else(|e| e.to_string(), |a| a.to_string());
+        #[cfg(feature = "openssl")]
+        if let Some(config) = config!(tls).as_ref().filter(|_| use_tls) {
+            log::info!("Binding to {binding} with TLS (openssl)");
+            server = server
+                .listen_openssl(listener, tls::get_openssl_config(config)?)
+                .map_err(|e| format!("Failed to bind server with TLS (openssl): {e}"))?;
+            continue;
+        }
         #[cfg(feature = "rustls")]
-        {
-            use crate::tls;
-            if let Some(config) = config!(tls) {
-                log::info!("Binding to {binding} with TLS (rustls)");
-                server
-                    .bind_rustls_0_23(binding, tls::get_rustls_config(config)?)
-                    .map_err(|e| format!("Failed to bind server with TLS (rustls): {e}"))?
-            } else {
-                warn_msg(&binding);
-                server.bind(binding).map_err(|e| format!("Failed to bind server: {e}"))?
-            }
+        if let Some(config) = rustls_config.as_ref().filter(|_| use_tls) {
+            log::info!("Binding to {binding} with TLS (rustls)");
+            server = server
+                .listen_rustls_0_23(listener, config.clone())
+                .map_err(|e| format!("Failed to bind server with TLS (rustls): {e}"))?;
+            continue;
+        }
+        if use_tls {
+            return Err(format!("Can't use TLS on {binding}, `tls` is not configured"));
         }
+        warn_msg(&binding);
+        server = server.listen(listener).map_err(|e| format!("Failed to bind server: {e}"))?;
+    }
 
-        #[cfg(feature = "no-tls")]
-        {
-            warn_msg(&binding);
-            server.bind(binding).map_err(|e| format!("Failed to bind server: {e}"))?
+    let redirect = match config!(server.http_redirect) {
+        Some(redirect) => {
+            let listeners = listen::bind_tcp(&[Bind {
+                host: &redirect.host,
+                port: redirect.port,
+                tls: false,
+            }])?;
+            let mut server = HttpServer::new(|| {
+                App::new()
+                    .wrap(middleware::Logger::default())
+                    .configure(acme_routes)
+                    .default_service(web::to(redirect_https))
+            })
+            .workers(1);
+            for (listener, _) in listeners {
+                log::info!(
+                    "Redirecting {} to HTTPS",
+                    listener.local_addr().map_or_else(|e| e.to_string(), |a| a.to_string())
+                );
+                server = server.listen(listener).map_err(|e| format!("Failed to bind HTTP redirect: {e}"))?;
+            }
+            Some(server.run())
         }
+        None => None,
     };
 
+    actix_web::rt::spawn(upload::collect_garbage());
+    actix_web::rt::spawn(auth::throttle::collect_garbage());
+    actix_web::rt::spawn(auth::store::sweep(sweep_database));
+    #[cfg(unix)]
+    actix_web::rt::spawn(reload::watch());
+    #[cfg(not(feature = "no-tls"))]
+    if config!(tls).is_some() {
+        actix_web::rt::spawn(tls::watch());
+    }
+    #[cfg(feature = "acme")]
+    if config!(acme).is_some() {
+        actix_web::rt::spawn(acme::renew());
+    }
+
     log::info!("Starting Tiny Cloud on version {}...", env!("CARGO_PKG_VERSION"),);
-    server
-        .workers(*config!(server.workers))
-        .run()
-        .await
-        .map_err(|e| format!("Error while running: {e}"))?;
-    Ok(())
+    let server = server.workers(*config!(server.workers)).run();
+    match redirect {
+        Some(redirect) => try_join(server, redirect).await.map(|_| ()),
+        None => server.await,
+    }
+    .map_err(|e| format!("Error while running: {e}"))
 }
@@ -1,55 +1,562 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use crate::config::Tls;
+use crate::config;
+use crate::config::{CertificateName, ClientAuth, Tls, TlsPolicy, TlsProfile, TlsVersion};
 #[cfg(feature = "openssl")]
-use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
+use actix_tls::accept::openssl::TlsStream;
 #[cfg(feature = "rustls")]
-use rustls::{ServerConfig, pki_types::CertificateDer};
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::{dev::Extensions, rt::net::TcpStream};
+#[cfg(all(feature = "acme", feature = "openssl"))]
+use openssl::ssl::NameType;
+#[cfg(feature = "openssl")]
+use openssl::{
+    error::ErrorStack,
+    pkey::{PKey, Private},
+    ssl::{
+        AlpnError, SniError, SslAcceptor, SslAcceptorBuilder, SslContext, SslMethod, SslRef, SslVerifyMode, SslVersion, cipher_name,
+        select_next_proto,
+    },
+    x509::{X509, X509Name},
+};
+#[cfg(feature = "rustls")]
+use rustls::{
+    ConfigBuilder, RootCertStore, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion, WantsVerifier,
+    crypto::CryptoProvider,
+    pki_types::CertificateDer,
+    server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier, danger::ClientCertVerifier},
+    sign::CertifiedKey,
+    version::{TLS12, TLS13},
+};
 #[cfg(feature = "rustls")]
 use rustls_pemfile::{certs, private_key};
 #[cfg(feature = "rustls")]
+use std::io::Error;
 use std::{
-    fs::File,
-    io::{BufReader, Error},
+    any::Any,
+    fs,
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
 };
+#[cfg(feature = "acme")]
+use std::{collections::HashMap, sync::LazyLock};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
 
 mutually_exclusive_features::exactly_one_of!("openssl", "rustls");
 
+/// Seconds between two checks of the certificate files for changes
+const WATCH_SECONDS: u64 = 60;
+
+/// IANA names of the TLS 1.3 cipher suites, which both backends configure apart from the older ones
+const TLS13_CIPHERS: [&str; 5] = [
+    "TLS_AES_128_GCM_SHA256",
+    "TLS_AES_256_GCM_SHA384",
+    "TLS_CHACHA20_POLY1305_SHA256",
+    "TLS_AES_128_CCM_SHA256",
+    "TLS_AES_128_CCM_8_SHA256",
+];
+
+/// ALPN protocols offered when HTTP/2 is disabled, in wire format
 #[cfg(feature = "openssl")]
-pub fn get_openssl_config(tls: &Tls) -> Result<SslAcceptorBuilder, String> {
-    let mut builder =
-        SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(|e| format!("Failed to start openssl acceptor: {e}"))?;
+const HTTP1_ALPN_WIRE: &[u8] = b"\x08http/1.1";
+
+/// Certificate given to new handshakes, replaced every time the files are reloaded
+static CERTIFICATE: RwLock<Option<Arc<Certificate>>> = RwLock::new(None);
+
+#[cfg(feature = "openssl")]
+struct Certificate {
+    cert: X509,
+    chain: Vec<X509>,
+    key: PKey<Private>,
+    /// OCSP response stapled along with the certificate
+    ocsp: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "rustls")]
+type Certificate = CertifiedKey;
+
+/// Protocol negotiated by the ACME server to check TLS-ALPN-01 challenges
+#[cfg(all(feature = "acme", feature = "rustls"))]
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+/// Certificates answering the TLS-ALPN-01 challenges in progress, by domain
+#[cfg(feature = "acme")]
+static CHALLENGES: LazyLock<RwLock<HashMap<String, Arc<Challenge>>>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// With openssl, a context whose ALPN callback sets the challenge certificate when `acme-tls/1` is negotiated
+#[cfg(all(feature = "acme", feature = "openssl"))]
+type Challenge = SslContext;
+
+#[cfg(all(feature = "acme", feature = "rustls"))]
+type Challenge = CertifiedKey;
+
+fn current() -> Option<Arc<Certificate>> {
+    CERTIFICATE.read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+fn store(certificate: Certificate) {
+    *CERTIFICATE.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(certificate));
+}
+
+fn load(tls: &Tls) -> Result<Certificate, String> {
+    let cert_pem = fs::read(&tls.cert_path).map_err(|e| format!("Failed to open certificate file at {}: {e}", tls.cert_path))?;
+    let key_pem = fs::read(&tls.privkey_path).map_err(|e| format!("Failed to open private key file at {}: {e}", tls.privkey_path))?;
+    let mut certificate = parse(&cert_pem, &key_pem)?;
+    if let Some(path) = ocsp_path() {
+        certificate.ocsp = Some(fs::read(&path).map_err(|e| format!("Failed to open OCSP response file at {path}: {e}"))?);
+    }
+    Ok(certificate)
+}
+
+fn ocsp_path() -> Option<String> {
+    config!(tls_policy).as_ref().and_then(|p| p.ocsp_path.clone())
+}
+
+#[cfg(feature = "openssl")]
+fn parse(cert_pem: &[u8], key_pem: &[u8]) -> Result<Certificate, String> {
+    let mut chain = X509::stack_from_pem(cert_pem).map_err(|e| format!("Failed to read certificate file: {e}"))?;
+    if chain.is_empty() {
+        return Err("No certificate found".into());
+    }
+    let cert = chain.remove(0);
+    let key = PKey::private_key_from_pem(key_pem).map_err(|e| format!("Failed to read private key: {e}"))?;
+    let public_key = cert
+        .public_key()
+        .map_err(|e| format!("Failed to read public key of certificate: {e}"))?;
+    if !public_key.public_eq(&key) {
+        return Err("Private key does not match the certificate".into());
+    }
+    Ok(Certificate {
+        cert,
+        chain,
+        key,
+        ocsp: None,
+    })
+}
+
+/// Gives the current certificate to a handshake, after switching it to `context` if some.
+/// OpenSSL calls it on every handshake, whether the client sent a server name or not.
+#[cfg(feature = "openssl")]
+fn set_certificate(ssl: &mut SslRef, context: Option<&SslContext>) -> Result<(), SniError> {
+    #[cfg(feature = "acme")]
+    if let Some(challenge) = ssl.servername(NameType::HOST_NAME).and_then(challenge) {
+        return ssl.set_ssl_context(&challenge).map_err(|e| {
+            log::error!("Failed to switch to the ACME challenge context: {e}");
+            SniError::ALERT_FATAL
+        });
+    }
+    if let Some(context) = context {
+        ssl.set_ssl_context(context).map_err(|e| {
+            log::error!("Failed to switch to the HTTP/1.1 context: {e}");
+            SniError::ALERT_FATAL
+        })?;
+    }
+    let certificate = current().ok_or(SniError::ALERT_FATAL)?;
+    set(ssl, &certificate).map_err(|e| {
+        log::error!("Failed to set TLS certificate: {e}");
+        SniError::ALERT_FATAL
+    })
+}
+
+#[cfg(feature = "openssl")]
+fn set(ssl: &mut SslRef, certificate: &Certificate) -> Result<(), ErrorStack> {
+    ssl.set_certificate(&certificate.cert)?;
+    ssl.set_private_key(&certificate.key)?;
+    for cert in &certificate.chain {
+        ssl.add_chain_cert(cert.clone())?;
+    }
+    Ok(())
+}
+
+/// Requests a client certificate on every handshake, as configured by `client_auth`
+#[cfg(feature = "openssl")]
+fn verify_clients(builder: &mut SslAcceptorBuilder, client_auth: &ClientAuth) -> Result<(), String> {
+    let path = &client_auth.ca_path;
     builder
-        .set_private_key_file(&tls.privkey_path, SslFiletype::PEM)
-        .map_err(|e| format!("Failed to get private key file: {e}"))?;
+        .set_ca_file(path)
+        .map_err(|e| format!("Failed to read client CA certificates at {path}: {e}"))?;
+    let names = X509Name::load_client_ca_file(path).map_err(|e| format!("Failed to read client CA certificates at {path}: {e}"))?;
+    builder.set_client_ca_list(names);
+    // Sessions are only resumed with the context they were created with, which must be named when clients are verified
     builder
-        .set_certificate_chain_file(&tls.cert_path)
-        .map_err(|e| format!("Failed to get certificate file: {e}"))?;
+        .set_session_id_context(b"tiny-cloud")
+        .map_err(|e| format!("Failed to set session id context: {e}"))?;
+    if client_auth.required {
+        builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    } else {
+        builder.set_verify(SslVerifyMode::PEER);
+    }
+    Ok(())
+}
+
+/// Whether HTTP/2 is offered to clients, only configurable with openssl
+#[cfg(feature = "openssl")]
+fn http2() -> bool {
+    config!(tls_policy).as_ref().and_then(|p| p.http2).unwrap_or(true)
+}
+
+/// Applies the protocol versions and cipher suites of `tls_policy`
+#[cfg(feature = "openssl")]
+fn apply_policy(builder: &mut SslAcceptorBuilder, policy: &TlsPolicy) -> Result<(), String> {
+    if let Some(version) = policy.min_version {
+        let version = match version {
+            TlsVersion::Tls12 => SslVersion::TLS1_2,
+            TlsVersion::Tls13 => SslVersion::TLS1_3,
+        };
+        builder
+            .set_min_proto_version(Some(version))
+            .map_err(|e| format!("Failed to set minimum TLS version: {e}"))?;
+    }
+    let (tls13, older): (Vec<&str>, Vec<&str>) = policy
+        .ciphers
+        .iter()
+        .flatten()
+        .map(String::as_str)
+        .partition(|name| TLS13_CIPHERS.contains(name));
+    if !tls13.is_empty() {
+        builder
+            .set_ciphersuites(&tls13.join(":"))
+            .map_err(|e| format!("Failed to set TLS 1.3 cipher suites: {e}"))?;
+    }
+    if !older.is_empty() {
+        // OpenSSL has its own names for the cipher suites older than TLS 1.3
+        let names = older
+            .iter()
+            .map(|name| match cipher_name(name) {
+                "(NONE)" => Err(format!("Cipher suite `{name}` is not supported by openssl")),
+                name => Ok(name),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        builder
+            .set_cipher_list(&names.join(":"))
+            .map_err(|e| format!("Failed to set TLS 1.2 cipher suites: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Builds an acceptor following `tls_policy` and requesting client certificates as configured by `client_auth`.
+/// Without a policy, it follows the older Mozilla intermediate profile, without TLS 1.3.
+#[cfg(feature = "openssl")]
+fn acceptor() -> Result<SslAcceptorBuilder, String> {
+    let policy = config!(tls_policy).clone();
+    let builder = match policy.as_ref().map(|p| p.profile) {
+        None => SslAcceptor::mozilla_intermediate(SslMethod::tls()),
+        Some(TlsProfile::Intermediate) => SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()),
+        Some(TlsProfile::Modern) => SslAcceptor::mozilla_modern_v5(SslMethod::tls()),
+    };
+    let mut builder = builder.map_err(|e| format!("Failed to start openssl acceptor: {e}"))?;
+    if let Some(policy) = &policy {
+        apply_policy(&mut builder, policy)?;
+    }
+    if let Some(client_auth) = config!(client_auth) {
+        verify_clients(&mut builder, client_auth)?;
+    }
     Ok(builder)
 }
 
-#[cfg(feature = "rustls")]
-pub fn get_rustls_config(tls: &Tls) -> Result<ServerConfig, String> {
-    // init server config builder
-    let config = ServerConfig::builder().with_no_client_auth();
+/// Staples the OCSP response of the current certificate for the clients asking for it
+#[cfg(feature = "openssl")]
+fn staple_ocsp(ssl: &mut SslRef) -> Result<bool, ErrorStack> {
+    match current().and_then(|certificate| certificate.ocsp.clone()) {
+        Some(response) => ssl.set_ocsp_status(&response).map(|()| true),
+        None => Ok(false),
+    }
+}
 
-    // load TLS key/cert files
-    let cert_file = &mut BufReader::new(
-        File::open(&tls.cert_path).map_err(|e| format!("Failed to open certificate file at {}: {e}", tls.cert_path))?,
-    );
-    let key_file = &mut BufReader::new(
-        File::open(&tls.privkey_path).map_err(|e| format!("Failed to open private key file at {}: {e}", tls.privkey_path))?,
-    );
+/// Builds the context switched to for a domain with a challenge in progress.
+/// The ACME server only offers `acme-tls/1` and gets the challenge certificate, other clients negotiate HTTP like
+/// the usual context does and get the current certificate.
+#[cfg(all(feature = "acme", feature = "openssl"))]
+fn challenge_context(challenge: Certificate) -> Result<Challenge, String> {
+    const ACME_TLS_ALPN_WIRE: &[u8] = b"\x0aacme-tls/1";
+    const HTTP2_ALPN_WIRE: &[u8] = b"\x02h2\x08http/1.1";
 
+    // Handshakes switched to this context still follow the policy and verify client certificates with it
+    let mut builder = acceptor()?;
+    if let Some(certificate) = current() {
+        builder
+            .set_certificate(&certificate.cert)
+            .and_then(|()| builder.set_private_key(&certificate.key))
+            .map_err(|e| format!("Failed to set TLS certificate: {e}"))?;
+        for cert in &certificate.chain {
+            builder
+                .add_extra_chain_cert(cert.clone())
+                .map_err(|e| format!("Failed to set TLS certificate: {e}"))?;
+        }
+    }
+    let http = if http2() { HTTP2_ALPN_WIRE } else { HTTP1_ALPN_WIRE };
+    builder.set_alpn_select_callback(move |ssl, client| {
+        if let Some(protocol) = select_next_proto(ACME_TLS_ALPN_WIRE, client) {
+            set(ssl, &challenge).map_err(|e| {
+                log::error!("Failed to set ACME challenge certificate: {e}");
+                AlpnError::ALERT_FATAL
+            })?;
+            return Ok(protocol);
+        }
+        select_next_proto(http, client).ok_or(AlpnError::NOACK)
+    });
+    Ok(builder.build().into_context())
+}
+
+/// The certificate is not set on the context but on each handshake, so that reloading it applies to new connections.
+#[cfg(feature = "openssl")]
+pub fn get_openssl_config(tls: &Tls) -> Result<SslAcceptorBuilder, String> {
+    store(load(tls)?);
+    let mut builder = acceptor()?;
+    if ocsp_path().is_some() {
+        builder
+            .set_status_callback(staple_ocsp)
+            .map_err(|e| format!("Failed to enable OCSP stapling: {e}"))?;
+    }
+    // actix-web always offers HTTP/2, so without it handshakes are switched to a context only offering HTTP/1.1
+    let http1 = if http2() {
+        None
+    } else {
+        let mut http1 = acceptor()?;
+        if ocsp_path().is_some() {
+            http1
+                .set_status_callback(staple_ocsp)
+                .map_err(|e| format!("Failed to enable OCSP stapling: {e}"))?;
+        }
+        http1.set_alpn_select_callback(|_, client| select_next_proto(HTTP1_ALPN_WIRE, client).ok_or(AlpnError::NOACK));
+        Some(http1.build().into_context())
+    };
+    builder.set_servername_callback(move |ssl, _| set_certificate(ssl, http1.as_ref()));
+    Ok(builder)
+}
+
+#[cfg(feature = "rustls")]
+fn parse(cert_pem: &[u8], key_pem: &[u8]) -> Result<Certificate, String> {
     // convert files to key/cert objects
-    let cert_chain = certs(cert_file)
+    let cert_chain = certs(&mut &cert_pem[..])
         .collect::<Result<Vec<CertificateDer<'_>>, Error>>()
         .map_err(|e| format!("Failed to read certificate file: {e}"))?;
-    let key_der = private_key(key_file)
+    let key_der = private_key(&mut &key_pem[..])
         .map_err(|e| format!("Failed to read private key: {e}"))?
         .ok_or("No private key found".to_string())?;
 
-    Ok(config
-        .with_single_cert(cert_chain, key_der)
-        .map_err(|e| format!("Failed to parse certificate and key: {e}"))?)
+    let provider = CryptoProvider::get_default().ok_or("No crypto provider installed for rustls")?;
+    CertifiedKey::from_der(cert_chain, key_der, provider).map_err(|e| format!("Failed to parse certificate and key: {e}"))
+}
+
+/// Unlike `parse`, does not check that the key matches the certificate: webpki refuses to read a challenge certificate
+/// because of its critical `acmeIdentifier` extension.
+#[cfg(all(feature = "acme", feature = "rustls"))]
+fn parse_challenge(cert_pem: &[u8], key_pem: &[u8]) -> Result<Challenge, String> {
+    let cert_chain = certs(&mut &cert_pem[..])
+        .collect::<Result<Vec<CertificateDer<'_>>, Error>>()
+        .map_err(|e| format!("Failed to read challenge certificate: {e}"))?;
+    let key_der = private_key(&mut &key_pem[..])
+        .map_err(|e| format!("Failed to read challenge private key: {e}"))?
+        .ok_or("No private key found".to_string())?;
+
+    let provider = CryptoProvider::get_default().ok_or("No crypto provider installed for rustls")?;
+    let key = provider
+        .key_provider
+        .load_private_key(key_der)
+        .map_err(|e| format!("Failed to load challenge private key: {e}"))?;
+    Ok(CertifiedKey::new(cert_chain, key))
+}
+
+/// Gives the current certificate to every handshake
+#[cfg(feature = "rustls")]
+#[derive(Debug)]
+struct CurrentCertificate;
+
+#[cfg(feature = "rustls")]
+impl ResolvesServerCert for CurrentCertificate {
+    #[cfg_attr(not(feature = "acme"), allow(unused_variables))]
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        #[cfg(feature = "acme")]
+        if client_hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|p| p == ACME_TLS_ALPN))
+        {
+            return client_hello.server_name().and_then(challenge);
+        }
+        current()
+    }
+}
+
+/// Verifies client certificates against the CA certificates of `client_auth`
+#[cfg(feature = "rustls")]
+fn verify_clients(client_auth: &ClientAuth) -> Result<Arc<dyn ClientCertVerifier>, String> {
+    let path = &client_auth.ca_path;
+    let pem = fs::read(path).map_err(|e| format!("Failed to open client CA certificates at {path}: {e}"))?;
+    let mut roots = RootCertStore::empty();
+    for cert in certs(&mut &pem[..]) {
+        let cert = cert.map_err(|e| format!("Failed to read client CA certificates at {path}: {e}"))?;
+        roots
+            .add(cert)
+            .map_err(|e| format!("Invalid client CA certificate in {path}: {e}"))?;
+    }
+    let builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    let builder = if client_auth.required {
+        builder
+    } else {
+        builder.allow_unauthenticated()
+    };
+    builder
+        .build()
+        .map_err(|e| format!("Failed to verify client certificates with {path}: {e}"))
+}
+
+/// IANA name of a cipher suite, which rustls prefixes with `TLS13_` instead of `TLS_` for TLS 1.3
+#[cfg(feature = "rustls")]
+fn suite_name(suite: &SupportedCipherSuite) -> String {
+    let name = suite.suite().as_str().unwrap_or_default();
+    match name.strip_prefix("TLS13_") {
+        Some(rest) => format!("TLS_{rest}"),
+        None => name.to_string(),
+    }
+}
+
+/// Starts a config with the protocol versions and cipher suites of `tls_policy`
+#[cfg(feature = "rustls")]
+fn apply_policy(policy: &TlsPolicy) -> Result<ConfigBuilder<ServerConfig, WantsVerifier>, String> {
+    let mut provider = rustls::crypto::aws_lc_rs::default_provider();
+    if let Some(ciphers) = &policy.ciphers {
+        if let Some(name) = ciphers
+            .iter()
+            .find(|name| !provider.cipher_suites.iter().any(|suite| suite_name(suite) == **name))
+        {
+            return Err(format!("Cipher suite `{name}` is not supported by rustls"));
+        }
+        let listed = |tls13: bool| ciphers.iter().any(|name| TLS13_CIPHERS.contains(&name.as_str()) == tls13);
+        let (tls13_listed, older_listed) = (listed(true), listed(false));
+        provider.cipher_suites.retain(|suite| {
+            let listed = match suite {
+                SupportedCipherSuite::Tls13(_) => tls13_listed,
+                _ => older_listed,
+            };
+            !listed || ciphers.contains(&suite_name(suite))
+        });
+    }
+    let versions: &[&SupportedProtocolVersion] = match (policy.profile, policy.min_version) {
+        (TlsProfile::Modern, _) | (_, Some(TlsVersion::Tls13)) => &[&TLS13],
+        _ => &[&TLS12, &TLS13],
+    };
+    ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(versions)
+        .map_err(|e| format!("Failed to apply TLS policy: {e}"))
+}
+
+#[cfg(feature = "rustls")]
+pub fn get_rustls_config(tls: &Tls) -> Result<ServerConfig, String> {
+    // Other dependencies may enable a second crypto provider, in which case none is picked by default
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+    let builder = match config!(tls_policy) {
+        Some(policy) => apply_policy(policy)?,
+        None => ServerConfig::builder(),
+    };
+    let config = match config!(client_auth) {
+        Some(client_auth) => builder.with_client_cert_verifier(verify_clients(client_auth)?),
+        None => builder.with_no_client_auth(),
+    };
+    store(load(tls)?);
+    #[cfg_attr(not(feature = "acme"), allow(unused_mut))]
+    let mut config = config.with_cert_resolver(Arc::new(CurrentCertificate));
+    // actix-web prepends `h2` and `http/1.1` to these, which is why `tls_policy.http2` is refused with rustls
+    #[cfg(feature = "acme")]
+    config.alpn_protocols.push(ACME_TLS_ALPN.to_vec());
+    Ok(config)
+}
+
+/// Client certificate of a connection in DER format, verified during its handshake
+pub struct PeerCertificate(Vec<u8>);
+
+impl PeerCertificate {
+    /// Reads the given name of the certificate
+    pub fn name(&self, name: CertificateName) -> Option<String> {
+        let (_, cert) = X509Certificate::from_der(&self.0).ok()?;
+        match name {
+            CertificateName::CommonName => cert.subject().iter_common_name().next()?.as_str().ok().map(str::to_string),
+            CertificateName::SubjectAltName => {
+                cert.subject_alternative_name()
+                    .ok()??
+                    .value
+                    .general_names
+                    .iter()
+                    .find_map(|name| match name {
+                        GeneralName::DNSName(name) => Some(name.to_string()),
+                        _ => None,
+                    })
+            }
+        }
+    }
+}
+
+/// Keeps the client certificate of a new connection for its requests, given to `HttpServer::on_connect`
+pub fn keep_peer_certificate(connection: &dyn Any, data: &mut Extensions) {
+    let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+    #[cfg(feature = "openssl")]
+    let cert = stream.ssl().peer_certificate().and_then(|cert| cert.to_der().ok());
+    #[cfg(feature = "rustls")]
+    let cert = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .map(|cert| cert.to_vec());
+    if let Some(cert) = cert {
+        data.insert(PeerCertificate(cert));
+    }
+}
+
+/// Reads the certificate files again for the next handshakes. The current certificate is kept if they are invalid.
+pub fn reload(tls: &Tls) -> Result<(), String> {
+    store(load(tls)?);
+    log::info!("TLS certificate reloaded");
+    Ok(())
+}
+
+fn modified(tls: &Tls) -> [Option<SystemTime>; 3] {
+    [Some(tls.cert_path.clone()), Some(tls.privkey_path.clone()), ocsp_path()]
+        .map(|path| path.and_then(|path| fs::metadata(path).and_then(|m| m.modified()).ok()))
+}
+
+/// Reloads the certificate whenever one of its files is modified, e.g. by a renewal.
+/// A certificate and a key that don't match yet, because only one of them was replaced, are retried on the next change.
+pub async fn watch() {
+    let mut interval = tokio::time::interval(Duration::from_secs(WATCH_SECONDS));
+    let mut last = None;
+    loop {
+        interval.tick().await;
+        let Some(tls) = config!(tls) else {
+            continue;
+        };
+        let modified = modified(tls);
+        if last.replace(modified).is_none_or(|last| last == modified) {
+            continue;
+        }
+        if let Err(e) = reload(tls) {
+            log::error!("Failed to reload TLS certificate, keeping the current one: {e}");
+        }
+    }
+}
+
+#[cfg(feature = "acme")]
+fn challenge(domain: &str) -> Option<Arc<Challenge>> {
+    CHALLENGES.read().unwrap_or_else(PoisonError::into_inner).get(domain).cloned()
+}
+
+/// Answers the TLS-ALPN-01 challenge of `domain` with the given certificate, until it is removed
+#[cfg(feature = "acme")]
+pub fn set_challenge(domain: &str, cert_pem: &[u8], key_pem: &[u8]) -> Result<(), String> {
+    #[cfg(feature = "openssl")]
+    let challenge = challenge_context(parse(cert_pem, key_pem)?)?;
+    #[cfg(feature = "rustls")]
+    let challenge = parse_challenge(cert_pem, key_pem)?;
+    CHALLENGES
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(domain.to_string(), Arc::new(challenge));
+    Ok(())
+}
+
+#[cfg(feature = "acme")]
+pub fn remove_challenge(domain: &str) {
+    CHALLENGES.write().unwrap_or_else(PoisonError::into_inner).remove(domain);
 }
@@ -0,0 +1,402 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+pub mod error;
+use crate::{config, quota};
+use actix_multipart::form::tempfile::TempFile;
+use actix_web::web::{Bytes, Payload};
+use common_library::{Json, serde_json};
+use error::UploadError;
+use futures_util::StreamExt;
+use rand::{Rng, distr::Alphanumeric};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime};
+use tempfile::{NamedTempFile, TempPath};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+const ID_SIZE: usize = 32;
+
+/// Uploads that are currently receiving data
+static ACTIVE: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+/// Unfinished uploads, by id
+static PENDING: LazyLock<Mutex<HashMap<String, Pending>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Owner and size of an unfinished upload, counted against the limits of its owner
+struct Pending {
+    owner: Option<String>,
+    size: u64,
+}
+
+/// Metadata of a partial upload, saved next to its data
+#[derive(Serialize, Deserialize)]
+struct UploadMeta {
+    owner: Option<String>,
+    plugin: String,
+    size: u64,
+    file_name: String,
+    info: Json,
+}
+
+/// Marks an upload as active until dropped
+struct ActiveGuard(String);
+
+impl ActiveGuard {
+    fn acquire(id: &str) -> Result<Self, UploadError> {
+        let mut active = ACTIVE
+            .lock()
+            .map_err(|_| UploadError::InternalError("Active uploads lock was poisoned".into()))?;
+        if active.insert(id.into()) {
+            Ok(Self(id.into()))
+        } else {
+            Err(UploadError::Busy)
+        }
+    }
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        if let Ok(mut active) = ACTIVE.lock() {
+            active.remove(&self.0);
+        }
+    }
+}
+
+fn uploads_dir() -> PathBuf {
+    let mut path = PathBuf::from(config!(data_directory));
+    path.push("uploads");
+    path
+}
+
+fn part_path(id: &str) -> PathBuf {
+    uploads_dir().join(format!("{id}.part"))
+}
+
+fn meta_path(id: &str) -> PathBuf {
+    uploads_dir().join(format!("{id}.json"))
+}
+
+fn check_id(id: &str) -> Result<(), UploadError> {
+    if id.len() == ID_SIZE && id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Ok(())
+    } else {
+        Err(UploadError::NotFound)
+    }
+}
+
+async fn load_meta(id: &str, owner: &Option<String>, plugin: &str) -> Result<UploadMeta, UploadError> {
+    check_id(id)?;
+    let meta = match fs::read(meta_path(id)).await {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(UploadError::NotFound),
+        Err(e) => return Err(UploadError::InternalError(format!("Failed to read upload metadata: {e}"))),
+    };
+    let meta: UploadMeta =
+        serde_json::from_slice(&meta).map_err(|e| UploadError::InternalError(format!("Invalid upload metadata: {e}")))?;
+    if &meta.owner != owner || meta.plugin != plugin {
+        return Err(UploadError::NotFound);
+    }
+    Ok(meta)
+}
+
+async fn current_offset(id: &str) -> Result<u64, UploadError> {
+    fs::metadata(part_path(id))
+        .await
+        .map(|m| m.len())
+        .map_err(|e| UploadError::InternalError(format!("Failed to read upload data: {e}")))
+}
+
+/// Counts an unfinished upload, unless it would go over `limits.user_uploads` for a user, or over
+/// `limits.anonymous_uploads` or `limits.anonymous_uploads_size` without an owner
+fn reserve_pending(id: &str, owner: &Option<String>, size: u64) -> Result<(), UploadError> {
+    let mut pending = PENDING
+        .lock()
+        .map_err(|_| UploadError::InternalError("Pending uploads lock was poisoned".into()))?;
+    let (count, total) = pending
+        .values()
+        .filter(|p| &p.owner == owner)
+        .fold((0, 0), |(count, total), p| (count + 1, total + p.size));
+    match owner {
+        Some(_) if config!(limits.user_uploads).is_some_and(|max| count >= max) => return Err(UploadError::UserLimit),
+        Some(_) => {}
+        None => {
+            let max_count = config!(limits.anonymous_uploads).unwrap_or(0);
+            if count >= max_count || config!(limits.anonymous_uploads_size).is_some_and(|max| total + size > max) {
+                return Err(UploadError::AnonymousLimit);
+            }
+        }
+    }
+    pending.insert(
+        id.into(),
+        Pending {
+            owner: owner.clone(),
+            size,
+        },
+    );
+    Ok(())
+}
+
+fn release_pending(id: &str) {
+    if let Ok(mut pending) = PENDING.lock() {
+        pending.remove(id);
+    }
+}
+
+async fn remove(id: &str) {
+    release_pending(id);
+    quota::release(id);
+    for path in [part_path(id), meta_path(id)] {
+        match fs::remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                log::error!("Failed to remove partial upload `{}`: {e}", path.display())
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Creates the directory where partial uploads are stored and counts the ones left by the previous run
+pub async fn init() -> Result<(), String> {
+    fs::create_dir_all(uploads_dir())
+        .await
+        .map_err(|e| format!("Failed to create uploads directory: {e}"))?;
+    let mut entries = fs::read_dir(uploads_dir())
+        .await
+        .map_err(|e| format!("Failed to read uploads directory: {e}"))?;
+    let mut pending = HashMap::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name();
+        let Some(id) = name.to_str().and_then(|n| n.strip_suffix(".json")) else {
+            continue;
+        };
+        let meta = fs::read(entry.path()).await.ok();
+        if let Some(meta) = meta.and_then(|m| serde_json::from_slice::<UploadMeta>(&m).ok()) {
+            if let Some(owner) = &meta.owner {
+                quota::restore(id, owner, meta.size);
+            }
+            pending.insert(
+                id.to_string(),
+                Pending {
+                    owner: meta.owner,
+                    size: meta.size,
+                },
+            );
+        }
+    }
+    *PENDING.lock().map_err(|_| "Pending uploads lock was poisoned".to_string())? = pending;
+    Ok(())
+}
+
+/// Starts a new upload and returns its id. The caller reserves its size in the quota of its owner.
+pub async fn create(owner: Option<String>, plugin: String, size: u64, file_name: String, info: Json) -> Result<String, UploadError> {
+    if size > *config!(limits.file_upload_size) as u64 {
+        return Err(UploadError::TooLarge);
+    }
+    if file_name.is_empty() {
+        return Err(UploadError::BadRequest("File name cannot be empty".into()));
+    }
+    let id: String = rand::rng().sample_iter(&Alphanumeric).take(ID_SIZE).map(char::from).collect();
+    reserve_pending(&id, &owner, size)?;
+    if let Err(e) = write_new(&id, owner, plugin, size, file_name, info).await {
+        remove(&id).await;
+        return Err(e);
+    }
+    Ok(id)
+}
+
+async fn write_new(
+    id: &str,
+    owner: Option<String>,
+    plugin: String,
+    size: u64,
+    file_name: String,
+    info: Json,
+) -> Result<(), UploadError> {
+    let meta = UploadMeta {
+        owner,
+        plugin,
+        size,
+        file_name,
+        info,
+    };
+    let meta =
+        serde_json::to_vec(&meta).map_err(|e| UploadError::InternalError(format!("Failed to serialize upload metadata: {e}")))?;
+    fs::File::create(part_path(id))
+        .await
+        .map_err(|e| UploadError::InternalError(format!("Failed to create upload data: {e}")))?;
+    fs::write(meta_path(id), meta)
+        .await
+        .map_err(|e| UploadError::InternalError(format!("Failed to write upload metadata: {e}")))
+}
+
+/// Returns the current offset and the total size of an upload
+pub async fn offset(id: &str, owner: &Option<String>, plugin: &str) -> Result<(u64, u64), UploadError> {
+    let meta = load_meta(id, owner, plugin).await?;
+    Ok((current_offset(id).await?, meta.size))
+}
+
+/// Appends a chunk to an upload, starting from `offset`, and returns the new offset.
+/// The chunk is streamed to disk, so whatever was received before an interruption is kept.
+pub async fn append(id: &str, owner: &Option<String>, plugin: &str, offset: u64, mut payload: Payload) -> Result<u64, UploadError> {
+    let _guard = ActiveGuard::acquire(id)?;
+    let meta = load_meta(id, owner, plugin).await?;
+    let mut current = current_offset(id).await?;
+    if current != offset {
+        return Err(UploadError::OffsetMismatch(current));
+    }
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(part_path(id))
+        .await
+        .map_err(|e| UploadError::InternalError(format!("Failed to open upload data: {e}")))?;
+    while let Some(chunk) = payload.next().await {
+        let chunk: Bytes = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                log::debug!("Upload `{id}` was interrupted at {current}: {e}");
+                break;
+            }
+        };
+        if current + chunk.len() as u64 > meta.size {
+            file.flush().await.ok();
+            return Err(UploadError::TooLarge);
+        }
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| UploadError::InternalError(format!("Failed to write upload data: {e}")))?;
+        current += chunk.len() as u64;
+    }
+    file.flush()
+        .await
+        .map_err(|e| UploadError::InternalError(format!("Failed to write upload data: {e}")))?;
+    Ok(current)
+}
+
+/// Completes an upload and returns it as a temporary file along with its info,
+/// which can then be handed to the plugin.
+pub async fn finish(id: &str, owner: &Option<String>, plugin: &str) -> Result<(TempFile, Json), UploadError> {
+    let _guard = ActiveGuard::acquire(id)?;
+    let meta = load_meta(id, owner, plugin).await?;
+    let size = current_offset(id).await?;
+    if size != meta.size {
+        return Err(UploadError::Incomplete);
+    }
+    let path = part_path(id);
+    let file = fs::File::open(&path)
+        .await
+        .map_err(|e| UploadError::InternalError(format!("Failed to open upload data: {e}")))?
+        .into_std()
+        .await;
+    // From now on the data is deleted along with the temporary file
+    let path =
+        TempPath::try_from_path(path).map_err(|e| UploadError::InternalError(format!("Failed to get upload data path: {e}")))?;
+    let file = NamedTempFile::from_parts(file, path);
+    fs::remove_file(meta_path(id))
+        .await
+        .map_err(|e| UploadError::InternalError(format!("Failed to remove upload metadata: {e}")))?;
+    // The quota reservation is left to the caller, which replaces it by the file once it is checked
+    release_pending(id);
+    Ok((
+        TempFile {
+            file,
+            content_type: None,
+            file_name: Some(meta.file_name),
+            size: size as usize,
+        },
+        meta.info,
+    ))
+}
+
+/// Cancels an upload and deletes its data
+pub async fn cancel(id: &str, owner: &Option<String>, plugin: &str) -> Result<(), UploadError> {
+    let _guard = ActiveGuard::acquire(id)?;
+    load_meta(id, owner, plugin).await?;
+    remove(id).await;
+    Ok(())
+}
+
+/// Periodically deletes partial uploads that did not receive any data for `limits.partial_upload_minutes`.
+/// The setting is read on every pass so that it follows config reloads.
+pub async fn collect_garbage() {
+    let mut interval = tokio::time::interval(Duration::from_secs(15 * 60));
+    loop {
+        interval.tick().await;
+        let Some(minutes) = *config!(limits.partial_upload_minutes) else {
+            continue;
+        };
+        let expire = Duration::from_secs(minutes * 60);
+        let mut entries = match fs::read_dir(uploads_dir()).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::error!("Failed to read uploads directory: {e}");
+                continue;
+            }
+        };
+        // Data and metadata are written one after the other, so either can be left alone by a crash
+        let mut ids = HashSet::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name();
+            if let Some(id) = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".part").or_else(|| n.strip_suffix(".json")))
+            {
+                ids.insert(id.to_string());
+            }
+        }
+        for id in ids {
+            // Held until the upload is removed, so that no chunk can arrive in the meantime
+            let Ok(_guard) = ActiveGuard::acquire(&id) else {
+                continue;
+            };
+            let mut modified = None;
+            for path in [part_path(&id), meta_path(&id)] {
+                if let Ok(time) = fs::metadata(path).await.and_then(|m| m.modified()) {
+                    modified = modified.max(Some(time));
+                }
+            }
+            let stale = modified
+                .and_then(|m| SystemTime::now().duration_since(m).ok())
+                .is_some_and(|age| age > expire);
+            if stale {
+                log::info!("Removing stale partial upload `{id}`");
+                remove(&id).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_uploads_are_capped_per_owner() {
+        config::use_default();
+        let max = config!(limits.user_uploads).unwrap();
+        let alice = Some("cap-alice".to_string());
+        for i in 0..max {
+            reserve_pending(&format!("cap-alice-{i}"), &alice, 1).unwrap();
+        }
+        assert!(matches!(reserve_pending("cap-alice-over", &alice, 1), Err(UploadError::UserLimit)));
+        reserve_pending("cap-bob", &Some("cap-bob".to_string()), 1).unwrap();
+
+        release_pending("cap-alice-0");
+        reserve_pending("cap-alice-again", &alice, 1).unwrap();
+    }
+
+    #[test]
+    fn anonymous_uploads_share_their_limits() {
+        config::use_default();
+        let max_size = config!(limits.anonymous_uploads_size).unwrap();
+        reserve_pending("anonymous-half", &None, max_size / 2).unwrap();
+        assert!(matches!(
+            reserve_pending("anonymous-over", &None, max_size / 2 + 1),
+            Err(UploadError::AnonymousLimit)
+        ));
+        release_pending("anonymous-half");
+        reserve_pending("anonymous-full", &None, max_size).unwrap();
+        release_pending("anonymous-full");
+    }
+}
@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_web::{HttpResponse, HttpResponseBuilder};
+use common_library::error::ErrToResponse;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum UploadError {
+    #[error("An internal server error occurred")]
+    InternalError(String),
+    #[error("Invalid upload request: {0}")]
+    BadRequest(String),
+    #[error("Upload was not found")]
+    NotFound,
+    #[error("File is larger than the maximum upload size")]
+    TooLarge,
+    #[error("Wrong upload offset, the upload is at {0}")]
+    OffsetMismatch(u64),
+    #[error("Upload is not complete yet")]
+    Incomplete,
+    #[error("Upload is already receiving data")]
+    Busy,
+    #[error("Too many uploads without an account are in progress, try again later")]
+    AnonymousLimit,
+    #[error("Too many of your uploads are in progress, finish or cancel some of them first")]
+    UserLimit,
+}
+
+impl ErrToResponse for UploadError {
+    fn error(&self) -> &'static str {
+        "UploadError"
+    }
+
+    fn err_type(&self) -> &'static str {
+        match self {
+            Self::InternalError(_) => stringify!(InternalError),
+            Self::BadRequest(_) => stringify!(BadRequest),
+            Self::NotFound => stringify!(NotFound),
+            Self::TooLarge => stringify!(TooLarge),
+            Self::OffsetMismatch(_) => stringify!(OffsetMismatch),
+            Self::Incomplete => stringify!(Incomplete),
+            Self::Busy => stringify!(Busy),
+            Self::AnonymousLimit => stringify!(AnonymousLimit),
+            Self::UserLimit => stringify!(UserLimit),
+        }
+    }
+
+    fn msg(&self) -> String {
+        self.to_string()
+    }
+
+    fn http_code(&self) -> HttpResponseBuilder {
+        match self {
+            Self::InternalError(_) => HttpResponse::InternalServerError(),
+            Self::BadRequest(_) => HttpResponse::BadRequest(),
+            Self::NotFound => HttpResponse::NotFound(),
+            Self::TooLarge => HttpResponse::PayloadTooLarge(),
+            Self::OffsetMismatch(_) => HttpResponse::Conflict(),
+            Self::Incomplete => HttpResponse::Conflict(),
+            Self::Busy => HttpResponse::Locked(),
+            Self::AnonymousLimit | Self::UserLimit => HttpResponse::TooManyRequests(),
+        }
+    }
+
+    fn handle(&self) {
+        if let Self::InternalError(err) = self {
+            log::error!("An internal server error occurred while handling an upload: {err}");
+        }
+    }
+}
@@ -23,17 +23,20 @@ pub mod error;
 mod home;
 pub mod images;
 mod login;
+mod plugin;
 mod register;
 mod settings;
+mod users;
 #[macro_use]
 mod macros;
-use crate::{auth::validate_user, config, utils};
+use crate::{auth::validate_user, config, plugins::Plugins, utils};
 use actix_identity::Identity;
 use actix_web::{
     HttpRequest, HttpResponse, Responder, get,
     web::{self, Redirect},
 };
 use async_sqlite::Pool;
+use common_library::plugin::User;
 
 #[get("")]
 pub async fn root(req: HttpRequest, pool: web::Data<Pool>, user: Option<Identity>) -> impl Responder {
@@ -94,3 +97,46 @@ pub async fn settings_page(req: HttpRequest, pool: web::Data<Pool>, user: Option
             .map_into_boxed_body()
     }
 }
+
+#[get("/users")]
+pub async fn users_page(req: HttpRequest, pool: web::Data<Pool>, user: Option<Identity>) -> impl Responder {
+    let pool = pool.into_inner();
+    if let Some(user) = user {
+        match validate_user(&pool, user).await {
+            Ok((_, true)) => HttpResponse::Ok().body(users::page()),
+            Ok((_, false)) => HttpResponse::NotFound().body(""),
+            Err(e) => error::to_response_page(e),
+        }
+    } else {
+        Redirect::to(utils::make_url("/ui/login"))
+            .see_other()
+            .respond_to(&req)
+            .map_into_boxed_body()
+    }
+}
+
+#[get("/p/{plugin}")]
+pub async fn plugin_page(
+    req: HttpRequest,
+    pool: web::Data<Pool>,
+    plugins: web::Data<Plugins>,
+    plugin: web::Path<String>,
+    user: Option<Identity>,
+) -> impl Responder {
+    let pool = pool.into_inner();
+    let plugin = plugin.into_inner();
+    if let Some(user) = user {
+        match validate_user(&pool, user).await {
+            Ok((name, is_admin)) => match plugins.webui(&plugin, &User { name, is_admin }).await {
+                Some(ui) => HttpResponse::Ok().body(plugin::page(&plugin, is_admin, ui)),
+                None => HttpResponse::NotFound().body(""),
+            },
+            Err(e) => error::to_response_page(e),
+        }
+    } else {
+        Redirect::to(utils::make_url("/ui/login"))
+            .see_other()
+            .respond_to(&req)
+            .map_into_boxed_body()
+    }
+}
@@ -2,7 +2,7 @@
 
 use std::sync::LazyLock;
 
-use crate::{config, utils, webfile};
+use crate::{auth::passkey_mode, config, config::PasskeyMode, utils, webfile};
 use maud::{DOCTYPE, PreEscaped, html};
 
 pub static PAGE: LazyLock<&'static str> = LazyLock::new(|| {
@@ -15,8 +15,17 @@ pub static PAGE: LazyLock<&'static str> = LazyLock::new(|| {
                 meta charset="UTF-8";
                 meta name="viewport" content="width=device-width, initial-scale=1.0";
                 meta name="tcloud-prefix" content=(config!(url_prefix));
+                @if let Some(mode) = passkey_mode() {
+                    meta name="tcloud-passkeys" content=(mode.as_str());
+                }
                 link rel="icon" type="image/x-icon" href=(utils::make_url("/static/favicon.ico"));
-                script type="text/javascript" { (webfile!("global.js")) (webfile!("login.js")) }
+                script type="text/javascript" {
+                    (webfile!("global.js"))
+                    @if passkey_mode().is_some() {
+                        (webfile!("webauthn.js"))
+                    }
+                    (webfile!("login.js"))
+                }
                 style { (webfile!("global.css")) (webfile!("login.css")) }
             }
             body {
@@ -31,9 +40,14 @@ pub static PAGE: LazyLock<&'static str> = LazyLock::new(|| {
                     br; input type="text" id="user" name="user" minlength=(config!(cred_size.min_username)) maxlength=(config!(cred_size.max_username)) required;
                     br; label for="password" { "Password:" }
                     br; input type="password" id="password" name="password" minlength=(config!(cred_size.min_passwd)) maxlength=(config!(cred_size.max_passwd)) required;
-                    br; label for="totp" { "TOTP Token:" }
-                    br; input type="text" id="totp" name="totp" minlength="6" maxlength="6" size="6" required;
+                    br; label for="totp" { "TOTP Token or Recovery Code:" }
+                    br; input type="text" id="totp" name="totp" minlength="6" maxlength="11" size="11" required;
                     br; input value="Login" type="submit" id="btn";
+                    @match passkey_mode() {
+                        Some(PasskeyMode::Totp) => { br; button type="button" id="passkey" { "Use a passkey instead of the TOTP token" } }
+                        Some(PasskeyMode::Password) => { br; button type="button" id="passkey" { "Use a passkey instead of the password" } }
+                        None => {}
+                    }
                 }
                 div id="msg" {}
                 @if config!(registration).is_some() {
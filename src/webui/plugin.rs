@@ -0,0 +1,38 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::{config, utils, webfile, webui::home::header};
+use common_library::plugin::WebUI;
+use maud::{DOCTYPE, PreEscaped, html};
+
+/// Wraps a plugin's web UI inside the standard page
+pub fn page(name: &str, is_admin: bool, ui: WebUI) -> String {
+    html! {
+        (DOCTYPE)
+        html lang="en-US" {
+            head {
+                title { (name) }
+                meta name="application-name" content=(config!(server_name));
+                meta charset="UTF-8";
+                meta name="viewport" content="width=device-width, initial-scale=1.0";
+                meta name="tcloud-prefix" content=(config!(url_prefix));
+                meta name="tcloud-plugin" content=(name);
+                link rel="icon" type="image/x-icon" href=(utils::make_url("/static/favicon.ico"));
+                script type="text/javascript" {
+                    (webfile!("global.js"))
+                    (webfile!("navbar.js"))
+                    (PreEscaped(ui.js))
+                }
+                style {
+                    (webfile!("global.css"))
+                    (webfile!("navbar.css"))
+                    (PreEscaped(ui.css))
+                }
+            }
+            body {
+                (header(is_admin))
+                (PreEscaped(ui.html))
+            }
+        }
+    }
+    .into()
+}
@@ -43,6 +43,8 @@ pub static PAGE: LazyLock<&'static str> = LazyLock::new(|| {
                     br; img id="totp-qr" hidden;
                     div id="totp-url" {}
                     p { "Save this in your TOTP app." }
+                    p { "Keep these recovery codes somewhere safe, each of them can be used once in place of a TOTP token:" }
+                    pre id="recovery-codes" {}
                     button type="button" id="continue" { "Continue" }
                 }
 
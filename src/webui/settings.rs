@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use crate::{config, utils, webfile, webui::home::header};
+use crate::{auth::passkey_mode, config, plugins, utils, webfile, webui::home::header};
 use maud::{DOCTYPE, PreEscaped, html};
 
 pub fn page(is_admin: bool) -> String {
@@ -18,12 +18,16 @@ pub fn page(is_admin: bool) -> String {
                     (webfile!("global.js"))
                     (webfile!("navbar.js"))
                     (webfile!("settings.js"))
+                    @if passkey_mode().is_some() {
+                        (webfile!("webauthn.js"))
+                    }
                 }
                 style { (webfile!("global.css")) (webfile!("navbar.css")) (webfile!("settings.css")) }
             }
             body {
                 (header(is_admin))
                 div id="settings" {
+                    p id="usage" { "" }
                     button type="button" class="setting" id="logout" { "Log Out" }
                     button type="button" class="setting" { "Recreate TOTP" }
                     form id="totp" name="totp" {
@@ -37,8 +41,22 @@ pub fn page(is_admin: bool) -> String {
                     div id="totp-res" hidden {
                         br; img id="totp-qr" src="";
                         br; p id="totp-url" { "" }
+                        p { "Keep these recovery codes somewhere safe, each of them can be used once in place of a TOTP token:" }
+                        pre id="recovery-codes" {}
                         br; button id="totp-btn" { "I saved the TOTP secret" }
                     }
+                    @if passkey_mode().is_some() {
+                        button type="button" class="setting" id="passkeys" { "Passkeys" }
+                        form id="addpasskey" name="addpasskey" {
+                            h4 { "Here you can manage the passkeys you can log in with" }
+                            ul id="passkey-list" {}
+                            br; label for="pkname" { "Name of the new passkey:" }
+                            br; input type="text" id="pkname" name="name" maxlength="64" required;
+                            br; label for="pkpasswd" { "Insert password:" }
+                            br; input type="password" id="pkpasswd" name="password" required;
+                            br; input value="Add passkey" type="submit";
+                        }
+                    }
                     button type="button" class="setting" id="passwd" { "Change Password" }
                     form id="changepwd" name="changepwd" {
                         h4 { "If you need to change password but you don't remember it you can ask the admin to create a token for changing your password. Check 'This is a token' if you are using a token" }
@@ -52,7 +70,37 @@ pub fn page(is_admin: bool) -> String {
                         br; input type="password" id="newpasswd_rep" name="newpasswd_rep" required;
                         br; input value="Change password" type="submit";
                     }
+                    @if let Some(api_tokens) = config!(api_tokens) {
+                        button type="button" class="setting" id="apitokens" { "API Tokens" }
+                        form id="addapitoken" name="addapitoken" {
+                            h4 { "Here you can manage the tokens scripts use to access your account, sent as 'Authorization: Bearer'" }
+                            ul id="apitoken-list" {}
+                            br; label for="atname" { "Name of the new token:" }
+                            br; input type="text" id="atname" name="name" maxlength="64" required;
+                            br; label for="atdays" { "Days it stays valid:" }
+                            br; input type="number" id="atdays" name="days" min="1" max=(api_tokens.max_days) value="30" required;
+                            br; "What it can access:"
+                            @for plugin in plugins::list() {
+                                br; input type="checkbox" id={ "scope-" (plugin.name) } name="scope" value={ "plugin:" (plugin.name) };
+                                label for={ "scope-" (plugin.name) } { "Plugin " (plugin.name) }
+                            }
+                            br; input type="checkbox" id="scope-quota" name="scope" value="quota";
+                            label for="scope-quota" { "Storage usage" }
+                            @if is_admin {
+                                br; input type="checkbox" id="scope-admin" name="scope" value="admin";
+                                label for="scope-admin" { "Administration" }
+                            }
+                            br; label for="atpasswd" { "Insert password:" }
+                            br; input type="password" id="atpasswd" name="password" required;
+                            br; input value="Create token" type="submit";
+                        }
+                        div id="apitoken-res" hidden {
+                            p { "Copy this token now, it won't be shown again:" }
+                            pre id="new-apitoken" {}
+                        }
+                    }
                     button type="button" class="setting" id="session" { "Log out all Sessions" }
+                    ul id="session-list" {}
                     button type="button" class="setting" id="delete" { "Delete Account" }
                 }
             }
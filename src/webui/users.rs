@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::{config, utils, webfile, webui::home::header};
+use maud::{DOCTYPE, PreEscaped, html};
+
+pub fn page() -> String {
+    html! {
+        (DOCTYPE)
+        html lang="en-US" {
+            head {
+                title { "Users" }
+                meta name="application-name" content=(config!(server_name));
+                meta charset="UTF-8";
+                meta name="viewport" content="width=device-width, initial-scale=1.0";
+                meta name="tcloud-prefix" content=(config!(url_prefix));
+                link rel="icon" type="image/x-icon" href=(utils::make_url("/static/favicon.ico"));
+                script type="text/javascript" {
+                    (webfile!("global.js"))
+                    (webfile!("navbar.js"))
+                    (webfile!("users.js"))
+                }
+                style { (webfile!("global.css")) (webfile!("navbar.css")) (webfile!("users.css")) }
+            }
+            body {
+                (header(true))
+                div id="users" {
+                    table id="userlist" {
+                        thead {
+                            tr {
+                                th { "Username" }
+                                th { "Admin" }
+                                th { "Storage" }
+                                th { "Actions" }
+                            }
+                        }
+                        tbody id="userrows" {}
+                    }
+                    div id="sessions-res" hidden {
+                        p id="sessions-user" { "" }
+                        ul id="session-list" {}
+                        button id="sessions-btn" { "Done" }
+                    }
+                    div id="totp-res" hidden {
+                        p id="totp-user" { "" }
+                        br; img id="totp-qr" src="";
+                        br; p id="totp-url" { "" }
+                        p { "Recovery codes, each of them can be used once in place of a TOTP token:" }
+                        pre id="recovery-codes" {}
+                        br; p { "Give this TOTP secret and the recovery codes to the user. They won't be shown again." }
+                        button id="totp-btn" { "Done" }
+                    }
+                }
+            }
+        }
+    }
+    .into()
+}